@@ -0,0 +1,431 @@
+/*!
+
+BWTS (bijective Burrows-Wheeler Transform, by Scott and Gil)
+
+The input is split into its Lyndon factors, a non-increasing sequence of
+words which are strictly smaller than all their rotations. The rotations of
+all the factors are sorted together, comparing them as infinitely repeated
+strings, and the transform is the last symbol of every rotation. Unlike the
+plain BWT, no origin index is needed to invert it, which saves 4 bytes per
+block and makes small blocks cheaper.
+
+Rotations are sorted by prefix doubling in O(N log^2 N), and the inverse is
+linear.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::bwt::bwts;
+
+let text = b"some text";
+let mut e = bwts::Encoder::new(Vec::new(), 4 << 20);
+e.write_all(text).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = bwts::Decoder::new(&encoded[..]);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &text[..]);
+```
+
+# Related links
+
+* http://arxiv.org/abs/1201.3077 - "A Bijective String Sorting Transform", by
+  Joseph Yossi Gil and David Allen Scott
+
+*/
+
+use std::{cmp, mem};
+use std::io::{self, Read, Write};
+
+use super::super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use super::super::ReadExact;
+use super::{Radix, Symbol};
+
+/// Header of the stream, setting it apart from the plain BWT one
+const MAGIC: [u8; 4] = *b"BWTS";
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Returns the end offsets of the Lyndon factors of the input, by Duval's
+/// algorithm
+fn lyndon_factors(input: &[Symbol]) -> Vec<usize> {
+    let n = input.len();
+    let mut ends = Vec::new();
+    let mut i = 0;
+    while i < n {
+        let (mut j, mut k) = (i + 1, i);
+        while j < n && input[k] <= input[j] {
+            k = if input[k] < input[j] { i } else { k + 1 };
+            j += 1;
+        }
+        while i <= k {
+            i += j - k;
+            ends.push(i);
+        }
+    }
+    ends
+}
+
+/// Computes the BWTS of 'input' into 'output', of the same size
+pub fn encode(input: &[Symbol], output: &mut [Symbol]) {
+    let n = input.len();
+    assert_eq!(n, output.len());
+    if n == 0 {
+        return
+    }
+    // the next position of every rotation, wrapping around its factor
+    let mut next: Vec<u32> = (1..(n as u32 + 1)).collect();
+    let mut start = 0;
+    let mut longest = 0;
+    for end in lyndon_factors(input) {
+        next[end - 1] = start as u32;
+        longest = cmp::max(longest, end - start);
+        start = end;
+    }
+    // the last symbol of a rotation precedes its first one
+    let mut last = vec![0; n];
+    for (p, &q) in next.iter().enumerate() {
+        last[q as usize] = input[p];
+    }
+
+    // sort the rotations by their first h symbols, for h doubling up to
+    // the point where equal prefixes mean equal repeated words
+    let mut rank: Vec<u32> = input.iter().map(|&b| b as u32).collect();
+    let mut order: Vec<u32> = (0..(n as u32)).collect();
+    let mut new_rank = vec![0u32; n];
+    let mut h = 1;
+    let mut groups;
+    loop {
+        {
+            let key = |p: u32| (rank[p as usize] as u64) << 32 | rank[next[p as usize] as usize] as u64;
+            order.sort_unstable_by_key(|&p| key(p));
+            let (mut prev, mut group) = (key(order[0]), 0);
+            groups = 1;
+            for (i, &p) in order.iter().enumerate() {
+                let k = key(p);
+                if k != prev {
+                    prev = k;
+                    group = i as u32;
+                    groups += 1;
+                }
+                new_rank[p as usize] = group;
+            }
+        }
+        mem::swap(&mut rank, &mut new_rank);
+        h *= 2;
+        if h >= 2 * longest || groups == n {
+            break
+        }
+        next = next.iter().map(|&p| next[p as usize]).collect();
+    }
+
+    for (out, &p) in output.iter_mut().zip(order.iter()) {
+        *out = last[p as usize];
+    }
+}
+
+/// Inverts the BWTS of 'input' into 'output', of the same size
+pub fn decode(input: &[Symbol], output: &mut [Symbol]) {
+    let n = input.len();
+    assert_eq!(n, output.len());
+    let mut radix = Radix::new();
+    radix.gather(input);
+    radix.accumulate();
+    // for every sorted rotation, the one starting a symbol later
+    let mut table = vec![0u32; n];
+    for (i, &ch) in input.iter().enumerate() {
+        table[radix.place(ch)] = i as u32;
+    }
+    // every cycle of the table, walked from the smallest unvisited rotation,
+    // spells a Lyndon factor, the factors coming out in increasing order
+    let mut end = n;
+    let mut word = Vec::new();
+    for i in 0..n {
+        if table[i] == !0 {
+            continue
+        }
+        word.clear();
+        let mut j = i;
+        while table[j] != !0 {
+            let k = table[j] as usize;
+            table[j] = !0;
+            j = k;
+            word.push(input[j]);
+        }
+        output[(end - word.len())..end].copy_from_slice(&word);
+        end -= word.len();
+    }
+}
+
+/// Computes the BWTS of a block, allocating the output
+pub fn encode_simple(input: &[Symbol]) -> Vec<Symbol> {
+    let mut output = vec![0; input.len()];
+    encode(input, &mut output);
+    output
+}
+
+/// Inverts the BWTS of a block, allocating the output
+pub fn decode_simple(input: &[Symbol]) -> Vec<Symbol> {
+    let mut output = vec![0; input.len()];
+    decode(input, &mut output);
+    output
+}
+
+
+/// This structure is used to decode a stream of BWTS blocks. This wraps an
+/// internal reader which is read from when this decoder's read method is
+/// called.
+pub struct Decoder<R> {
+    /// The internally wrapped reader. This is exposed so it may be moved out
+    /// of. Note that if data is read from the reader while decoding is in
+    /// progress the output stream will get corrupted.
+    pub r: R,
+    start: usize,
+    temp: Vec<u8>,
+    output: Vec<u8>,
+    header: bool,
+    max_block_size: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            start: 0,
+            temp: Vec::new(),
+            output: Vec::new(),
+            header: false,
+            max_block_size: 0,
+        }
+    }
+
+    /// Resets this decoder back to its initial state. Note that the underlying
+    /// stream is not seeked on or has any alterations performed on it.
+    pub fn reset(&mut self) {
+        self.header = false;
+        self.start = 0;
+        self.output.clear();
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        try!(self.r.read_exact(&mut magic));
+        if magic != MAGIC {
+            return error("invalid BWTS header");
+        }
+        self.max_block_size = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        Ok(())
+    }
+
+    fn decode_block(&mut self) -> io::Result<bool> {
+        let n = match self.r.read_u32::<LittleEndian>() {
+            Ok(n) => n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if n > self.max_block_size {
+            return error("BWTS block larger than the stream block size");
+        }
+        self.temp.clear();
+        try!(self.r.push_exactly(n as u64, &mut self.temp));
+        self.output.resize(n, 0);
+        decode(&self.temp, &mut self.output);
+        self.start = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if !self.header {
+            try!(self.read_header());
+            self.header = true;
+        }
+        let mut amt = 0;
+        while amt < dst.len() {
+            if self.output.len() == self.start && !try!(self.decode_block()) {
+                break
+            }
+            let n = cmp::min(dst.len() - amt, self.output.len() - self.start);
+            dst[amt..(amt + n)].copy_from_slice(&self.output[self.start..(self.start + n)]);
+            self.start += n;
+            amt += n;
+        }
+        Ok(amt)
+    }
+}
+
+
+/// This structure is used to compress a stream of bytes using the BWTS.
+/// This is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<u8>,
+    output: Vec<u8>,
+    wrote_header: bool,
+    block_size: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W, block_size: usize) -> Encoder<W> {
+        assert!(block_size > 0, "empty BWTS blocks");
+        Encoder {
+            w,
+            buf: Vec::new(),
+            output: Vec::new(),
+            wrote_header: false,
+            block_size,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.wrote_header {
+            return Ok(())
+        }
+        self.wrote_header = true;
+        try!(self.w.write_all(&MAGIC));
+        self.w.write_u32::<LittleEndian>(self.block_size as u32)
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let n = self.buf.len();
+        try!(self.w.write_u32::<LittleEndian>(n as u32));
+        self.output.resize(n, 0);
+        encode(&self.buf, &mut self.output);
+        try!(self.w.write_all(&self.output));
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+            if self.buf.len() == self.block_size {
+                try!(self.encode_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(self.encode_block());
+        }
+        self.w.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::super::super::rand::{random, Rng, thread_rng};
+    use super::{decode_simple, encode_simple, lyndon_factors, Decoder, Encoder};
+
+    /// Sorts the rotations of the factors by comparing their repetitions
+    fn naive(input: &[u8]) -> Vec<u8> {
+        let mut rotations = Vec::new();
+        let mut start = 0;
+        for end in lyndon_factors(input) {
+            let word = &input[start..end];
+            for i in 0..word.len() {
+                let mut rotation = word[i..].to_vec();
+                rotation.extend_from_slice(&word[..i]);
+                rotations.push(rotation);
+            }
+            start = end;
+        }
+        let key = |r: &Vec<u8>| -> Vec<u8> {
+            r.iter().cycle().take(2 * input.len()).cloned().collect()
+        };
+        rotations.sort_by_key(|r| key(r));
+        rotations.iter().map(|r| r[r.len() - 1]).collect()
+    }
+
+    #[test]
+    fn factors() {
+        assert_eq!(lyndon_factors(b"banana"), vec![1, 3, 5, 6]);
+        assert_eq!(lyndon_factors(b"aab"), vec![3]);
+        assert_eq!(lyndon_factors(b"abab"), vec![2, 4]);
+        assert_eq!(lyndon_factors(b""), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn reference() {
+        let input = b"SIX.MIXED.PIXIES.SIFT.SIXTY.PIXIE.DUST.BOXES";
+        let expected = b"STEYDST.E.IXXIIXXSMPPXS.B..EE..SUSFXDIOIIIIT";
+        assert!(encode_simple(input)[..] == expected[..]);
+    }
+
+    #[test]
+    fn transforms() {
+        let mut rng = thread_rng();
+        let mut inputs: Vec<Vec<u8>> = vec![b"".to_vec(), b"a".to_vec(), b"banana".to_vec(),
+                                            b"abcab".to_vec(), b"abababab".to_vec(), vec![7; 100],
+                                            include_bytes!("../data/test.txt").to_vec()];
+        for _ in 0..200 {
+            let len = random::<usize>() % 100;
+            let alphabet = 1 + random::<u8>() % 4;
+            inputs.push((0..len).map(|_| rng.gen_range(0, alphabet)).collect());
+        }
+        for input in inputs.iter() {
+            let encoded = encode_simple(input);
+            if input.len() < 1000 {
+                assert_eq!(encoded, naive(input));
+            }
+            assert!(&decode_simple(&encoded) == input);
+        }
+    }
+
+    fn roundtrip(bytes: &[u8], block_size: usize) {
+        let mut e = Encoder::new(Vec::new(), block_size);
+        e.write_all(bytes).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        assert!(encoded.len() == bytes.len() + 8 + 4 * bytes.len().div_ceil(block_size));
+        let mut d = Decoder::new(&encoded[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == bytes);
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"", 100);
+        roundtrip(b"test", 100);
+        roundtrip(include_bytes!("../data/test.txt"), 1 << 10);
+        roundtrip(&include_bytes!("../data/test.large")[..(1 << 18)], 1 << 16);
+    }
+
+    #[test]
+    fn plain_bwt_stream() {
+        let mut e = super::super::Encoder::new(Vec::new(), 100);
+        e.write_all(b"test").unwrap();
+        let encoded = e.finish().0;
+        let mut d = Decoder::new(&encoded[..]);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -17,6 +17,24 @@ let distances = dc::encode_simple::<usize>(bytes);
 let decoded = dc::decode_simple(bytes.len(), &distances[..]);
 ```
 
+The `Encoder` and `Decoder` wrappers code a stream by blocks, the distances
+being coded with an adaptive arithmetic model, which predicts them from the
+symbol and from its last rank.
+
+```rust
+use std::io::{Read, Write};
+use compress::bwt::dc;
+
+let mut e = dc::Encoder::new(Vec::new(), 1<<16);
+e.write_all(b"aaaaabbbbbbaaccccc").unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut decoded = Vec::new();
+dc::Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], b"aaaaabbbbbbaaccccc");
+```
+
 # Credit
 
 This is an original implementation.
@@ -24,11 +42,15 @@ Thanks to Edgar Binder for inventing DC!
 
 */
 
-use std::io;
+use std::{cmp, io};
+use std::io::{Read, Write};
 use std::iter::{self, repeat};
 use std::slice as vec;
 use super::num::traits::{NumCast, ToPrimitive};
 use super::mtf::MTF;
+use super::alloc_block;
+use super::super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use super::super::entropy::ari::{self, bin, Border};
 
 pub type Symbol = u8;
 pub type Rank = u8;
@@ -57,6 +79,10 @@ impl Context {
     }
 }
 
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
 
 /// DC body iterator, can be used to encode distances
 pub struct EncodeIterator<'a,'b, D: 'b> {
@@ -136,7 +162,9 @@ pub fn encode<'a, 'b, D: Clone + Copy + Eq + NumCast>(input: &'a [Symbol], dista
             }
         }
     }
-    for (rank,&sym) in mtf.symbols[..num_unique].iter().enumerate() {
+    // a block of a single symbol is decoded without distances
+    let num_swept = if num_unique > 1 {num_unique} else {0};
+    for (rank,&sym) in mtf.symbols[..num_swept].iter().enumerate() {
         let base = last[sym as usize];
         debug!("\tSweep symbol {} of rank {}, last known at {}, encoding {}", sym, rank, base, n-base-rank-1);
         assert!(n >= base+rank+1);
@@ -158,6 +186,20 @@ pub fn encode_simple<D: Clone + Copy + Eq + NumCast>(input: &[Symbol]) -> Vec<D>
     init.iter().map(|d| d.clone()).chain(eniter.by_ref().map(|(d,_)| d)).collect()
 }
 
+/// Checks that the initial positions of the present symbols are all different,
+/// one of them starting the block
+fn check_init(init: &[usize; TOTAL_SYMBOLS], n: usize) -> io::Result<()> {
+    let mut present: Vec<usize> = init.iter().cloned().filter(|&pos| pos < n).collect();
+    present.sort();
+    if n > 0 && present.first() != Some(&0) {
+        return error("DC block has no symbol at its start")
+    }
+    if present.windows(2).any(|w| w[0] == w[1]) {
+        return error("DC symbols sharing an initial position")
+    }
+    Ok(())
+}
+
 /// Decode a block of distances given the initial symbol positions
 pub fn decode<F>(mut next: [usize; TOTAL_SYMBOLS], output: &mut [Symbol], mtf: &mut MTF,
                  mut fn_dist: F) -> io::Result<()>
@@ -165,6 +207,7 @@ pub fn decode<F>(mut next: [usize; TOTAL_SYMBOLS], output: &mut [Symbol], mtf: &
 {
 
     let n = output.len();
+    try!(check_init(&next, n));
     let mut i = 0;
     for (sym,d) in next.iter().enumerate() {
         if *d < n {
@@ -198,7 +241,7 @@ pub fn decode<F>(mut next: [usize; TOTAL_SYMBOLS], output: &mut [Symbol], mtf: &
     i = 0;
     while i<n {
         let sym = mtf.symbols[0];
-        let stop = next[mtf.symbols[1] as usize];
+        let stop = cmp::min(next[mtf.symbols[1] as usize], n);
         debug!("\tFilling region [{}-{}) with symbol {}", i, stop, sym);
         while i<stop    {
             output[i] = sym;
@@ -210,7 +253,9 @@ pub fn decode<F>(mut next: [usize; TOTAL_SYMBOLS], output: &mut [Symbol], mtf: &
             Err(e) => return Err(e)
         };
         debug!("\t\tLooking for future position {}", future);
-        assert!(future <= n);
+        if future > n {
+            return error("DC distance out of the block")
+        }
         let mut rank = 1;
         while rank < alphabet_size && future+rank > next[mtf.symbols[rank] as usize] {
             mtf.symbols[rank-1] = mtf.symbols[rank];
@@ -227,8 +272,9 @@ pub fn decode<F>(mut next: [usize; TOTAL_SYMBOLS], output: &mut [Symbol], mtf: &
         next[sym as usize] = future+rank-1;
         ranks[sym as usize] = (rank-1) as Rank;
     }
-    assert_eq!(next.iter().position(|&d| d<n || d>=n+alphabet_size), None);
-    assert_eq!(i, n);
+    if next.iter().any(|&d| d<n || d>=n+alphabet_size) {
+        return error("DC distances inconsistent with the block")
+    }
     Ok(())
 }
 
@@ -252,8 +298,376 @@ pub fn decode_simple<D: ToPrimitive>(n: usize, distances: &[D]) -> Vec<Symbol> {
 }
 
 
+/// Coding of the distances in a stream
+const CODING_RAW: u8 = 0;
+const CODING_ADAPTIVE: u8 = 1;
+
+/// Largest block size of a stream, which bounds the memory of the decoder
+pub const MAX_BLOCK_SIZE: usize = 1 << 26;
+
+/// Number of the last rank contexts, the higher ranks sharing the last one
+const RANK_CONTEXTS: usize = 4;
+/// Number of the bit lengths of a distance
+const LENGTH_CONTEXTS: usize = 33;
+const MODEL_THRESHOLD: Border = ari::RANGE_DEFAULT_THRESHOLD >> 3;
+const MODEL_RATE: Border = 4;
+
+/// Codes binary decisions, writing or reading them
+trait BitCoder {
+    /// Code 'bit' under 'model', return the actual bit
+    fn code<M: ari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<bool>;
+}
+
+impl<W: Write> BitCoder for ari::Encoder<W> {
+    fn code<M: ari::Model<bool>>(&mut self, bit: bool, model: &M) -> io::Result<bool> {
+        self.encode(bit, model).map(|_| bit)
+    }
+}
+
+impl<R: Read> BitCoder for ari::Decoder<R> {
+    fn code<M: ari::Model<bool>>(&mut self, _bit: bool, model: &M) -> io::Result<bool> {
+        self.decode(model)
+    }
+}
+
+fn bit_length(value: usize) -> usize {
+    (usize::BITS - value.leading_zeros()) as usize
+}
+
+/// Adaptive model of the distances of a block, for the arithmetic coder
+/// The bit length of a distance is coded as a sequence of binary decisions,
+/// skipping the lengths above the distance limit, followed by the bits under
+/// the leading one. The decisions are predicted by mixing the statistics of
+/// the symbol with the ones of its last rank.
+pub struct DistanceModel {
+    /// length decisions of the initial positions
+    init: Vec<bin::Model>,
+    /// length decisions per last rank
+    ranks: Vec<bin::Model>,
+    /// length decisions per symbol
+    symbols: Vec<bin::Model>,
+    /// the bit under the leading one, per length
+    high: Vec<bin::Model>,
+    /// the remaining bits
+    flat: bin::Model,
+}
+
+impl Default for DistanceModel {
+    fn default() -> DistanceModel {
+        DistanceModel::new()
+    }
+}
+
+impl DistanceModel {
+    /// Create a new model, to be used for a single block
+    pub fn new() -> DistanceModel {
+        let models = |num| (0..num).map(|_| bin::Model::new_flat(MODEL_THRESHOLD, MODEL_RATE)).collect();
+        DistanceModel {
+            init: models(LENGTH_CONTEXTS),
+            ranks: models(RANK_CONTEXTS * LENGTH_CONTEXTS),
+            symbols: models(TOTAL_SYMBOLS * LENGTH_CONTEXTS),
+            high: models(LENGTH_CONTEXTS),
+            flat: bin::Model::new_flat(MODEL_THRESHOLD, MODEL_RATE),
+        }
+    }
+
+    /// Encode the initial position of a symbol in a block of 'n' bytes,
+    /// 'n' standing for an absent symbol
+    pub fn encode_init<W: Write>(&mut self, e: &mut ari::Encoder<W>, pos: usize, n: usize)
+                                 -> io::Result<()> {
+        self.code(e, pos, n + 1, None).map(|_| ())
+    }
+
+    /// Decode the initial position of a symbol in a block of 'n' bytes
+    pub fn decode_init<R: Read>(&mut self, d: &mut ari::Decoder<R>, n: usize) -> io::Result<usize> {
+        self.code(d, 0, n + 1, None)
+    }
+
+    /// Encode a distance under its context
+    pub fn encode<W: Write>(&mut self, e: &mut ari::Encoder<W>, dist: usize, ctx: &Context)
+                            -> io::Result<()> {
+        self.code(e, dist, ctx.distance_limit, Some(ctx)).map(|_| ())
+    }
+
+    /// Decode a distance under its context
+    pub fn decode<R: Read>(&mut self, d: &mut ari::Decoder<R>, ctx: &Context) -> io::Result<usize> {
+        self.code(d, 0, ctx.distance_limit, Some(ctx))
+    }
+
+    fn code<C: BitCoder>(&mut self, coder: &mut C, value: usize, limit: usize,
+                         ctx: Option<&Context>) -> io::Result<usize> {
+        let max_length = bit_length(limit - 1);
+        let length = bit_length(value);
+        let mut k = 0;
+        while k < max_length {
+            let stop = match ctx {
+                Some(ctx) => {
+                    let rank = cmp::min(ctx.last_rank as usize, RANK_CONTEXTS - 1);
+                    let rm = &mut self.ranks[rank * LENGTH_CONTEXTS + k];
+                    let sm = &mut self.symbols[ctx.symbol as usize * LENGTH_CONTEXTS + k];
+                    let stop = try!(coder.code(length == k, &bin::SumProxy::new(1, rm, 1, sm, 1)));
+                    rm.update(stop);
+                    sm.update(stop);
+                    stop
+                },
+                None => {
+                    let stop = try!(coder.code(length == k, &self.init[k]));
+                    self.init[k].update(stop);
+                    stop
+                },
+            };
+            if stop {
+                break
+            }
+            k += 1;
+        }
+        if k < 2 {
+            return Ok(k)
+        }
+        let high = try!(coder.code((value >> (k - 2)) & 1 != 0, &self.high[k]));
+        self.high[k].update(high);
+        let mut result = 2 | high as usize;
+        for i in (0..k - 2).rev() {
+            let bit = try!(coder.code((value >> i) & 1 != 0, &self.flat));
+            result = (result << 1) | bit as usize;
+        }
+        if result < limit {
+            Ok(result)
+        } else {
+            error("DC distance out of range")
+        }
+    }
+}
+
+
+/// This structure is used to decode a stream of DC-encoded blocks. This is a
+/// wrapper around an internal reader which bytes will be read from.
+pub struct Decoder<R> {
+    r: R,
+    /// the block size and the coding, once the header is read
+    header: Option<(usize, u8)>,
+    output: Vec<Symbol>,
+    start: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            header: None,
+            output: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Returns the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    fn read_header(&mut self) -> io::Result<(usize, u8)> {
+        let block_size = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        if block_size > MAX_BLOCK_SIZE {
+            return error("DC block size too large")
+        }
+        let coding = try!(self.r.read_u8());
+        if coding != CODING_RAW && coding != CODING_ADAPTIVE {
+            return error("Unknown DC distance coding")
+        }
+        Ok((block_size, coding))
+    }
+
+    fn decode_block(&mut self, block_size: usize, coding: u8) -> io::Result<bool> {
+        let n = match self.r.read_u32::<LittleEndian>() {
+            Ok(n) => n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false), // EOF
+            Err(e) => return Err(e),
+        };
+        if n > block_size {
+            return error("DC block larger than the stream block size")
+        }
+        try!(alloc_block(&mut self.output, n));
+        let mut init = [0; TOTAL_SYMBOLS];
+
+        if coding == CODING_ADAPTIVE {
+            let mut model = DistanceModel::new();
+            let mut coder = ari::Decoder::new(&mut self.r);
+            for pos in init.iter_mut() {
+                *pos = try!(model.decode_init(&mut coder, n));
+            }
+            try!(decode(init, &mut self.output[..], &mut MTF::new(), |ctx| {
+                model.decode(&mut coder, &ctx)
+            }));
+            let (_, result) = coder.finish();
+            try!(result);
+        } else {
+            for pos in init.iter_mut() {
+                *pos = try!(self.r.read_u32::<LittleEndian>()) as usize;
+                if *pos > n {
+                    return error("DC initial position out of the block")
+                }
+            }
+            let r = &mut self.r;
+            try!(decode(init, &mut self.output[..], &mut MTF::new(), |ctx| {
+                let dist = try!(r.read_u32::<LittleEndian>()) as usize;
+                if dist < ctx.distance_limit {
+                    Ok(dist)
+                } else {
+                    error("DC distance out of range")
+                }
+            }));
+        }
+
+        self.start = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let (block_size, coding) = match self.header {
+            Some(header) => header,
+            None => {
+                let header = try!(self.read_header());
+                self.header = Some(header);
+                header
+            }
+        };
+        let mut amt = 0;
+        while amt < dst.len() {
+            if self.output.len() == self.start && !try!(self.decode_block(block_size, coding)) {
+                break
+            }
+            let n = cmp::min(dst.len() - amt, self.output.len() - self.start);
+            dst[amt..amt + n].copy_from_slice(&self.output[self.start..self.start + n]);
+            self.start += n;
+            amt += n;
+        }
+        Ok(amt)
+    }
+}
+
+
+/// This structure is used to compress a stream of bytes, typically the
+/// output of the BWT, with the distance coding. This is a wrapper around an
+/// internal writer which bytes will be written to.
+/// The distances of a block are coded with an adaptive model by default.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<Symbol>,
+    distances: Vec<u32>,
+    wrote_header: bool,
+    block_size: usize,
+    adaptive: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream, coding the input by blocks of 'block_size' bytes.
+    /// The output stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, block_size: usize) -> Encoder<W> {
+        assert!(block_size > 0 && block_size <= MAX_BLOCK_SIZE, "invalid DC block size");
+        Encoder {
+            w,
+            buf: Vec::new(),
+            distances: Vec::new(),
+            wrote_header: false,
+            block_size,
+            adaptive: true,
+        }
+    }
+
+    /// Writes the distances as plain 32-bit integers instead of coding them
+    /// with the adaptive model, leaving them to another coder.
+    /// This has to be set before anything is written.
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        assert!(!self.wrote_header, "DC stream header already written");
+        self.adaptive = adaptive;
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            try!(self.w.write_u32::<LittleEndian>(self.block_size as u32));
+            try!(self.w.write_u8(if self.adaptive { CODING_ADAPTIVE } else { CODING_RAW }));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let n = self.buf.len();
+        try!(self.w.write_u32::<LittleEndian>(n as u32));
+
+        self.distances.truncate(0);
+        self.distances.resize(n, 0);
+        let iter = encode(&self.buf[..], &mut self.distances[..], &mut MTF::new());
+        let init = *iter.get_init();
+
+        if self.adaptive {
+            let mut model = DistanceModel::new();
+            let mut coder = ari::Encoder::new(&mut self.w);
+            for &pos in init.iter() {
+                try!(model.encode_init(&mut coder, pos, n));
+            }
+            for (dist, ctx) in iter {
+                try!(model.encode(&mut coder, dist as usize, &ctx));
+            }
+            let (_, result) = coder.finish();
+            try!(result);
+        } else {
+            for &pos in init.iter() {
+                try!(self.w.write_u32::<LittleEndian>(pos as u32));
+            }
+            for (dist, _) in iter {
+                try!(self.w.write_u32::<LittleEndian>(dist));
+            }
+        }
+        self.buf.truncate(0);
+
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+
+            if self.buf.len() == self.block_size {
+                try!(self.encode_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let ret = if !self.buf.is_empty() {
+            self.encode_block()
+        } else {
+            Ok(())
+        };
+        ret.and(self.w.flush())
+    }
+}
+
+
 #[cfg(test)]
 mod test {
+    use std::io::{Read, Write};
     use std::iter::repeat;
 
     fn roundtrip(bytes: &[u8]) {
@@ -295,6 +709,81 @@ mod test {
         roundtrip(include_bytes!("../data/test.txt"));
     }
 
+    fn roundtrip_stream(bytes: &[u8], block_size: usize, adaptive: bool) -> usize {
+        let mut e = super::Encoder::new(Vec::new(), block_size);
+        e.set_adaptive(adaptive);
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+
+        let mut d = super::Decoder::new(&encoded[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], bytes);
+        encoded.len()
+    }
+
+    #[test]
+    fn roundtrips_stream() {
+        let text = include_bytes!("../data/test.txt");
+        let (transformed, _) = super::super::encode_simple(text);
+        for &adaptive in [false, true].iter() {
+            roundtrip_stream(b"", 100, adaptive);
+            roundtrip_stream(b"teeesst_dc", 3, adaptive);
+            roundtrip_stream(b"aaaaaaaa", 5, adaptive);
+            roundtrip_stream(text, 1<<10, adaptive);
+            roundtrip_stream(&transformed[..], 1<<20, adaptive);
+        }
+        let raw = roundtrip_stream(&transformed[..], 1<<20, false);
+        let adaptive = roundtrip_stream(&transformed[..], 1<<20, true);
+        assert!(adaptive < transformed.len() * 2 / 3 && adaptive < raw / 4);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let text = include_bytes!("../data/test.txt");
+        for &adaptive in [false, true].iter() {
+            let mut e = super::Encoder::new(Vec::new(), 1<<12);
+            e.set_adaptive(adaptive);
+            e.write_all(text).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            for &len in [3, 10, 200, encoded.len() / 2, encoded.len() - 1].iter() {
+                let mut d = super::Decoder::new(&encoded[..len]);
+                assert!(d.read_to_end(&mut Vec::new()).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn hostile_header() {
+        // a block of 4 GB in a stream of blocks of 4 GB
+        let stream = [0xff, 0xff, 0xff, 0xff, 0, 0xf0, 0xff, 0xff, 0xff];
+        assert!(super::Decoder::new(&stream[..]).read_to_end(&mut Vec::new()).is_err());
+        // a block as large as allowed, with nothing to decode it from
+        let mut stream = vec![0, 0, 0, 4, 1];
+        stream.extend_from_slice(&[0, 0, 0, 4]);
+        assert!(super::Decoder::new(&stream[..]).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupted_stream() {
+        let text = &include_bytes!("../data/test.txt")[..1<<10];
+        for &adaptive in [false, true].iter() {
+            let mut e = super::Encoder::new(Vec::new(), 1<<12);
+            e.set_adaptive(adaptive);
+            e.write_all(text).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            // flipped bits give an error or a wrong block, but never a panic
+            for i in 0..encoded.len() {
+                let mut wrong = encoded.clone();
+                wrong[i] ^= 1 << (i % 8);
+                let _ = super::Decoder::new(&wrong[..]).read_to_end(&mut Vec::new());
+            }
+        }
+    }
+
     #[test]
     fn roundtrips_context() {
         roundtrip_ctx(b"teeesst_dc");
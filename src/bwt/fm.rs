@@ -0,0 +1,305 @@
+/*!
+
+FM-index: substring search over the BWT of a block
+
+The BWT of a block, together with the counts of its symbols, is enough to
+find where a pattern occurs without decoding the block. The backward search
+extends the pattern one symbol at a time from its end, each step narrowing
+the range of sorted suffixes starting with the matched part, which takes the
+number of occurrences of a symbol in a prefix of the BWT (its rank).
+
+Ranks are answered from the counts of all the symbols, stored every
+`occ_rate` positions, and a scan of the BWT from there. These counts are
+kept on 16 bits, relative to the full ones stored every 65536 positions.
+Positions in the text are found by walking the BWT back to a suffix which
+position is stored, positions multiple of `sa_rate` being kept.
+
+The BWT produced by `bwt::encode` has no end of block symbol: its row
+given by the origin holds the last symbol instead. The index accounts for it,
+so the output of the encoder can be given as is.
+
+# Example
+
+```rust
+use compress::bwt;
+use compress::bwt::fm::FmIndex;
+
+let text = b"abracadabra";
+let (transformed, origin) = bwt::encode_simple(text);
+let index = FmIndex::new(transformed, origin);
+assert_eq!(index.count(b"abra"), 2);
+assert_eq!(index.locate(b"abra"), vec![0, 7]);
+assert_eq!(index.count(b"cab"), 0);
+```
+
+# Related links
+
+* http://en.wikipedia.org/wiki/FM-index
+
+*/
+
+use super::{Radix, Symbol, ALPHABET_SIZE};
+
+/// Default distance between the rank checkpoints
+pub const DEFAULT_OCC_RATE: usize = 512;
+/// Default distance between the text positions of the sampled suffixes
+pub const DEFAULT_SA_RATE: usize = 32;
+/// Distance between the full symbol counts
+const SUPERBLOCK_SIZE: usize = 1 << 16;
+
+/// An FM-index over a BWT block
+pub struct FmIndex {
+    bwt: Vec<Symbol>,
+    origin: usize,
+    /// number of symbols smaller than each one, the end of block included
+    first: [usize; ALPHABET_SIZE],
+    occ_rate: usize,
+    /// counts of every symbol before each superblock
+    superblocks: Vec<u32>,
+    /// counts of every symbol before each checkpoint, from its superblock
+    checkpoints: Vec<u16>,
+    sa_rate: usize,
+    /// rows of the rotation matrix whose position is sampled, a bit each
+    sampled: Vec<u64>,
+    /// number of sampled rows before each word of 'sampled'
+    sampled_before: Vec<u32>,
+    /// positions of the sampled rows, in row order
+    positions: Vec<u32>,
+}
+
+impl FmIndex {
+    /// Creates an index from the output of `bwt::encode`, with the default
+    /// sampling rates
+    pub fn new(bwt: Vec<Symbol>, origin: usize) -> FmIndex {
+        FmIndex::with_rates(bwt, origin, DEFAULT_OCC_RATE, DEFAULT_SA_RATE)
+    }
+
+    /// Creates an index keeping the symbol counts every 'occ_rate' symbols of
+    /// the BWT, and the positions multiple of 'sa_rate'. Lower rates make
+    /// faster searches, and a larger index: including the BWT, it takes about
+    /// 1.2 + 512/occ_rate + 4/sa_rate bytes per byte of text, which is 2.3
+    /// with the default rates.
+    pub fn with_rates(bwt: Vec<Symbol>, origin: usize, occ_rate: usize, sa_rate: usize)
+                      -> FmIndex {
+        assert!(occ_rate > 0 && sa_rate > 0, "invalid FM-index sampling rates");
+        assert!(origin < bwt.len() || (bwt.is_empty() && origin == 0), "invalid BWT origin");
+        assert!(bwt.len() < u32::MAX as usize, "BWT block too large");
+
+        let mut radix = Radix::new();
+        radix.gather(&bwt);
+        radix.accumulate();
+        let mut first = [0; ALPHABET_SIZE];
+        for (f, &count) in first.iter_mut().zip(radix.freq.iter()) {
+            *f = count + 1;
+        }
+
+        let mut superblocks = Vec::with_capacity((bwt.len() / SUPERBLOCK_SIZE + 1) * ALPHABET_SIZE);
+        let mut checkpoints = Vec::with_capacity((bwt.len() / occ_rate + 1) * ALPHABET_SIZE);
+        let mut counts = [0u32; ALPHABET_SIZE];
+        let mut base = [0u32; ALPHABET_SIZE];
+        for i in 0..=bwt.len() {
+            if i % SUPERBLOCK_SIZE == 0 {
+                superblocks.extend_from_slice(&counts);
+                base = counts;
+            }
+            if i % occ_rate == 0 {
+                checkpoints.extend(counts.iter().zip(base.iter()).map(|(&c, &b)| (c - b) as u16));
+            }
+            if i < bwt.len() {
+                counts[bwt[i] as usize] += 1;
+            }
+        }
+
+        let rows = bwt.len() + 1;
+        let mut index = FmIndex {
+            bwt,
+            origin,
+            first,
+            occ_rate,
+            superblocks,
+            checkpoints,
+            sa_rate,
+            sampled: vec![0; rows.div_ceil(64)],
+            sampled_before: Vec::new(),
+            positions: Vec::new(),
+        };
+        index.sample_positions();
+        index
+    }
+
+    /// Walks the whole text backwards from its end, to sample the positions
+    fn sample_positions(&mut self) {
+        let n = self.bwt.len();
+        let mut samples = Vec::with_capacity(n / self.sa_rate + 2);
+        // the first row is the empty suffix, at the end of the text
+        let mut row = 0;
+        for pos in (0..=n).rev() {
+            if pos % self.sa_rate == 0 || pos == n {
+                samples.push((row, pos as u32));
+                self.sampled[row / 64] |= 1 << (row % 64);
+            }
+            if pos != 0 {
+                let ch = self.symbol(row).unwrap();
+                row = self.lf(ch, row);
+            }
+        }
+        samples.sort();
+        self.positions = samples.into_iter().map(|(_, pos)| pos).collect();
+        let mut total = 0;
+        self.sampled_before = self.sampled.iter().map(|&word| {
+            let before = total;
+            total += word.count_ones();
+            before
+        }).collect();
+    }
+
+    /// Returns the length of the indexed text
+    pub fn len(&self) -> usize {
+        self.bwt.len()
+    }
+
+    /// Tests if the indexed text is empty
+    pub fn is_empty(&self) -> bool {
+        self.bwt.is_empty()
+    }
+
+    /// Returns the last symbol of a row of the sorted rotations, the end of
+    /// block being None. The rows are the ones of the BWT, after a first one
+    /// for the empty suffix.
+    fn symbol(&self, row: usize) -> Option<Symbol> {
+        if row == 0 {
+            Some(self.bwt[self.origin])
+        } else if row == self.origin + 1 {
+            None
+        } else {
+            Some(self.bwt[row - 1])
+        }
+    }
+
+    /// Number of occurrences of 'ch' in the rows before 'row'
+    fn occ(&self, ch: Symbol, row: usize) -> usize {
+        if row == 0 || self.bwt.is_empty() {
+            return 0
+        }
+        // the first row holds the symbol of the origin, where the end of
+        // block is instead
+        let end = row - 1;
+        let block = end / self.occ_rate;
+        let start = block * self.occ_rate;
+        let superblock = start / SUPERBLOCK_SIZE;
+        let mut count = self.superblocks[superblock * ALPHABET_SIZE + ch as usize] as usize +
+            self.checkpoints[block * ALPHABET_SIZE + ch as usize] as usize +
+            self.bwt[start..end].iter().filter(|&&b| b == ch).count();
+        if ch == self.bwt[self.origin] && self.origin >= end {
+            count += 1;
+        }
+        count
+    }
+
+    /// Maps a row to the one of the suffix one symbol longer, given the
+    /// symbol of the row
+    fn lf(&self, ch: Symbol, row: usize) -> usize {
+        self.first[ch as usize] + self.occ(ch, row)
+    }
+
+    /// Returns the range of the rows starting with 'pattern'
+    fn rows(&self, pattern: &[Symbol]) -> (usize, usize) {
+        let (mut low, mut high) = (0, self.bwt.len() + 1);
+        for &ch in pattern.iter().rev() {
+            low = self.lf(ch, low);
+            high = self.lf(ch, high);
+            if low >= high {
+                return (0, 0)
+            }
+        }
+        (low, high)
+    }
+
+    /// Counts the occurrences of 'pattern' in the text. The empty pattern
+    /// occurs at every position, the end included.
+    pub fn count(&self, pattern: &[Symbol]) -> usize {
+        let (low, high) = self.rows(pattern);
+        high - low
+    }
+
+    /// Returns the sorted positions of the occurrences of 'pattern' in the text
+    pub fn locate(&self, pattern: &[Symbol]) -> Vec<usize> {
+        let (low, high) = self.rows(pattern);
+        let mut positions: Vec<usize> = (low..high).map(|row| self.position(row)).collect();
+        positions.sort();
+        positions
+    }
+
+    /// Finds the text position of a row, walking back to a sampled one
+    fn position(&self, mut row: usize) -> usize {
+        let mut steps = 0;
+        loop {
+            let word = self.sampled[row / 64];
+            let bit = 1u64 << (row % 64);
+            if word & bit != 0 {
+                let rank = self.sampled_before[row / 64] + (word & (bit - 1)).count_ones();
+                return self.positions[rank as usize] as usize + steps
+            }
+            // the row of the whole text is always sampled
+            let ch = self.symbol(row).unwrap();
+            row = self.lf(ch, row);
+            steps += 1;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::super::encode_simple;
+    use super::FmIndex;
+
+    fn naive(text: &[u8], pattern: &[u8]) -> Vec<usize> {
+        (0..(text.len() + 1).saturating_sub(pattern.len()))
+            .filter(|&i| &text[i..(i + pattern.len())] == pattern)
+            .collect()
+    }
+
+    fn check(text: &[u8], patterns: &[&[u8]], occ_rate: usize, sa_rate: usize) {
+        let (transformed, origin) = encode_simple(text);
+        let index = FmIndex::with_rates(transformed, origin, occ_rate, sa_rate);
+        assert_eq!(index.len(), text.len());
+        for &pattern in patterns.iter() {
+            let expected = naive(text, pattern);
+            assert_eq!(index.count(pattern), expected.len());
+            assert_eq!(index.locate(pattern), expected);
+        }
+    }
+
+    #[test]
+    fn searches() {
+        let text = include_bytes!("../data/test.txt");
+        let mut patterns: Vec<&[u8]> = vec![b"", b"the", b"e", b"\n", b"zzz", b"Rust"];
+        for i in (0..(text.len() - 20)).step_by(97) {
+            patterns.push(&text[i..(i + 1 + i % 20)]);
+        }
+        patterns.push(&text[..10]);
+        patterns.push(&text[(text.len() - 10)..]);
+        for &(occ_rate, sa_rate) in [(1, 1), (7, 3), (64, 32), (1000, 100)].iter() {
+            check(text, &patterns, occ_rate, sa_rate);
+        }
+    }
+
+    #[test]
+    fn superblocks() {
+        let text = &include_bytes!("../data/test.large")[..(1 << 17) + 1000];
+        let patterns: Vec<&[u8]> = vec![b"zzz", &text[(1 << 16) - 10..(1 << 16) + 10],
+                                        &text[(1 << 17)..(1 << 17) + 20]];
+        for &occ_rate in [700, super::DEFAULT_OCC_RATE].iter() {
+            check(text, &patterns, occ_rate, super::DEFAULT_SA_RATE);
+        }
+    }
+
+    #[test]
+    fn repetitions() {
+        let patterns: Vec<&[u8]> = vec![b"a", b"aa", b"aaa", b"ab", b"ba", b"abab", b"b"];
+        check(b"aaaaaaaaaa", &patterns, 4, 3);
+        check(b"abababababa", &patterns, 2, 5);
+        check(b"b", &patterns, 1, 1);
+    }
+}
@@ -0,0 +1,500 @@
+/*!
+
+IF (Inverse Frequencies) forward and backward transformation.
+Designed to be used on BWT block output for compression, like DC.
+
+For every symbol, IF lists how many larger symbols occur before each of its
+occurrences, counting from the previous one. The values of the largest symbol
+are all zero, so a block is given by the frequencies of its symbols and the
+values of the other ones. The decoder rebuilds the block from the largest
+symbol down, inserting the occurrences of each symbol among the larger ones.
+
+The values are laid out by symbol, from the largest one to the smallest, and
+can be coded under a `dc::Context`, made of the symbol, its previous value
+(in place of the last rank), and the number of the larger symbols left.
+
+# Links
+
+http://www.data-compression.info/Algorithms/IF/
+
+# Example
+
+```rust
+use compress::bwt::inverse_freq;
+
+let bytes = b"abracadabra";
+let values = inverse_freq::encode_simple::<usize>(bytes);
+let decoded = inverse_freq::decode_simple(bytes.len(), &values[..]);
+assert_eq!(&decoded[..], &bytes[..]);
+```
+
+# Credit
+
+This is an original implementation.
+Thanks to Ziya Arnavut and Spyros Magliveras for inventing IF!
+
+*/
+
+use std::{cmp, io};
+use std::io::{Read, Write};
+use std::slice;
+use super::num::traits::{NumCast, ToPrimitive};
+use super::super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use super::super::entropy::ari;
+use super::alloc_block;
+use super::dc::{Context, DistanceModel, Rank, Symbol, TOTAL_SYMBOLS};
+
+/// Largest block size of a stream, which bounds the memory of the decoder
+pub const MAX_BLOCK_SIZE: usize = 1 << 26;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Context of a value: its symbol, the previous value of the symbol, and
+/// the bound given by the larger symbols left
+fn context(symbol: usize, last: usize, larger_left: usize) -> Context {
+    Context::new(symbol as Symbol, cmp::min(last, Rank::MAX as usize) as Rank, larger_left + 1)
+}
+
+
+/// IF body iterator, can be used to encode the values
+/// The values of the largest symbol are skipped, being all zero.
+pub struct EncodeIterator<'a, D: 'a> {
+    values: slice::Iter<'a, D>,
+    freq: [usize; TOTAL_SYMBOLS],
+    /// current symbol, with its values left, previous value, and larger symbols left
+    symbol: usize,
+    left: usize,
+    last: usize,
+    larger_left: usize,
+    /// number of the symbols larger than the current one
+    larger: usize,
+}
+
+impl<'a, D: ToPrimitive> EncodeIterator<'a, D> {
+    /// create a new encode iterator over the output of `encode`
+    pub fn new(values: &'a [D], freq: [usize; TOTAL_SYMBOLS]) -> EncodeIterator<'a, D> {
+        assert_eq!(values.len(), freq.iter().sum::<usize>());
+        let mut iter = EncodeIterator {
+            values: values.iter(),
+            freq,
+            symbol: TOTAL_SYMBOLS,
+            left: 0,
+            last: 0,
+            larger_left: 0,
+            larger: 0,
+        };
+        // skip the largest symbol
+        if let Some(largest) = freq.iter().rposition(|&f| f != 0) {
+            for _ in 0..freq[largest] {
+                iter.values.next();
+            }
+            iter.symbol = largest;
+            iter.larger = freq[largest];
+        }
+        iter
+    }
+
+    /// get the symbol frequencies, to be coded before the values
+    pub fn get_freq(&self) -> &[usize; TOTAL_SYMBOLS] {
+        &self.freq
+    }
+}
+
+impl<'a, D: Clone + ToPrimitive> Iterator for EncodeIterator<'a, D> {
+    type Item = (D, Context);
+    fn next(&mut self) -> Option<(D, Context)> {
+        while self.left == 0 {
+            if self.symbol == 0 {
+                return None
+            }
+            self.symbol -= 1;
+            self.left = self.freq[self.symbol];
+            self.last = 0;
+            self.larger_left = self.larger;
+            self.larger += self.left;
+        }
+        self.values.next().map(|value| {
+            let ctx = context(self.symbol, self.last, self.larger_left);
+            self.last = value.to_usize().unwrap();
+            self.larger_left -= self.last;
+            self.left -= 1;
+            (value.clone(), ctx)
+        })
+    }
+}
+
+/// Encode a block of bytes 'input'
+/// write the values into 'output', from the largest symbol to the smallest
+/// return: an iterator over the values and their contexts, also giving the
+/// symbol frequencies
+pub fn encode<'a, D: NumCast + ToPrimitive>(input: &[Symbol], output: &'a mut [D]) -> EncodeIterator<'a, D> {
+    assert_eq!(input.len(), output.len());
+    let mut freq = [0; TOTAL_SYMBOLS];
+    for &sym in input.iter() {
+        freq[sym as usize] += 1;
+    }
+    // offsets of the values of every symbol
+    let mut offsets = [0; TOTAL_SYMBOLS];
+    let mut total = 0;
+    for sym in (0..TOTAL_SYMBOLS).rev() {
+        offsets[sym] = total;
+        total += freq[sym];
+    }
+
+    // the symbols not processed yet, in the input order
+    let mut remaining = input.to_vec();
+    for sym in 0..TOTAL_SYMBOLS {
+        if freq[sym] == 0 {
+            continue
+        }
+        let mut offset = offsets[sym];
+        let mut count = 0;
+        for &b in remaining.iter() {
+            if b as usize == sym {
+                output[offset] = NumCast::from(count).unwrap();
+                offset += 1;
+                count = 0;
+            } else {
+                count += 1;
+            }
+        }
+        remaining.retain(|&b| b as usize != sym);
+    }
+    EncodeIterator::new(output, freq)
+}
+
+/// Encode version with "batteries included" for quick testing
+/// Returns the symbol frequencies followed by the values.
+pub fn encode_simple<D: Clone + NumCast + ToPrimitive>(input: &[Symbol]) -> Vec<D> {
+    let mut raw: Vec<D> = (0..input.len()).map(|_| NumCast::from(0).unwrap()).collect();
+    let iter = encode(input, &mut raw[..]);
+    let freq: Vec<D> = iter.get_freq().iter().map(|&f| NumCast::from(f).unwrap()).collect();
+    freq.into_iter().chain(iter.map(|(v, _)| v)).collect()
+}
+
+/// Decode a block given the symbol frequencies, the values coming from
+/// 'fn_value' in the order of `EncodeIterator`
+pub fn decode<F>(freq: &[usize; TOTAL_SYMBOLS], output: &mut [Symbol], mut fn_value: F)
+                 -> io::Result<()>
+    where F: FnMut(Context) -> io::Result<usize>
+{
+    let n = output.len();
+    if freq.iter().sum::<usize>() != n {
+        return error("IF frequencies inconsistent with the block")
+    }
+    let largest = match freq.iter().rposition(|&f| f != 0) {
+        Some(sym) => sym,
+        None => return Ok(()),
+    };
+    let mut sequence: Vec<Symbol> = vec![largest as Symbol; freq[largest]];
+    let mut next = Vec::with_capacity(n);
+    for sym in (0..largest).rev() {
+        if freq[sym] == 0 {
+            continue
+        }
+        next.truncate(0);
+        let mut pos = 0;
+        let mut last = 0;
+        for _ in 0..freq[sym] {
+            let value = try!(fn_value(context(sym, last, sequence.len() - pos)));
+            if value > sequence.len() - pos {
+                return error("IF value out of the block")
+            }
+            next.extend_from_slice(&sequence[pos..pos + value]);
+            next.push(sym as Symbol);
+            pos += value;
+            last = value;
+        }
+        next.extend_from_slice(&sequence[pos..]);
+        ::std::mem::swap(&mut sequence, &mut next);
+    }
+    output.copy_from_slice(&sequence);
+    Ok(())
+}
+
+/// Decode version with "batteries included" for quick testing
+pub fn decode_simple<D: ToPrimitive>(n: usize, values: &[D]) -> Vec<Symbol> {
+    let mut output = vec![0; n];
+    let mut freq = [0; TOTAL_SYMBOLS];
+    for (f, v) in freq.iter_mut().zip(values.iter()) {
+        *f = v.to_usize().unwrap();
+    }
+    let mut vi = TOTAL_SYMBOLS;
+    decode(&freq, &mut output[..], |_ctx| {
+        vi += 1;
+        if vi > values.len() {
+            Err(io::Error::other("Unexpected end of file"))
+        } else {
+            Ok(values[vi-1].to_usize().unwrap())
+        }
+    }).unwrap();
+    output
+}
+
+
+/// This structure is used to decode a stream of IF-encoded blocks. This is a
+/// wrapper around an internal reader which bytes will be read from.
+pub struct Decoder<R> {
+    r: R,
+    /// largest block size, once the header is read
+    block_size: Option<usize>,
+    output: Vec<Symbol>,
+    start: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            block_size: None,
+            output: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Returns the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    fn decode_block(&mut self, block_size: usize) -> io::Result<bool> {
+        let n = match self.r.read_u32::<LittleEndian>() {
+            Ok(n) => n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false), // EOF
+            Err(e) => return Err(e),
+        };
+        if n > block_size {
+            return error("IF block larger than the stream block size")
+        }
+
+        let mut model = DistanceModel::new();
+        let mut coder = ari::Decoder::new(&mut self.r);
+        // the frequencies are bounded by the block size, like the initial
+        // positions of DC
+        let mut freq = [0; TOTAL_SYMBOLS];
+        for f in freq.iter_mut() {
+            *f = try!(model.decode_init(&mut coder, n));
+        }
+        // the block takes memory only once the frequencies agree with it
+        if freq.iter().sum::<usize>() != n {
+            return error("IF frequencies inconsistent with the block")
+        }
+        try!(alloc_block(&mut self.output, n));
+        try!(decode(&freq, &mut self.output[..], |ctx| model.decode(&mut coder, &ctx)));
+        let (_, result) = coder.finish();
+        try!(result);
+
+        self.start = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let block_size = match self.block_size {
+            Some(size) => size,
+            None => {
+                let size = try!(self.r.read_u32::<LittleEndian>()) as usize;
+                if size > MAX_BLOCK_SIZE {
+                    return error("IF block size too large")
+                }
+                self.block_size = Some(size);
+                size
+            }
+        };
+        let mut amt = 0;
+        while amt < dst.len() {
+            if self.output.len() == self.start && !try!(self.decode_block(block_size)) {
+                break
+            }
+            let n = cmp::min(dst.len() - amt, self.output.len() - self.start);
+            dst[amt..amt + n].copy_from_slice(&self.output[self.start..self.start + n]);
+            self.start += n;
+            amt += n;
+        }
+        Ok(amt)
+    }
+}
+
+
+/// This structure is used to compress a stream of bytes, typically the
+/// output of the BWT, with the inverse frequencies, coded by the adaptive
+/// model of DC. This is a wrapper around an internal writer which bytes
+/// will be written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<Symbol>,
+    values: Vec<u32>,
+    wrote_header: bool,
+    block_size: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream, coding the input by blocks of 'block_size' bytes.
+    /// The output stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, block_size: usize) -> Encoder<W> {
+        assert!(block_size > 0 && block_size <= MAX_BLOCK_SIZE, "invalid IF block size");
+        Encoder {
+            w,
+            buf: Vec::new(),
+            values: Vec::new(),
+            wrote_header: false,
+            block_size,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            try!(self.w.write_u32::<LittleEndian>(self.block_size as u32));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let n = self.buf.len();
+        try!(self.w.write_u32::<LittleEndian>(n as u32));
+
+        self.values.truncate(0);
+        self.values.resize(n, 0);
+        let iter = encode(&self.buf[..], &mut self.values[..]);
+        let mut model = DistanceModel::new();
+        let mut coder = ari::Encoder::new(&mut self.w);
+        for &f in iter.get_freq().iter() {
+            try!(model.encode_init(&mut coder, f, n));
+        }
+        for (value, ctx) in iter {
+            try!(model.encode(&mut coder, value as usize, &ctx));
+        }
+        let (_, result) = coder.finish();
+        try!(result);
+        self.buf.truncate(0);
+
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+
+            if self.buf.len() == self.block_size {
+                try!(self.encode_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let ret = if !self.buf.is_empty() {
+            self.encode_block()
+        } else {
+            Ok(())
+        };
+        ret.and(self.w.flush())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    fn roundtrip(bytes: &[u8]) {
+        let values = super::encode_simple::<usize>(bytes);
+        let decoded = super::decode_simple(bytes.len(), &values[..]);
+        assert_eq!(&decoded[..], bytes);
+    }
+
+    fn roundtrip_stream(bytes: &[u8], block_size: usize) -> usize {
+        let mut e = super::Encoder::new(Vec::new(), block_size);
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+
+        let mut decoded = Vec::new();
+        super::Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], bytes);
+        encoded.len()
+    }
+
+    #[test]
+    fn roundtrips() {
+        roundtrip(b"teeesst_if");
+        roundtrip(b"");
+        roundtrip(b"aaaa");
+        roundtrip(include_bytes!("../data/test.txt"));
+        // the values of "abracadabra": none for 'r', the largest symbol, then
+        // the ones of 'd', 'c', 'b' and 'a'
+        let values = super::encode_simple::<usize>(b"abracadabra");
+        assert_eq!(&values[(b'a' as usize)..(b'e' as usize)], &[5, 2, 1, 1]);
+        assert_eq!(&values[super::TOTAL_SYMBOLS..], &[1, 1, 0, 3, 0, 2, 1, 1, 2]);
+    }
+
+    #[test]
+    fn roundtrips_stream() {
+        let text = include_bytes!("../data/test.txt");
+        let (transformed, _) = super::super::encode_simple(text);
+        roundtrip_stream(b"", 100);
+        roundtrip_stream(b"teeesst_if", 3);
+        roundtrip_stream(text, 1<<10);
+        let size = roundtrip_stream(&transformed[..], 1<<20);
+        assert!(size < transformed.len() * 2 / 3);
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let text = include_bytes!("../data/test.txt");
+        let mut e = super::Encoder::new(Vec::new(), 1<<12);
+        e.write_all(text).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        for &len in [3, 10, 200, encoded.len() / 2, encoded.len() - 1].iter() {
+            let mut d = super::Decoder::new(&encoded[..len]);
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn hostile_header() {
+        // a block of 4 GB in a stream of blocks of 4 GB
+        let stream = [0xff, 0xff, 0xff, 0xff, 0xf0, 0xff, 0xff, 0xff];
+        assert!(super::Decoder::new(&stream[..]).read_to_end(&mut Vec::new()).is_err());
+        // a block as large as allowed, with nothing to decode it from
+        let stream = [0, 0, 0, 4, 0, 0, 0, 4];
+        assert!(super::Decoder::new(&stream[..]).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupted_stream() {
+        let text = &include_bytes!("../data/test.txt")[..1<<10];
+        let mut e = super::Encoder::new(Vec::new(), 1<<12);
+        e.write_all(text).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        // flipped bits give an error or a wrong block, but never a panic
+        for i in 0..encoded.len() {
+            let mut wrong = encoded.clone();
+            wrong[i] ^= 1 << (i % 8);
+            let _ = super::Decoder::new(&wrong[..]).read_to_end(&mut Vec::new());
+        }
+    }
+}
@@ -2,8 +2,14 @@
 
 BWT (Burrows-Wheeler Transform) forward and backward transformation. Requires `bwt` feature, enabled by default
 
-This module contains a bruteforce implementation of BWT encoding in Rust as well as standard decoding.
+This module contains an implementation of BWT encoding in Rust as well as standard decoding.
 These are exposed as a standard `Reader` and `Writer` interfaces wrapping an underlying stream.
+Suffixes are sorted in linear time by SA-IS, while the original bruteforce sorter stays
+available through the `SuffixSorter` trait.
+
+The decoder validates the size and origin of every block against the stream header, and
+the encoder can follow every block with its CRC-32 (`Encoder::set_checksum`), which the
+decoder then verifies.
 
 BWT output stream places together symbols with similar leading contexts. This reshaping of the entropy
 allows further stages to deal with repeated sequences of symbols for better compression.
@@ -11,10 +17,10 @@ allows further stages to deal with repeated sequences of symbols for better comp
 Typical compression schemes are:
 BWT + RLE (+ EC)
 RLE + BWT + MTF + RLE + EC  : bzip2
-BWT + DC + EC               : ybs
+BWT + DC + EC               : ybs (implemented by the `ybs` module)
 
 Where the stage families are:
-BWT: BWT (Burrows-Wheeler Transform), ST (Shindler transform)
+BWT: BWT (Burrows-Wheeler Transform, also on several threads), BWTS (bijective BWT), ST (Schindler transform)
 RLE: RLE (Run-Length Encoding)
 MTF: MTF (Move-To-Front), WFC (Weighted Frequency Coding)
 DC: DC (Distance Coding), IF (Inverse Frequencies)
@@ -44,7 +50,8 @@ assert_eq!(&decoded[..], text.as_bytes());
 
 # Credit
 
-This is an original (mostly trivial) implementation.
+This is an original (mostly trivial) implementation. The suffix sorting follows the SA-IS
+algorithm of Ge Nong, Sen Zhang and Wai Hong Chan.
 
 */
 
@@ -60,9 +67,17 @@ use self::num::traits::{NumCast, ToPrimitive};
 
 use super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 use super::{byteorder_err_to_io, ReadExact};
+use super::checksum::crc32;
 
+pub mod bwts;
 pub mod dc;
+pub mod fm;
+pub mod inverse_freq;
 pub mod mtf;
+pub mod parallel;
+pub mod st;
+pub mod ybs;
+mod sais;
 
 /// A base element for the transformation
 pub type Symbol = u8;
@@ -130,39 +145,68 @@ impl Radix  {
 }
 
 
-/// Compute a suffix array from a given input string
-/// Resulting suffixes are guaranteed to be alphabetically sorted
-/// Run time: O(N^3), memory: N words (suf_array) + ALPHABET_SIZE words (Radix)
-pub fn compute_suffixes<SUF: NumCast + ToPrimitive + fmt::Debug>(input: &[Symbol], suf_array: &mut [SUF]) {
-    let mut radix = Radix::new();
-    radix.gather(input);
-    radix.accumulate();
+/// An algorithm sorting the suffixes of a block
+pub trait SuffixSorter {
+    /// Fill 'suf_array' with the alphabetically sorted suffixes of 'input',
+    /// a suffix being smaller than the longer ones it is a prefix of
+    fn sort(&mut self, input: &[Symbol], suf_array: &mut [u32]);
+}
 
-    debug!("SA compute input: {:?}", input);
-    debug!("radix offsets: {:?}", &radix.freq[..]);
+/// Linear time suffix sorting by induced sorting (SA-IS)
+/// Run time: O(N), memory: N/32 words + up to N/2 u32 (buckets of the recursion) on top of the suffix array
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SaIs;
 
-    for (i,&ch) in input.iter().enumerate() {
-        let p = radix.place(ch);
-        suf_array[p] = NumCast::from(i).unwrap();
+impl SuffixSorter for SaIs {
+    fn sort(&mut self, input: &[Symbol], suf_array: &mut [u32]) {
+        sais::suffix_array(input, suf_array);
     }
+}
+
+/// Bruteforce suffix sorting, comparing whole suffixes within each radix bucket
+/// Run time: O(N^3), memory: ALPHABET_SIZE words (Radix)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BruteForce;
 
-    // bring the original offsets back
-    radix.shift();
+impl SuffixSorter for BruteForce {
+    fn sort(&mut self, input: &[Symbol], suf_array: &mut [u32]) {
+        let mut radix = Radix::new();
+        radix.gather(input);
+        radix.accumulate();
 
-    for i in 0..ALPHABET_SIZE {
-        let lo = radix.freq[i];
-        let hi = radix.freq[i+1];
-        if lo == hi {
-            continue;
+        debug!("SA compute input: {:?}", input);
+        debug!("radix offsets: {:?}", &radix.freq[..]);
+
+        for (i,&ch) in input.iter().enumerate() {
+            let p = radix.place(ch);
+            suf_array[p] = i as u32;
         }
-        let slice = &mut suf_array[lo..hi];
-        debug!("\tsorting group [{}-{}) for symbol {}", lo, hi, i);
-        slice.sort_by(|a,b| {
-            input[(a.to_usize().unwrap())..].cmp(&input[(b.to_usize().unwrap())..])
-        });
+
+        // bring the original offsets back
+        radix.shift();
+
+        for i in 0..ALPHABET_SIZE {
+            let lo = radix.freq[i];
+            let hi = radix.freq[i+1];
+            if lo == hi {
+                continue;
+            }
+            let slice = &mut suf_array[lo..hi];
+            debug!("\tsorting group [{}-{}) for symbol {}", lo, hi, i);
+            slice.sort_by(|&a,&b| {
+                input[(a as usize)..].cmp(&input[(b as usize)..])
+            });
+        }
+
+        debug!("sorted SA: {:?}", suf_array);
     }
+}
 
-    debug!("sorted SA: {:?}", suf_array);
+/// Compute a suffix array from a given input string
+/// Resulting suffixes are guaranteed to be alphabetically sorted
+/// Run time: O(N), memory: N words (suf_array) + SA-IS overhead
+pub fn compute_suffixes<SUF: NumCast + ToPrimitive + fmt::Debug>(input: &[Symbol], suf_array: &mut [SUF]) {
+    sais::suffix_array(input, suf_array);
 }
 
 /// An iterator over BWT output
@@ -212,12 +256,37 @@ pub fn encode<'a, SUF: NumCast + ToPrimitive + fmt::Debug>(input: &'a [Symbol],
 /// Transform an input block into the output slice, all-inclusive version.
 /// Returns the index of the original string in the output matrix.
 pub fn encode_simple(input: &[Symbol]) -> (Vec<Symbol>, usize) {
-    let mut suf_array: Vec<usize> = repeat(0).take(input.len()).collect();
+    let mut suf_array = vec![0u32; input.len()];
     let mut iter = encode(input, &mut suf_array[..]);
     let output: Vec<Symbol> = iter.by_ref().collect();
     (output, iter.get_origin())
 }
 
+/// Transform a block by sorting its rotations instead of its suffixes, like
+/// bzip2 does, appending the result to 'output'. Returns the index of the
+/// original string in the sorted rotations.
+/// Run time: O(N), memory: 2N u32 + 2N bytes (the block twice)
+pub fn encode_rotations(input: &[Symbol], output: &mut Vec<Symbol>) -> usize {
+    let n = input.len();
+    let mut doubled = Vec::with_capacity(2 * n);
+    doubled.extend_from_slice(input);
+    doubled.extend_from_slice(input);
+    let mut suffixes = vec![0u32; 2 * n];
+    SaIs.sort(&doubled, &mut suffixes);
+
+    // the first N symbols of the suffixes starting in the first copy are the
+    // rotations, which keep their order
+    let start = output.len();
+    let mut origin = 0;
+    output.reserve(n);
+    for p in suffixes.into_iter().map(|p| p as usize).filter(|&p| p < n) {
+        if p == 0 {
+            origin = output.len() - start;
+        }
+        output.push(doubled[p + n - 1]);
+    }
+    origin
+}
 
 /// Compute an inversion jump table, needed for BWT decoding
 pub fn compute_inversion_table<SUF: NumCast + fmt::Debug>(input: &[Symbol], origin: usize, table: &mut [SUF]) {
@@ -293,6 +362,99 @@ pub fn decode_simple(input: &[Symbol], origin: usize) -> Vec<Symbol> {
     decode(input, origin, &mut suf[..]).take(input.len()).collect()
 }
 
+/// Largest block `decode_interleaved` accepts, the top bit of the table
+/// entries being used as a flag
+pub const MAX_INTERLEAVED_BLOCK: usize = (1 << 31) - 1;
+
+/// Flag of the table entries leading to the start of a segment
+const SEGMENT_FLAG: u32 = 1 << 31;
+/// Number of segments walked at the same time
+const LANES: usize = 8;
+
+/// Decode a BWT block into 'output', using 'table' temporarily.
+///
+/// The cycle of the inverse permutation is cut into segments, starting at
+/// rows sampled at regular intervals. Several segments are followed at once,
+/// so the random accesses to the table overlap instead of waiting on each
+/// other, and the segments are joined in text order at the end.
+/// Run time: O(N), memory: N u32 (table) + N bytes (segments)
+pub fn decode_interleaved(input: &[Symbol], origin: usize, table: &mut [u32], output: &mut Vec<Symbol>) {
+    let n = input.len();
+    assert_eq!(n, table.len());
+    assert!(n <= MAX_INTERLEAVED_BLOCK, "BWT block too large");
+    if n == 0 {
+        return
+    }
+    assert!(origin < n);
+
+    // rows starting the segments, the origin starting the text
+    let count = (n >> 14).clamp(1, 1024);
+    let mut starts: Vec<u32> = (0..count).map(|i| (i * n / count) as u32).collect();
+    if let Err(pos) = starts.binary_search(&(origin as u32)) {
+        starts.insert(pos, origin as u32);
+    }
+
+    let mut radix = Radix::new();
+    radix.gather(input);
+    radix.accumulate();
+
+    table[radix.place(input[origin])] = origin as u32 | SEGMENT_FLAG;
+    let mut next_start = starts.iter().peekable();
+    for (i, &ch) in input.iter().enumerate() {
+        let mut entry = i as u32;
+        while next_start.peek().is_some_and(|&&s| s < entry) {
+            next_start.next();
+        }
+        if next_start.peek() == Some(&&entry) {
+            entry |= SEGMENT_FLAG;
+        }
+        if i != origin {
+            table[radix.place(ch)] = entry;
+        }
+    }
+
+    // walk the segments, each lane picking up a new one when done
+    let mut segments: Vec<Vec<Symbol>> = starts.iter()
+        .map(|_| Vec::with_capacity(n / starts.len()))
+        .collect();
+    let mut successors = vec![0; starts.len()];
+    let mut lanes: Vec<(usize, u32)> = starts.iter().cloned().enumerate().take(LANES).collect();
+    let mut pending = lanes.len();
+    while !lanes.is_empty() {
+        let mut i = 0;
+        while i < lanes.len() {
+            let (seg, row) = lanes[i];
+            let entry = table[row as usize];
+            let next = entry & !SEGMENT_FLAG;
+            segments[seg].push(input[next as usize]);
+            if entry & SEGMENT_FLAG == 0 {
+                lanes[i].1 = next;
+                i += 1;
+            } else {
+                successors[seg] = starts.binary_search(&next).unwrap();
+                if pending < starts.len() {
+                    lanes[i] = (pending, starts[pending]);
+                    pending += 1;
+                    i += 1;
+                } else {
+                    lanes.swap_remove(i);
+                }
+            }
+        }
+    }
+
+    let first = starts.binary_search(&(origin as u32)).unwrap();
+    let mut seg = first;
+    output.reserve(n);
+    loop {
+        output.extend_from_slice(&segments[seg]);
+        seg = successors[seg];
+        if seg == first {
+            break
+        }
+    }
+}
+
 /// Decode without additional memory, can be greatly optimized
 /// Run time: O(n^2), Memory: 0n
 fn decode_minimal(input: &[Symbol], origin: usize, output: &mut [Symbol]) {
@@ -305,16 +467,114 @@ fn decode_minimal(input: &[Symbol], origin: usize, output: &mut [Symbol]) {
     radix.gather(input);
     radix.accumulate();
 
+    // the symbol at the origin stands for the end of the block, which sorts
+    // before any other occurrence of it
     let n = input.len();
     (0..n).fold(origin, |i,j| {
         let ch = input[i];
         output[n-j-1] = ch;
-        let offset = &input[..i].iter().filter(|&k| *k==ch).count();
+        let offset = if i == origin {
+            0
+        } else {
+            let before = input[..i].iter().filter(|&k| *k==ch).count();
+            if origin > i && input[origin] == ch { before + 1 } else { before }
+        };
         radix.freq[ch as usize] + offset
     });
 }
 
 
+/// Flag of the stream header announcing the versioned format, whose version
+/// and options follow the block size
+const VERSIONED: u32 = 1 << 31;
+/// Current version of the format
+const VERSION: u8 = 1;
+/// Option of the versioned format: every block is followed by the CRC-32 of
+/// its decoded content
+const OPTION_CRC: u8 = 1;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// The stream header: the largest block size, and the options of the
+/// versioned format. Streams without options keep the original format, made
+/// of the block size only.
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    max_block_size: usize,
+    checksum: bool,
+}
+
+impl Header {
+    fn read<R: Read>(r: &mut R) -> io::Result<Header> {
+        let size = match r.read_u32::<LittleEndian>() {
+            Ok(size) => size,
+            Err(e) => return Err(byteorder_err_to_io(e)),
+        };
+        if size & VERSIONED == 0 {
+            return Ok(Header {
+                max_block_size: size as usize,
+                checksum: false,
+            })
+        }
+        if try!(r.read_u8()) != VERSION {
+            return error("unsupported BWT stream version");
+        }
+        let options = try!(r.read_u8());
+        if options & !OPTION_CRC != 0 {
+            return error("unsupported BWT stream options");
+        }
+        Ok(Header {
+            max_block_size: (size & !VERSIONED) as usize,
+            checksum: options & OPTION_CRC != 0,
+        })
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        assert!(self.max_block_size < VERSIONED as usize, "BWT block size too large");
+        if !self.checksum {
+            return w.write_u32::<LittleEndian>(self.max_block_size as u32)
+        }
+        try!(w.write_u32::<LittleEndian>(self.max_block_size as u32 | VERSIONED));
+        try!(w.write_u8(VERSION));
+        w.write_u8(OPTION_CRC)
+    }
+
+    /// Validates the size and origin of a block before it's decoded
+    fn check_block(&self, n: usize, origin: usize) -> io::Result<()> {
+        if n > self.max_block_size {
+            return error("BWT block larger than the stream block size");
+        }
+        if origin >= cmp::max(n, 1) {
+            return error("invalid BWT origin");
+        }
+        Ok(())
+    }
+}
+
+/// Verifies the CRC-32 of a decoded block
+/// Resizes a decoding buffer to the 'n' elements of a block which size comes
+/// from the stream, failing instead of aborting if the memory is not there
+fn alloc_block<T: Clone + Default>(buf: &mut Vec<T>, n: usize) -> io::Result<()> {
+    buf.truncate(0);
+    if buf.try_reserve_exact(n).is_err() {
+        return error("not enough memory to decode the block");
+    }
+    buf.resize(n, T::default());
+    Ok(())
+}
+
+fn check_crc(block: &[Symbol], crc: u32) -> io::Result<()> {
+    let mut state = crc32::State::new();
+    state.feed(block);
+    if state.result() != crc {
+        return error("invalid checksum on BWT block");
+    }
+    Ok(())
+}
+
+
 /// This structure is used to decode a stream of BWT blocks. This wraps an
 /// internal reader which is read from when this decoder's read method is
 /// called.
@@ -327,10 +587,9 @@ pub struct Decoder<R> {
 
     temp   : Vec<u8>,
     output : Vec<u8>,
-    table  : Vec<usize>,
+    table  : Vec<u32>,
 
-    header         : bool,
-    max_block_size : usize,
+    header         : Option<Header>,
     extra_memory   : bool,
 }
 
@@ -338,7 +597,8 @@ impl<R: Read> Decoder<R> {
     /// Creates a new decoder which will read data from the given stream. The
     /// inner stream can be re-acquired by moving out of the `r` field of this
     /// structure.
-    /// 'extra_mem' switch allows allocating extra N words of memory for better performance
+    /// 'extra_mem' switch allows allocating extra 5N bytes of memory for a linear time decoding,
+    /// instead of the quadratic one working in place
     pub fn new(r: R, extra_mem: bool) -> Decoder<R> {
         Decoder {
             r: r,
@@ -346,8 +606,7 @@ impl<R: Read> Decoder<R> {
             temp: Vec::new(),
             output: Vec::new(),
             table: Vec::new(),
-            header: false,
-            max_block_size: 0,
+            header: None,
             extra_memory: extra_mem,
         }
     }
@@ -355,46 +614,45 @@ impl<R: Read> Decoder<R> {
     /// Resets this decoder back to its initial state. Note that the underlying
     /// stream is not seeked on or has any alterations performed on it.
     pub fn reset(&mut self) {
-        self.header = false;
+        self.header = None;
         self.start = 0;
     }
 
-    fn read_header(&mut self) -> io::Result<()> {
-        match self.r.read_u32::<LittleEndian>() {
-            Ok(size) => {
-                self.max_block_size = size as usize;
-                debug!("max size: {}", self.max_block_size);
-                Ok(())
-            },
-            Err(e) => Err(byteorder_err_to_io(e)),
-        }
-    }
-
-    fn decode_block(&mut self) -> io::Result<bool> {
+    fn decode_block(&mut self, header: Header) -> io::Result<bool> {
         let n = match self.r.read_u32::<LittleEndian>() {
             Ok(n) => n as usize,
             Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false), // EOF
             Err(e) => return Err(e),
         };
+        if n > header.max_block_size {
+            return error("BWT block larger than the stream block size");
+        }
 
+        // the buffer grows with the data actually read
         self.temp.truncate(0);
-        self.temp.reserve(n);
         try!(self.r.push_exactly(n as u64, &mut self.temp));
 
         let origin = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        try!(header.check_block(n, origin));
+        let crc = if header.checksum {
+            Some(try!(self.r.read_u32::<LittleEndian>()))
+        } else {
+            None
+        };
         self.output.truncate(0);
         self.output.reserve(n);
 
-        if self.extra_memory    {
+        if self.extra_memory && n <= MAX_INTERLEAVED_BLOCK {
             self.table.truncate(0);
             self.table.extend((0..n).map(|_| 0));
-            for ch in decode(&self.temp[..], origin, &mut self.table[..]) {
-                self.output.push(ch);
-            }
+            decode_interleaved(&self.temp[..], origin, &mut self.table[..], &mut self.output);
         }else   {
             self.output.extend((0..n).map(|_| 0));
             decode_minimal(&self.temp[..], origin, &mut self.output[..]);
         }
+        if let Some(crc) = crc {
+            try!(check_crc(&self.output, crc));
+        }
 
         self.start = 0;
         return Ok(true);
@@ -403,16 +661,21 @@ impl<R: Read> Decoder<R> {
 
 impl<R: Read> Read for Decoder<R> {
     fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
-        if !self.header {
-            try!(self.read_header());
-            self.header = true;
-        }
+        let header = match self.header {
+            Some(header) => header,
+            None => {
+                let header = try!(Header::read(&mut self.r));
+                debug!("header: {:?}", header);
+                self.header = Some(header);
+                header
+            }
+        };
         let mut amt = dst.len();
         let dst_len = amt;
 
         while amt > 0 {
             if self.output.len() == self.start {
-                let keep_going = try!(self.decode_block());
+                let keep_going = try!(self.decode_block(header));
                 if !keep_going {
                    break
                 }
@@ -434,12 +697,15 @@ impl<R: Read> Read for Decoder<R> {
 
 /// This structure is used to compress a stream of bytes using the BWT.
 /// This is a wrapper around an internal writer which bytes will be written to.
-pub struct Encoder<W> {
+/// Suffixes are sorted by SA-IS, unless another sorter is given.
+pub struct Encoder<W, S = SaIs> {
     w: W,
     buf: Vec<u8>,
-    suf: Vec<usize>,
+    suf: Vec<u32>,
+    sorter: S,
     wrote_header: bool,
     block_size: usize,
+    checksum: bool,
 }
 
 impl<W: Write> Encoder<W> {
@@ -449,31 +715,54 @@ impl<W: Write> Encoder<W> {
     /// 'block_size' is idealy as big as your input, unless you know for sure that
     /// the input consists of multiple parts of different nature. Often set as 4Mb.
     pub fn new(w: W, block_size: usize) -> Encoder<W> {
+        Encoder::with_sorter(w, block_size, SaIs)
+    }
+}
+
+impl<W: Write, S: SuffixSorter> Encoder<W, S> {
+    /// Creates a new encoder sorting the suffixes of its blocks with the given sorter.
+    pub fn with_sorter(w: W, block_size: usize, sorter: S) -> Encoder<W, S> {
         Encoder {
             w: w,
             buf: Vec::new(),
             suf: Vec::new(),
+            sorter,
             wrote_header: false,
             block_size: block_size,
+            checksum: false,
         }
     }
 
+    /// Follows every block with the CRC-32 of its content, which the decoder
+    /// verifies. This selects the versioned stream format, unknown to the
+    /// decoders predating it, and has to be set before anything is written.
+    pub fn set_checksum(&mut self, checksum: bool) {
+        assert!(!self.wrote_header, "BWT stream header already written");
+        self.checksum = checksum;
+    }
+
     fn encode_block(&mut self) -> io::Result<()> {
         let n = self.buf.len();
         try!(self.w.write_u32::<LittleEndian>(n as u32));
 
         self.suf.truncate(0);
-        self.suf.extend((0..n).map(|_| n));
+        self.suf.extend((0..n).map(|_| n as u32));
+        self.sorter.sort(&self.buf[..], &mut self.suf[..]);
         let w = &mut self.w;
 
         {
-            let mut iter = encode(&self.buf[..], &mut self.suf[..]);
+            let mut iter = TransformIterator::new(&self.buf[..], &self.suf[..]);
             for ch in iter.by_ref() {
                 try!(w.write_u8(ch));
             }
 
             try!(w.write_u32::<LittleEndian>(iter.get_origin() as u32));
         }
+        if self.checksum {
+            let mut crc = crc32::State::new();
+            crc.feed(&self.buf);
+            try!(self.w.write_u32::<LittleEndian>(crc.result()));
+        }
         self.buf.truncate(0);
 
         Ok(())
@@ -483,18 +772,28 @@ impl<W: Write> Encoder<W> {
     /// with. The stream is finished up (final bytes are written), and then the
     /// wrapped writer is returned.
     pub fn finish(mut self) -> (W, io::Result<()>) {
-        let result = self.flush();
+        let result = self.write_header().and_then(|_| self.flush());
         (self.w, result)
     }
-}
 
-impl<W: Write> Write for Encoder<W> {
-    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+    fn write_header(&mut self) -> io::Result<()> {
         if !self.wrote_header {
-            try!(self.w.write_u32::<LittleEndian>(self.block_size as u32));
+            let header = Header {
+                max_block_size: self.block_size,
+                checksum: self.checksum,
+            };
+            try!(header.write(&mut self.w));
             self.wrote_header = true;
         }
+        Ok(())
+    }
+}
 
+impl<W: Write, S: SuffixSorter> Write for Encoder<W, S> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+
+        let len = buf.len();
         while buf.len() > 0 {
             let amt = cmp::min( self.block_size - self.buf.len(), buf.len() );
             self.buf.extend(buf[..amt].iter().map(|b| *b));
@@ -504,7 +803,7 @@ impl<W: Write> Write for Encoder<W> {
             }
             buf = &buf[amt..];
         }
-        Ok(buf.len())
+        Ok(len)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -520,10 +819,11 @@ impl<W: Write> Write for Encoder<W> {
 
 #[cfg(test)]
 mod test {
-    use std::io::{BufReader, BufWriter, Read, Write};
+    use std::io::{self, BufReader, BufWriter, Read, Write};
     #[cfg(feature="unstable")]
     use test::Bencher;
-    use super::{Decoder, Encoder};
+    use super::super::rand::{random, Rng, thread_rng};
+    use super::{BruteForce, Decoder, Encoder, SaIs, SuffixSorter};
 
     fn roundtrip(bytes: &[u8], extra_mem: bool) {
         let mut e = Encoder::new(BufWriter::new(Vec::new()), 1<<10);
@@ -548,6 +848,151 @@ mod test {
     #[test]
     fn decode_minimal() {
         roundtrip(b"abracadabra", false);
+        roundtrip(include_bytes!("../data/test.txt"), false);
+    }
+
+    fn decode(encoded: &[u8], extra_mem: bool) -> io::Result<Vec<u8>> {
+        let mut d = Decoder::new(encoded, extra_mem);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).map(|_| decoded)
+    }
+
+    #[test]
+    fn invalid_blocks() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 1 << 12);
+        e.write_all(input).unwrap();
+        let encoded = e.finish().0;
+        let n = input.len();
+        // header, block size, block and origin
+        assert_eq!(encoded.len(), 4 + 4 + n + 4);
+
+        for &extra_mem in [false, true].iter() {
+            let mut bad_origin = encoded.clone();
+            bad_origin[(8 + n)..].copy_from_slice(&(n as u32).to_le_bytes());
+            assert!(decode(&bad_origin, extra_mem).is_err());
+
+            let mut too_large = encoded.clone();
+            too_large[4..8].copy_from_slice(&(1u32 << 12 | 1).to_le_bytes());
+            assert!(decode(&too_large, extra_mem).is_err());
+
+            // a huge block claimed by a huge header is only read as far as
+            // the data goes
+            let mut huge = encoded.clone();
+            huge[..4].copy_from_slice(&0x7fff_ffffu32.to_le_bytes());
+            huge[4..8].copy_from_slice(&0x7fff_fff0u32.to_le_bytes());
+            assert!(decode(&huge, extra_mem).is_err());
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new(), 1 << 10);
+        e.set_checksum(true);
+        e.write_all(input).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        assert!(decode(&encoded, true).unwrap()[..] == input[..]);
+        assert!(decode(&encoded, false).unwrap()[..] == input[..]);
+
+        // swapping two symbols of the transform keeps a valid block
+        let mut corrupted = encoded.clone();
+        let pos = (10..).find(|&i| corrupted[i] != corrupted[i + 1]).unwrap();
+        corrupted.swap(pos, pos + 1);
+        assert!(decode(&corrupted, true).is_err());
+
+        let mut unknown = encoded.clone();
+        unknown[4] = 2;
+        assert!(decode(&unknown, true).is_err());
+    }
+
+    fn check_inverse(input: &[u8]) {
+        let (transformed, origin) = super::encode_simple(input);
+        let mut table = vec![0u32; input.len()];
+        let mut output = Vec::new();
+        super::decode_interleaved(&transformed, origin, &mut table, &mut output);
+        assert!(&output[..] == input);
+    }
+
+    #[test]
+    fn interleaved_inverse() {
+        check_inverse(b"a");
+        check_inverse(b"abracadabra");
+        check_inverse(&[7; 100_000]);
+        check_inverse(&include_bytes!("../data/test.large")[..(1 << 20)]);
+        let mut rng = thread_rng();
+        for &len in [1000, 50_000, 300_000].iter() {
+            let alphabet = rng.gen_range(1, 255);
+            let input: Vec<u8> = (0..len).map(|_| rng.gen_range(0, alphabet)).collect();
+            check_inverse(&input);
+        }
+    }
+
+    fn check_sorters(input: &[u8]) {
+        let mut expected = vec![0u32; input.len()];
+        BruteForce.sort(input, &mut expected);
+        let mut suffixes = vec![0u32; input.len()];
+        SaIs.sort(input, &mut suffixes);
+        assert!(suffixes == expected, "suffixes of {:?}", input);
+        let mut wide = vec![0usize; input.len()];
+        super::compute_suffixes(input, &mut wide);
+        assert!(wide.iter().zip(expected.iter()).all(|(&a, &b)| a == b as usize));
+    }
+
+    #[test]
+    fn sorters_agree() {
+        check_sorters(b"");
+        check_sorters(b"a");
+        check_sorters(b"abracadabra");
+        check_sorters(b"mmiissiissiippii");
+        check_sorters(&[0; 1000]);
+        check_sorters(&b"abcabcabcabcabcab"[..]);
+        check_sorters(include_bytes!("../data/test.txt"));
+        let mut rng = thread_rng();
+        for _ in 0..500 {
+            let len = random::<usize>() % 200;
+            let alphabet = 1 + random::<u8>() % 4;
+            let input: Vec<u8> = (0..len).map(|_| rng.gen_range(0, alphabet)).collect();
+            check_sorters(&input);
+        }
+    }
+
+    #[test]
+    fn repetitive_blocks() {
+        let mut input = vec![0u8; 1 << 20];
+        for _ in 0..20000 {
+            input.extend_from_slice(b"2015-06-01 12:00:00 INFO request served\n");
+        }
+        let mut e = Encoder::new(Vec::new(), 4 << 20);
+        e.write_all(&input).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]), true);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == input);
+
+        let mut e = Encoder::with_sorter(Vec::new(), 1 << 10, BruteForce);
+        e.write_all(&input[(1 << 20)..]).unwrap();
+        let mut reference = Encoder::new(Vec::new(), 1 << 10);
+        reference.write_all(&input[(1 << 20)..]).unwrap();
+        assert!(e.finish().0 == reference.finish().0);
+    }
+
+    #[cfg(feature="unstable")]
+    #[bench]
+    fn decode_interleaved_speed(bh: &mut Bencher) {
+        let input = &include_bytes!("../data/test.large")[..(1 << 20)];
+        let (output, origin) = super::encode_simple(input);
+        let mut table = vec![0u32; input.len()];
+
+        bh.iter(|| {
+            let mut decoded = Vec::new();
+            super::decode_interleaved(&output, origin, &mut table, &mut decoded);
+            decoded
+        });
+        bh.bytes = input.len() as u64;
     }
 
     #[cfg(feature="unstable")]
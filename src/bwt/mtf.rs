@@ -4,9 +4,17 @@ MTF (Move To Front) encoder/decoder
 Produces a rank for each input character based on when it was seen last time.
 Useful for BWT output encoding, which produces a lot of zeroes and low ranks.
 
+Next to the classic MTF, the `RankCoder` trait is implemented by variants
+moving the symbols less eagerly to the front: MTF-1 and MTF-2 of Balkenhol,
+Kurtz and Shtarkov, and a limited move to a fraction of the rank (`Sticky`).
+WFC (Weighted Frequency Coding) ranks the symbols by the sum of the weights
+of their recent occurrences, the weight of an occurrence depending on its age.
+The stream encoder and decoder take any of them, MTF by default.
+
 # Links
 
 http://en.wikipedia.org/wiki/Move-to-front_transform
+http://www.data-compression.info/Algorithms/MTF/
 
 # Example
 
@@ -24,6 +32,15 @@ let encoded = e.finish().into_inner().unwrap();
 let mut d = mtf::Decoder::new(io::BufReader::new(&encoded[..]));
 let mut decoded = Vec::new();
 let result = d.read_to_end(&mut decoded).unwrap();
+
+// Use MTF-2 instead
+let mut e = mtf::Encoder::with_coder(Vec::new(), mtf::Mtf2::new());
+e.write_all(bytes).unwrap();
+let encoded = e.finish();
+let mut d = mtf::Decoder::with_coder(&encoded[..], mtf::Mtf2::new());
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &bytes[..]);
 ```
 
 # Credit
@@ -32,6 +49,7 @@ let result = d.read_to_end(&mut decoded).unwrap();
 
 use std::mem;
 use std::io::{self, Read, Write};
+use std::collections::VecDeque;
 
 use super::super::byteorder::{WriteBytesExt, ReadBytesExt};
 
@@ -90,11 +108,260 @@ impl MTF {
     }
 }
 
+impl RankCoder for MTF {
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        MTF::encode(self, sym)
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        MTF::decode(self, rank)
+    }
+}
+
+
+/// A coder of symbols into ranks, the list of symbols being reordered after
+/// each of them by the same rule when encoding and decoding
+pub trait RankCoder {
+    /// encode a symbol into its rank
+    fn encode(&mut self, sym: Symbol) -> Rank;
+    /// decode a rank into its symbol
+    fn decode(&mut self, rank: Rank) -> Symbol;
+}
+
+fn alphabetical() -> [Symbol; TOTAL_SYMBOLS] {
+    let mut symbols = [0; TOTAL_SYMBOLS];
+    for (i,sym) in symbols.iter_mut().enumerate() {
+        *sym = i as Symbol;
+    }
+    symbols
+}
+
+fn rank_of(symbols: &[Symbol; TOTAL_SYMBOLS], sym: Symbol) -> Rank {
+    symbols.iter().position(|&s| s == sym).unwrap() as Rank
+}
+
+/// move the symbol of rank 'from' to rank 'to', not further from the front
+fn move_to(symbols: &mut [Symbol; TOTAL_SYMBOLS], from: Rank, to: Rank) {
+    symbols[to as usize .. from as usize + 1].rotate_right(1);
+}
+
+/// MTF-1: only a symbol of rank 1 moves to the front, the others moving to
+/// rank 1
+pub struct Mtf1 {
+    /// rank-ordered list of unique Symbols
+    pub symbols: [Symbol; TOTAL_SYMBOLS],
+}
+
+impl Mtf1 {
+    /// create a new MTF-1 with symbols in the alphabetical order
+    pub fn new() -> Mtf1 {
+        Mtf1 { symbols: alphabetical() }
+    }
+
+    fn update(&mut self, rank: Rank) {
+        match rank {
+            0 => (),
+            1 => move_to(&mut self.symbols, 1, 0),
+            _ => move_to(&mut self.symbols, rank, 1),
+        }
+    }
+}
+
+impl Default for Mtf1 {
+    fn default() -> Mtf1 {
+        Mtf1::new()
+    }
+}
+
+impl RankCoder for Mtf1 {
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = rank_of(&self.symbols, sym);
+        self.update(rank);
+        rank
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = self.symbols[rank as usize];
+        self.update(rank);
+        sym
+    }
+}
+
+/// MTF-2: like MTF-1, but a symbol of rank 1 only moves to the front when
+/// the previous rank is not 0, so that a symbol interrupting a run of the
+/// front one stays behind it
+pub struct Mtf2 {
+    /// rank-ordered list of unique Symbols
+    pub symbols: [Symbol; TOTAL_SYMBOLS],
+    last_rank: Rank,
+}
+
+impl Mtf2 {
+    /// create a new MTF-2 with symbols in the alphabetical order
+    pub fn new() -> Mtf2 {
+        Mtf2 { symbols: alphabetical(), last_rank: 0 }
+    }
+
+    fn update(&mut self, rank: Rank) {
+        match rank {
+            0 => (),
+            1 if self.last_rank == 0 => (),
+            1 => move_to(&mut self.symbols, 1, 0),
+            _ => move_to(&mut self.symbols, rank, 1),
+        }
+        self.last_rank = rank;
+    }
+}
+
+impl Default for Mtf2 {
+    fn default() -> Mtf2 {
+        Mtf2::new()
+    }
+}
+
+impl RankCoder for Mtf2 {
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = rank_of(&self.symbols, sym);
+        self.update(rank);
+        rank
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = self.symbols[rank as usize];
+        self.update(rank);
+        sym
+    }
+}
+
+/// Limited move: a symbol of rank 'r' moves to rank 'r >> shift', so that
+/// the symbols near the front stick to their places
+pub struct Sticky {
+    /// rank-ordered list of unique Symbols
+    pub symbols: [Symbol; TOTAL_SYMBOLS],
+    shift: u32,
+}
+
+impl Sticky {
+    /// create a new limited move coder with symbols in the alphabetical
+    /// order, 'shift' being 1 for moving half way to the front, and 8 for
+    /// the classic MTF
+    pub fn new(shift: u32) -> Sticky {
+        assert!(shift > 0, "a sticky MTF has to move the symbols");
+        Sticky { symbols: alphabetical(), shift }
+    }
+
+    fn update(&mut self, rank: Rank) {
+        let to = (rank as u32).checked_shr(self.shift).unwrap_or(0) as Rank;
+        move_to(&mut self.symbols, rank, to);
+    }
+}
+
+impl RankCoder for Sticky {
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = rank_of(&self.symbols, sym);
+        self.update(rank);
+        rank
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = self.symbols[rank as usize];
+        self.update(rank);
+        sym
+    }
+}
+
+/// Default number of the last symbols weighted by WFC
+pub const DEFAULT_WFC_WINDOW: usize = 64;
+
+/// WFC (Weighted Frequency Coding): the symbols are ranked by the sum of the
+/// weights of their occurrences among the last ones, given by their age, the
+/// ties keeping their previous order
+pub struct Wfc {
+    /// rank-ordered list of unique Symbols
+    pub symbols: [Symbol; TOTAL_SYMBOLS],
+    /// weight of an occurrence, by its age starting with the last symbol
+    weights: Vec<u32>,
+    /// the last symbols, the latest first
+    history: VecDeque<Symbol>,
+    totals: [u64; TOTAL_SYMBOLS],
+}
+
+impl Wfc {
+    /// create a new WFC weighting the last DEFAULT_WFC_WINDOW symbols by the
+    /// inverse of their age
+    pub fn new() -> Wfc {
+        Wfc::with_function(DEFAULT_WFC_WINDOW, |age| (1 << 16) / age as u32)
+    }
+
+    /// create a new WFC weighting the last 'window' symbols by 'weight',
+    /// given the age of an occurrence, from 1 for the last symbol
+    pub fn with_function<F: Fn(usize) -> u32>(window: usize, weight: F) -> Wfc {
+        Wfc::with_weights((1 .. window + 1).map(weight).collect())
+    }
+
+    /// create a new WFC with the weights of the occurrences by their age,
+    /// the first one being for the last symbol
+    pub fn with_weights(weights: Vec<u32>) -> Wfc {
+        assert!(!weights.is_empty(), "WFC needs a window of symbols");
+        Wfc {
+            symbols: alphabetical(),
+            history: VecDeque::with_capacity(weights.len()),
+            weights,
+            totals: [0; TOTAL_SYMBOLS],
+        }
+    }
+
+    fn update(&mut self, sym: Symbol) {
+        if self.history.len() == self.weights.len() {
+            self.history.pop_back();
+        }
+        self.history.push_front(sym);
+        for total in self.totals.iter_mut() {
+            *total = 0;
+        }
+        for (&s, &w) in self.history.iter().zip(self.weights.iter()) {
+            self.totals[s as usize] += w as u64;
+        }
+        // the order changes little between symbols, so an insertion sort is
+        // about linear
+        for i in 1 .. TOTAL_SYMBOLS {
+            let s = self.symbols[i];
+            let mut j = i;
+            while j > 0 && self.totals[self.symbols[j-1] as usize] < self.totals[s as usize] {
+                self.symbols[j] = self.symbols[j-1];
+                j -= 1;
+            }
+            self.symbols[j] = s;
+        }
+    }
+}
+
+impl Default for Wfc {
+    fn default() -> Wfc {
+        Wfc::new()
+    }
+}
+
+impl RankCoder for Wfc {
+    fn encode(&mut self, sym: Symbol) -> Rank {
+        let rank = rank_of(&self.symbols, sym);
+        self.update(sym);
+        rank
+    }
+
+    fn decode(&mut self, rank: Rank) -> Symbol {
+        let sym = self.symbols[rank as usize];
+        self.update(sym);
+        sym
+    }
+}
+
 
-/// A simple MTF stream encoder
-pub struct Encoder<W> {
+/// A simple MTF stream encoder, ranking the symbols with classic MTF unless
+/// another `RankCoder` is given
+pub struct Encoder<W, C = MTF> {
     w: W,
-    mtf: MTF,
+    mtf: C,
 }
 
 impl<W> Encoder<W> {
@@ -102,9 +369,16 @@ impl<W> Encoder<W> {
     pub fn new(w: W) -> Encoder<W> {
         let mut mtf = MTF::new();
         mtf.reset_alphabetical();
+        Encoder::with_coder(w, mtf)
+    }
+}
+
+impl<W, C> Encoder<W, C> {
+    /// start encoding into the given writer with the given rank coder
+    pub fn with_coder(w: W, coder: C) -> Encoder<W, C> {
         Encoder {
             w: w,
-            mtf: mtf,
+            mtf: coder,
         }
     }
 
@@ -114,7 +388,7 @@ impl<W> Encoder<W> {
     }
 }
 
-impl<W: Write> Write for Encoder<W> {
+impl<W: Write, C: RankCoder> Write for Encoder<W, C> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         for sym in buf.iter() {
             let rank = self.mtf.encode(*sym);
@@ -129,10 +403,10 @@ impl<W: Write> Write for Encoder<W> {
 }
 
 
-/// A simple MTF stream decoder
-pub struct Decoder<R> {
+/// A simple MTF stream decoder, expecting the rank coder of the encoder
+pub struct Decoder<R, C = MTF> {
     r: R,
-    mtf: MTF,
+    mtf: C,
 }
 
 impl<R> Decoder<R> {
@@ -140,9 +414,16 @@ impl<R> Decoder<R> {
     pub fn new(r: R) -> Decoder<R> {
         let mut mtf = MTF::new();
         mtf.reset_alphabetical();
+        Decoder::with_coder(r, mtf)
+    }
+}
+
+impl<R, C> Decoder<R, C> {
+    /// start decoding the given reader with the given rank coder
+    pub fn with_coder(r: R, coder: C) -> Decoder<R, C> {
         Decoder {
             r: r,
-            mtf: mtf,
+            mtf: coder,
         }
     }
 
@@ -152,7 +433,7 @@ impl<R> Decoder<R> {
     }
 }
 
-impl<R: Read> Read for Decoder<R> {
+impl<R: Read, C: RankCoder> Read for Decoder<R, C> {
     fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
         let mut bytes_read = 0;
         for sym in dst.iter_mut() {
@@ -174,7 +455,7 @@ mod test {
     use std::io::{self, Read, Write};
     #[cfg(feature="unstable")]
     use test::Bencher;
-    use super::{Encoder, Decoder};
+    use super::{Encoder, Decoder, RankCoder, MTF, Mtf1, Mtf2, Sticky, Wfc};
 
     fn roundtrip(bytes: &[u8]) {
         info!("Roundtrip MTF of size {}", bytes.len());
@@ -189,6 +470,20 @@ mod test {
         assert_eq!(&decoded[..], bytes);
     }
 
+    fn roundtrip_coder<C: RankCoder, F: Fn() -> C>(bytes: &[u8], new_coder: F) {
+        let mut e = Encoder::with_coder(Vec::new(), new_coder());
+        e.write_all(bytes).unwrap();
+        let encoded = e.finish();
+        let mut d = Decoder::with_coder(&encoded[..], new_coder());
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], bytes);
+    }
+
+    fn ranks<C: RankCoder>(bytes: &[u8], mut coder: C) -> Vec<u8> {
+        bytes.iter().map(|&b| coder.encode(b)).collect()
+    }
+
     #[test]
     fn some_roundtrips() {
         roundtrip(b"teeesst_mtf");
@@ -196,6 +491,33 @@ mod test {
         roundtrip(include_bytes!("../data/test.txt"));
     }
 
+    #[test]
+    fn variants() {
+        let text = include_bytes!("../data/test.txt");
+        let (transformed, _) = super::super::encode_simple(text);
+        for bytes in [&b"teeesst_mtf"[..], b"", text, &transformed[..]].iter() {
+            roundtrip_coder(bytes, Mtf1::new);
+            roundtrip_coder(bytes, Mtf2::new);
+            roundtrip_coder(bytes, || Sticky::new(1));
+            roundtrip_coder(bytes, || Sticky::new(3));
+            roundtrip_coder(bytes, Wfc::new);
+            roundtrip_coder(bytes, || Wfc::with_function(8, |age| 9 - age as u32));
+            roundtrip_coder(bytes, || Wfc::with_weights(vec![1]));
+        }
+
+        assert_eq!(ranks(&[0, 0, 1, 0, 0, 2, 2], Mtf1::new()), [0, 0, 1, 1, 0, 2, 1]);
+        assert_eq!(ranks(&[0, 0, 1, 0, 0, 1, 1], Mtf2::new()), [0, 0, 1, 0, 0, 1, 1]);
+        assert_eq!(ranks(&[4, 4, 4, 4], Sticky::new(1)), [4, 2, 1, 0]);
+        assert_eq!(ranks(&[4, 4, 4], Sticky::new(8)), [4, 0, 0]);
+        // 3 weighs less than 0 after two other symbols
+        assert_eq!(ranks(&[3, 3, 0, 3], Wfc::new()), [3, 0, 1, 1]);
+        // with a single weight, WFC is the classic MTF
+        let mut mtf = MTF::new();
+        mtf.reset_alphabetical();
+        assert_eq!(ranks(&transformed, Wfc::with_weights(vec![1])),
+                   ranks(&transformed, mtf));
+    }
+
     #[cfg(feature="unstable")]
     #[bench]
     fn encode_speed(bh: &mut Bencher) {
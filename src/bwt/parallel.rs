@@ -0,0 +1,448 @@
+/*!
+
+Parallel BWT encoding and decoding of independent blocks
+
+The blocks of a BWT stream don't depend on each other, so several of them can
+be sorted or inverted at the same time. The `Encoder` and `Decoder` of this
+module hand the blocks over to a fixed set of worker threads, while the
+stream itself is written or read by the calling thread, in order. At most two
+blocks per worker are in flight at any time, which bounds the memory use.
+
+The stream format is the one of `bwt::Encoder` and `bwt::Decoder`, so both
+sides can be mixed freely.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::bwt::parallel;
+
+let text = b"some text";
+let mut e = parallel::Encoder::new(Vec::new(), 4 << 20, 4);
+e.write_all(text).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+let mut d = parallel::Decoder::new(&encoded[..], 4);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &text[..]);
+```
+
+*/
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use super::super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use super::super::ReadExact;
+use super::super::checksum::crc32;
+use super::{check_crc, decode_interleaved, Header, MAX_INTERLEAVED_BLOCK, SaIs, SuffixSorter,
+            TransformIterator};
+
+type Job = Box<dyn FnOnce() -> io::Result<Vec<u8>> + Send>;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Worker threads running jobs, whose results are returned in the order the
+/// jobs were submitted
+struct Pool {
+    jobs: Option<SyncSender<(u64, Job)>>,
+    results: Receiver<(u64, io::Result<Vec<u8>>)>,
+    threads: Vec<JoinHandle<()>>,
+    /// results received ahead of their turn
+    pending: BTreeMap<u64, io::Result<Vec<u8>>>,
+    submitted: u64,
+    returned: u64,
+}
+
+impl Pool {
+    fn new(workers: usize) -> Pool {
+        assert!(workers > 0, "no worker threads");
+        let (jobs, job_receiver) = sync_channel::<(u64, Job)>(workers);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (result_sender, results) = channel();
+        let threads = (0..workers).map(|_| {
+            let jobs = job_receiver.clone();
+            let results: Sender<_> = result_sender.clone();
+            thread::spawn(move || loop {
+                let job = match jobs.lock() {
+                    Ok(jobs) => jobs.recv(),
+                    Err(_) => return,
+                };
+                match job {
+                    Ok((seq, job)) => {
+                        if results.send((seq, job())).is_err() {
+                            return
+                        }
+                    }
+                    Err(_) => return,
+                }
+            })
+        }).collect();
+        Pool {
+            jobs: Some(jobs),
+            results,
+            threads,
+            pending: BTreeMap::new(),
+            submitted: 0,
+            returned: 0,
+        }
+    }
+
+    /// Number of jobs submitted whose result wasn't returned yet
+    fn in_flight(&self) -> usize {
+        (self.submitted - self.returned) as usize
+    }
+
+    /// Most jobs kept in flight, to keep the workers busy while the results
+    /// are consumed
+    fn capacity(&self) -> usize {
+        2 * self.threads.len()
+    }
+
+    fn submit(&mut self, job: Job) -> io::Result<()> {
+        let sent = match self.jobs {
+            Some(ref jobs) => jobs.send((self.submitted, job)).is_ok(),
+            None => false,
+        };
+        if !sent {
+            return Err(io::Error::other("BWT worker thread failed"));
+        }
+        self.submitted += 1;
+        Ok(())
+    }
+
+    /// Waits for the result of the oldest job in flight
+    fn next(&mut self) -> io::Result<Vec<u8>> {
+        assert!(self.in_flight() > 0);
+        loop {
+            if let Some(result) = self.pending.remove(&self.returned) {
+                self.returned += 1;
+                return result
+            }
+            match self.results.recv() {
+                Ok((seq, result)) => {
+                    self.pending.insert(seq, result);
+                }
+                Err(_) => return Err(io::Error::other("BWT worker thread failed")),
+            }
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // workers stop once the job queue is closed
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Encodes a block into its serialized form: size, transform, origin and
+/// the optional CRC-32
+fn encode_block(block: Vec<u8>, checksum: bool) -> io::Result<Vec<u8>> {
+    let n = block.len();
+    let mut suf = vec![0u32; n];
+    SaIs.sort(&block, &mut suf);
+    let mut out = Vec::with_capacity(n + 8);
+    try!(out.write_u32::<LittleEndian>(n as u32));
+    let mut iter = TransformIterator::new(&block, &suf);
+    out.extend(iter.by_ref());
+    try!(out.write_u32::<LittleEndian>(iter.get_origin() as u32));
+    if checksum {
+        let mut crc = crc32::State::new();
+        crc.feed(&block);
+        try!(out.write_u32::<LittleEndian>(crc.result()));
+    }
+    Ok(out)
+}
+
+/// Decodes the transform of a block, verifying its CRC-32 if any
+fn decode_block(block: Vec<u8>, origin: usize, crc: Option<u32>) -> io::Result<Vec<u8>> {
+    let mut table = vec![0u32; block.len()];
+    let mut output = Vec::new();
+    decode_interleaved(&block, origin, &mut table, &mut output);
+    if let Some(crc) = crc {
+        try!(check_crc(&output, crc));
+    }
+    Ok(output)
+}
+
+
+/// This structure is used to compress a stream of bytes using the BWT, on
+/// several threads. This is a wrapper around an internal writer which bytes
+/// will be written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<u8>,
+    pool: Pool,
+    wrote_header: bool,
+    block_size: usize,
+    checksum: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream, sorting up to 'workers' blocks at once. The output
+    /// stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, block_size: usize, workers: usize) -> Encoder<W> {
+        assert!(block_size > 0, "empty BWT blocks");
+        Encoder {
+            w,
+            buf: Vec::new(),
+            pool: Pool::new(workers),
+            wrote_header: false,
+            block_size,
+            checksum: false,
+        }
+    }
+
+    /// Follows every block with the CRC-32 of its content, like
+    /// `bwt::Encoder::set_checksum` does. Has to be set before anything is
+    /// written.
+    pub fn set_checksum(&mut self, checksum: bool) {
+        assert!(!self.wrote_header, "BWT stream header already written");
+        self.checksum = checksum;
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            let header = Header {
+                max_block_size: self.block_size,
+                checksum: self.checksum,
+            };
+            try!(header.write(&mut self.w));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    /// Writes out the oldest block in flight
+    fn write_next(&mut self) -> io::Result<()> {
+        let block = try!(self.pool.next());
+        self.w.write_all(&block)
+    }
+
+    fn submit_block(&mut self) -> io::Result<()> {
+        if self.pool.in_flight() == self.pool.capacity() {
+            try!(self.write_next());
+        }
+        let block = ::std::mem::take(&mut self.buf);
+        let checksum = self.checksum;
+        self.pool.submit(Box::new(move || encode_block(block, checksum)))
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        let Encoder { w, pool, .. } = self;
+        drop(pool);
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+            if self.buf.len() == self.block_size {
+                try!(self.submit_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    /// Encodes the pending data as a block, and waits for all the blocks in
+    /// flight to be written.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(self.submit_block());
+        }
+        while self.pool.in_flight() > 0 {
+            try!(self.write_next());
+        }
+        self.w.flush()
+    }
+}
+
+
+/// This structure is used to decode a stream of BWT blocks on several
+/// threads. This wraps an internal reader which is read from when this
+/// decoder's read method is called.
+pub struct Decoder<R> {
+    r: R,
+    pool: Pool,
+    output: Vec<u8>,
+    start: usize,
+    header: Option<Header>,
+    input_done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream,
+    /// inverting up to 'workers' blocks at once.
+    pub fn new(r: R, workers: usize) -> Decoder<R> {
+        Decoder {
+            r,
+            pool: Pool::new(workers),
+            output: Vec::new(),
+            start: 0,
+            header: None,
+            input_done: false,
+        }
+    }
+
+    /// Destroys this decoder, returning the underlying reader. It may have
+    /// been read ahead of the data returned so far.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    /// Reads the next block and submits it, returning false at the end
+    fn submit_block(&mut self, header: Header) -> io::Result<bool> {
+        let n = match self.r.read_u32::<LittleEndian>() {
+            Ok(n) => n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if n > header.max_block_size {
+            return error("BWT block larger than the stream block size");
+        }
+        if n > MAX_INTERLEAVED_BLOCK {
+            return error("BWT block too large to decode");
+        }
+        let mut block = Vec::new();
+        try!(self.r.push_exactly(n as u64, &mut block));
+        let origin = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        try!(header.check_block(n, origin));
+        let crc = if header.checksum {
+            Some(try!(self.r.read_u32::<LittleEndian>()))
+        } else {
+            None
+        };
+        try!(self.pool.submit(Box::new(move || decode_block(block, origin, crc))));
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let header = match self.header {
+            Some(header) => header,
+            None => {
+                let header = try!(Header::read(&mut self.r));
+                self.header = Some(header);
+                header
+            }
+        };
+        let mut amt = 0;
+        while amt < dst.len() {
+            if self.output.len() == self.start {
+                while !self.input_done && self.pool.in_flight() < self.pool.capacity() {
+                    self.input_done = !try!(self.submit_block(header));
+                }
+                if self.pool.in_flight() == 0 {
+                    break
+                }
+                self.output = try!(self.pool.next());
+                self.start = 0;
+            }
+            let n = cmp::min(dst.len() - amt, self.output.len() - self.start);
+            dst[amt..(amt + n)].copy_from_slice(&self.output[self.start..(self.start + n)]);
+            self.start += n;
+            amt += n;
+        }
+        Ok(amt)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use super::{Decoder, Encoder};
+
+    fn serial(bytes: &[u8], block_size: usize) -> Vec<u8> {
+        let mut e = super::super::Encoder::new(Vec::new(), block_size);
+        e.write_all(bytes).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    fn check(bytes: &[u8], block_size: usize, workers: usize) {
+        let mut e = Encoder::new(Vec::new(), block_size, workers);
+        for chunk in bytes.chunks(1000) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        assert!(encoded == serial(bytes, block_size));
+
+        let mut d = Decoder::new(BufReader::new(&encoded[..]), workers);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == bytes);
+
+        let mut d = super::super::Decoder::new(BufReader::new(&encoded[..]), true);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == bytes);
+    }
+
+    #[test]
+    fn matches_serial() {
+        check(b"", 100, 2);
+        check(b"test", 100, 1);
+        check(include_bytes!("../data/test.txt"), 100, 3);
+        check(&include_bytes!("../data/test.large")[..(1 << 20)], 1 << 16, 4);
+    }
+
+    #[test]
+    fn checksums() {
+        let input = include_bytes!("../data/test.txt");
+        let mut serial = super::super::Encoder::new(Vec::new(), 1 << 9);
+        serial.set_checksum(true);
+        serial.write_all(input).unwrap();
+        let mut e = Encoder::new(Vec::new(), 1 << 9, 2);
+        e.set_checksum(true);
+        e.write_all(input).unwrap();
+        let encoded = e.finish().0;
+        assert!(encoded == serial.finish().0);
+
+        let mut d = Decoder::new(&encoded[..], 2);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded[..] == input[..]);
+
+        let mut corrupted = encoded.clone();
+        let pos = (10..).find(|&i| corrupted[i] != corrupted[i + 1]).unwrap();
+        corrupted.swap(pos, pos + 1);
+        let mut d = Decoder::new(&corrupted[..], 2);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut encoded = serial(include_bytes!("../data/test.txt"), 1 << 10);
+        let len = encoded.len();
+        encoded.truncate(len - 100);
+        let mut d = Decoder::new(&encoded[..], 2);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
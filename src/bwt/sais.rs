@@ -0,0 +1,269 @@
+/*!
+
+Suffix array construction by induced sorting (SA-IS)
+
+Suffixes are classified as S-type when they are smaller than the suffix that
+follows them, and L-type otherwise. The leftmost S-type suffixes of their
+runs (LMS) are sorted first, and their order induces the one of all the
+other suffixes in two linear passes. Sorting the LMS suffixes themselves
+reduces to building the suffix array of a string at most half as long, made
+of the ranks of the LMS substrings, hence the recursion.
+
+The end of the input acts as a virtual sentinel, smaller than any symbol, so
+a suffix which is a prefix of another one sorts first.
+
+The reduced string and its suffix array are both kept in the suffix array of
+the input, of any integer type able to hold the input length. On top of it,
+every level of the recursion needs a bit per symbol for the suffix types and
+a bucket counter per symbol of its alphabet. The alphabet of the reduced
+string is the set of names of the LMS substrings, so up to N/2 counters are
+needed at the first level of the recursion, and half as many at each next one.
+
+# Related links
+
+* http://ge-nong.googlecode.com/files/Linear%20Suffix%20Array%20Construction%20by%20Almost%20Pure%20Induced-Sorting.pdf
+  - the paper of Ge Nong, Sen Zhang and Wai Hong Chan
+
+*/
+
+use super::num::traits::{NumCast, ToPrimitive};
+
+/// A symbol of the string to sort, which is either the input or the reduced
+/// string of the recursion, stored in the suffix array
+trait Char {
+    fn index(&self) -> usize;
+}
+
+impl<T: ToPrimitive> Char for T {
+    fn index(&self) -> usize {
+        self.to_usize().unwrap()
+    }
+}
+
+/// A suffix array slot, holding a position, a name or the empty marker
+trait Slot {
+    fn get(&self) -> u32;
+    fn set(&mut self, value: u32);
+}
+
+impl<S: NumCast> Slot for S {
+    fn get(&self) -> u32 {
+        self.to_u32().unwrap()
+    }
+    fn set(&mut self, value: u32) {
+        *self = NumCast::from(value).unwrap();
+    }
+}
+
+/// Marker of an unfilled slot of the suffix array of 's': no suffix, nor
+/// name, can be as large as the length
+fn empty<T>(s: &[T]) -> u32 {
+    s.len() as u32
+}
+
+/// The S/L types of the suffixes, a bit each
+struct Types {
+    bits: Vec<u64>,
+}
+
+impl Types {
+    fn new<T: Char>(s: &[T]) -> Types {
+        let n = s.len();
+        let mut types = Types {
+            bits: vec![0; n / 64 + 1],
+        };
+        // the last suffix is larger than the virtual sentinel
+        for i in (0..n.saturating_sub(1)).rev() {
+            let (a, b) = (s[i].index(), s[i + 1].index());
+            if a < b || (a == b && types.is_s(i + 1)) {
+                types.bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+        types
+    }
+
+    fn is_s(&self, i: usize) -> bool {
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    /// Tests for a leftmost S-type suffix
+    fn is_lms(&self, i: usize) -> bool {
+        i > 0 && self.is_s(i) && !self.is_s(i - 1)
+    }
+}
+
+/// Computes the first ('end' false) or one past the last ('end' true) slot
+/// of every symbol bucket
+fn buckets<T: Char>(s: &[T], k: usize, end: bool) -> Vec<u32> {
+    let mut bkt = vec![0u32; k];
+    for c in s.iter() {
+        bkt[c.index()] += 1;
+    }
+    let mut sum = 0;
+    for b in bkt.iter_mut() {
+        sum += *b;
+        *b = if end { sum } else { sum - *b };
+    }
+    bkt
+}
+
+/// Sorts the L-type then the S-type suffixes from the LMS ones already in
+/// place
+fn induce<T: Char, S: Slot>(s: &[T], sa: &mut [S], types: &Types, k: usize) {
+    let n = s.len();
+    let empty = empty(s);
+    let mut bkt = buckets(s, k, false);
+    // the suffix before the virtual sentinel comes first in its bucket
+    let c = s[n - 1].index();
+    sa[bkt[c] as usize].set((n - 1) as u32);
+    bkt[c] += 1;
+    for i in 0..n {
+        let j = sa[i].get();
+        if j != empty && j > 0 && !types.is_s(j as usize - 1) {
+            let c = s[j as usize - 1].index();
+            sa[bkt[c] as usize].set(j - 1);
+            bkt[c] += 1;
+        }
+    }
+    let mut bkt = buckets(s, k, true);
+    for i in (0..n).rev() {
+        let j = sa[i].get();
+        if j != empty && j > 0 && types.is_s(j as usize - 1) {
+            let c = s[j as usize - 1].index();
+            bkt[c] -= 1;
+            sa[bkt[c] as usize].set(j - 1);
+        }
+    }
+}
+
+/// Tests if the LMS substrings starting at 'a' and 'b' are equal. The one
+/// running up to the sentinel is unique.
+fn lms_equal<T: Char>(s: &[T], types: &Types, a: usize, b: usize) -> bool {
+    let n = s.len();
+    for d in 0.. {
+        if a + d == n || b + d == n {
+            return false
+        }
+        if s[a + d].index() != s[b + d].index() || types.is_s(a + d) != types.is_s(b + d) {
+            return false
+        }
+        if d > 0 && types.is_lms(a + d) {
+            return types.is_lms(b + d)
+        }
+    }
+    unreachable!()
+}
+
+/// Fills 'sa' with the suffix array of 's', made of symbols below 'k'
+fn sais<T: Char, S: Slot + Char>(s: &[T], sa: &mut [S], k: usize) {
+    let n = s.len();
+    match n {
+        0 => return,
+        1 => {
+            sa[0].set(0);
+            return
+        }
+        _ => (),
+    }
+    let empty = empty(s);
+    let types = Types::new(s);
+
+    // sort the LMS substrings
+    for x in sa.iter_mut() {
+        x.set(empty);
+    }
+    {
+        let mut bkt = buckets(s, k, true);
+        for (i, c) in s.iter().enumerate().skip(1) {
+            if types.is_lms(i) {
+                bkt[c.index()] -= 1;
+                sa[bkt[c.index()] as usize].set(i as u32);
+            }
+        }
+    }
+    induce(s, sa, &types, k);
+
+    // gather them at the front, and name them by rank
+    let mut n1 = 0;
+    for i in 0..n {
+        let j = sa[i].get();
+        if types.is_lms(j as usize) {
+            sa[n1].set(j);
+            n1 += 1;
+        }
+    }
+    for x in sa[n1..].iter_mut() {
+        x.set(empty);
+    }
+    // LMS positions are 2 apart at least, so their halves don't collide
+    let mut names = 0;
+    let mut prev = None;
+    for i in 0..n1 {
+        let pos = sa[i].get() as usize;
+        let same = match prev {
+            Some(p) => lms_equal(s, &types, p, pos),
+            None => false,
+        };
+        if !same {
+            names += 1;
+            prev = Some(pos);
+        }
+        sa[n1 + pos / 2].set(names - 1);
+    }
+    let mut j = n;
+    for i in (n1..n).rev() {
+        let name = sa[i].get();
+        if name != empty {
+            j -= 1;
+            sa[j].set(name);
+        }
+    }
+
+    // sort the reduced string, made of the names in the text order
+    {
+        let (sa1, rest) = sa.split_at_mut(n1);
+        let s1 = &mut rest[(n - 2 * n1)..];
+        if (names as usize) < n1 {
+            sais(s1, sa1, names as usize);
+        } else {
+            for (i, c) in s1.iter().enumerate() {
+                sa1[c.index()].set(i as u32);
+            }
+        }
+        // turn the reduced suffixes back into text positions
+        let mut j = 0;
+        for i in 1..n {
+            if types.is_lms(i) {
+                s1[j].set(i as u32);
+                j += 1;
+            }
+        }
+        for x in sa1.iter_mut() {
+            let pos = s1[x.index()].get();
+            x.set(pos);
+        }
+    }
+
+    // place the sorted LMS suffixes at the ends of their buckets, and induce
+    for x in sa[n1..].iter_mut() {
+        x.set(empty);
+    }
+    let mut bkt = buckets(s, k, true);
+    for i in (0..n1).rev() {
+        let j = sa[i].get();
+        sa[i].set(empty);
+        let c = s[j as usize].index();
+        bkt[c] -= 1;
+        sa[bkt[c] as usize].set(j);
+    }
+    induce(s, sa, &types, k);
+}
+
+/// Computes the suffix array of 'input' into 'sa', which has the same size
+/// and an integer type able to hold it
+pub fn suffix_array<S: NumCast>(input: &[u8], sa: &mut [S]) {
+    assert_eq!(input.len(), sa.len());
+    assert!(input.len() < u32::MAX as usize && <S as NumCast>::from(input.len()).is_some(),
+        "input too large for SA-IS");
+    sais(input, sa, 0x100);
+}
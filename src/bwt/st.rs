@@ -0,0 +1,442 @@
+/*!
+
+ST (Schindler Transform) of a bounded order, forward and backward transformation
+
+The ST of order k sorts the rotations of a block by their first k symbols
+only, keeping the rotations of equal contexts in their original order, and
+outputs the symbol preceding every rotation, like the BWT does. Sorting is
+done by k counting sort passes, so encoding takes O(k N) time whatever the
+input, at the cost of a slightly worse compression than the full BWT.
+
+Decoding first rebuilds the boundaries of the context groups: the rows of a
+context of order j+1 are the predecessors of the rows of its last j symbols.
+Walking the block backwards from the origin then visits the rotations of
+every group in decreasing positions, which picks the row of each of them.
+
+# Example
+
+```rust
+use compress::bwt::st;
+
+let (encoded, origin) = st::encode_simple(b"abracadabra", 3);
+assert_eq!(st::decode_simple(&encoded, 3, origin), b"abracadabra");
+```
+
+# Related links
+
+* http://www.compressconsult.com/st/ - the Schindler Transform by Michael
+  Schindler
+
+*/
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use super::super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use super::super::ReadExact;
+use super::{Radix, Symbol, ALPHABET_SIZE};
+
+/// Highest order supported
+pub const MAX_ORDER: usize = 8;
+
+/// Header of the stream, followed by the order
+const MAGIC: [u8; 3] = *b"ST\0";
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Transform an input block into the output slice, of the same size, sorting
+/// the rotations by their first 'order' symbols. Returns the index of the
+/// original string in the output matrix.
+pub fn encode(input: &[Symbol], order: usize, output: &mut [Symbol]) -> usize {
+    assert!((1..=MAX_ORDER).contains(&order), "unsupported ST order {}", order);
+    let n = input.len();
+    assert_eq!(n, output.len());
+    if n == 0 {
+        return 0
+    }
+    // least significant symbol first, every pass being stable
+    let mut rotations: Vec<u32> = (0..(n as u32)).collect();
+    let mut sorted = vec![0u32; n];
+    for j in (0..order).rev() {
+        let symbol = |p: u32| input[(p as usize + j) % n];
+        let mut radix = Radix::new();
+        for &p in rotations.iter() {
+            radix.freq[symbol(p) as usize] += 1;
+        }
+        radix.accumulate();
+        for &p in rotations.iter() {
+            sorted[radix.place(symbol(p))] = p;
+        }
+        ::std::mem::swap(&mut rotations, &mut sorted);
+    }
+    let mut origin = 0;
+    for (i, (out, &p)) in output.iter_mut().zip(rotations.iter()).enumerate() {
+        if p == 0 {
+            origin = i;
+        }
+        *out = input[(p as usize + n - 1) % n];
+    }
+    origin
+}
+
+/// Invert the transform of order 'order' from the input into the output
+/// slice, of the same size, given the origin returned by `encode`. Fails if
+/// the input is not the output of the transform.
+/// Run time: O(order N), memory: 3N words
+pub fn decode(input: &[Symbol], order: usize, origin: usize, output: &mut [Symbol]) -> io::Result<()> {
+    assert!((1..=MAX_ORDER).contains(&order), "unsupported ST order {}", order);
+    let n = input.len();
+    assert_eq!(n, output.len());
+    if n == 0 {
+        return Ok(())
+    }
+    if origin >= n {
+        return error("invalid ST origin");
+    }
+
+    let mut radix = Radix::new();
+    radix.gather(input);
+    radix.accumulate();
+    let first = radix.freq;
+
+    // the first row of the context group of every row, starting from the
+    // order 1, and of the group of the predecessor of every row
+    let mut group = vec![0u32; n];
+    for c in 0..ALPHABET_SIZE {
+        for g in group[first[c]..first[c + 1]].iter_mut() {
+            *g = first[c] as u32;
+        }
+    }
+    let mut pred: Vec<u32> = input.iter().map(|&c| first[c as usize] as u32).collect();
+    let mut next_group = vec![0u32; n];
+    for _ in 1..order {
+        step(input, &first, &group, &mut pred, &mut next_group);
+        ::std::mem::swap(&mut group, &mut next_group);
+    }
+    drop(next_group);
+
+    // the rows of a group are taken from the last one, as positions decrease
+    let mut ends = group;
+    for (i, end) in ends.iter_mut().enumerate() {
+        *end = i as u32;
+    }
+    for &g in pred.iter() {
+        ends[g as usize] += 1;
+    }
+    let mut r = origin;
+    for out in output.iter_mut().rev() {
+        *out = input[r];
+        let g = pred[r] as usize;
+        // a valid block never takes more rows from a group than it has
+        if ends[g] as usize <= g {
+            return error("corrupted ST block");
+        }
+        ends[g] -= 1;
+        r = ends[g] as usize;
+        if r >= n {
+            return error("corrupted ST block");
+        }
+    }
+    Ok(())
+}
+
+/// Computes the groups of the next order, along with the group of the
+/// predecessor of every row for the current one
+fn step(input: &[Symbol], first: &[usize], group: &[u32], pred: &mut [u32], next_group: &mut [u32]) {
+    let mut fill = [0u32; ALPHABET_SIZE];
+    let mut start = [0u32; ALPHABET_SIZE];
+    let mut last = [!0u32; ALPHABET_SIZE];
+    for c in 0..ALPHABET_SIZE {
+        fill[c] = first[c] as u32;
+    }
+    // the groups of a given last symbol come in order as rows increase
+    for (r, &c) in input.iter().enumerate() {
+        let c = c as usize;
+        if group[r] != last[c] {
+            last[c] = group[r];
+            start[c] = fill[c];
+        }
+        pred[r] = start[c];
+        next_group[fill[c] as usize] = start[c];
+        fill[c] += 1;
+    }
+}
+
+/// Transform an input block, allocating the output. Returns the output along
+/// with the index of the original string in the output matrix.
+pub fn encode_simple(input: &[Symbol], order: usize) -> (Vec<Symbol>, usize) {
+    let mut output = vec![0; input.len()];
+    let origin = encode(input, order, &mut output);
+    (output, origin)
+}
+
+/// Invert the transform of a block, allocating the output
+pub fn decode_simple(input: &[Symbol], order: usize, origin: usize) -> Vec<Symbol> {
+    let mut output = vec![0; input.len()];
+    decode(input, order, origin, &mut output).unwrap();
+    output
+}
+
+
+/// This structure is used to decode a stream of ST blocks. This wraps an
+/// internal reader which is read from when this decoder's read method is
+/// called.
+pub struct Decoder<R> {
+    /// The internally wrapped reader. This is exposed so it may be moved out
+    /// of. Note that if data is read from the reader while decoding is in
+    /// progress the output stream will get corrupted.
+    pub r: R,
+    start: usize,
+    temp: Vec<u8>,
+    output: Vec<u8>,
+    header: bool,
+    order: usize,
+    max_block_size: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream. The
+    /// order is read from the stream.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            start: 0,
+            temp: Vec::new(),
+            output: Vec::new(),
+            header: false,
+            order: 0,
+            max_block_size: 0,
+        }
+    }
+
+    /// Resets this decoder back to its initial state. Note that the underlying
+    /// stream is not seeked on or has any alterations performed on it.
+    pub fn reset(&mut self) {
+        self.header = false;
+        self.start = 0;
+        self.output.clear();
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut magic = [0u8; 3];
+        try!(self.r.read_exact(&mut magic));
+        if magic != MAGIC {
+            return error("invalid ST header");
+        }
+        self.order = try!(self.r.read_u8()) as usize;
+        if !(1..=MAX_ORDER).contains(&self.order) {
+            return error("unsupported ST order");
+        }
+        self.max_block_size = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        Ok(())
+    }
+
+    fn decode_block(&mut self) -> io::Result<bool> {
+        let n = match self.r.read_u32::<LittleEndian>() {
+            Ok(n) => n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if n > self.max_block_size {
+            return error("ST block larger than the stream block size");
+        }
+        self.temp.clear();
+        try!(self.r.push_exactly(n as u64, &mut self.temp));
+        let origin = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        if origin >= cmp::max(n, 1) {
+            return error("invalid ST origin");
+        }
+        self.output.resize(n, 0);
+        try!(decode(&self.temp, self.order, origin, &mut self.output));
+        self.start = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        if !self.header {
+            try!(self.read_header());
+            self.header = true;
+        }
+        let mut amt = 0;
+        while amt < dst.len() {
+            if self.output.len() == self.start && !try!(self.decode_block()) {
+                break
+            }
+            let n = cmp::min(dst.len() - amt, self.output.len() - self.start);
+            dst[amt..(amt + n)].copy_from_slice(&self.output[self.start..(self.start + n)]);
+            self.start += n;
+            amt += n;
+        }
+        Ok(amt)
+    }
+}
+
+
+/// This structure is used to compress a stream of bytes using the ST.
+/// This is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<u8>,
+    output: Vec<u8>,
+    wrote_header: bool,
+    order: usize,
+    block_size: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder of the given order, from 1 to `MAX_ORDER`, which
+    /// will have its output written to the given output stream. The output
+    /// stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, order: usize, block_size: usize) -> Encoder<W> {
+        assert!((1..=MAX_ORDER).contains(&order), "unsupported ST order {}", order);
+        assert!(block_size > 0, "empty ST blocks");
+        Encoder {
+            w,
+            buf: Vec::new(),
+            output: Vec::new(),
+            wrote_header: false,
+            order,
+            block_size,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.wrote_header {
+            return Ok(())
+        }
+        self.wrote_header = true;
+        try!(self.w.write_all(&MAGIC));
+        try!(self.w.write_u8(self.order as u8));
+        self.w.write_u32::<LittleEndian>(self.block_size as u32)
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let n = self.buf.len();
+        try!(self.w.write_u32::<LittleEndian>(n as u32));
+        self.output.resize(n, 0);
+        let origin = encode(&self.buf, self.order, &mut self.output);
+        try!(self.w.write_all(&self.output));
+        try!(self.w.write_u32::<LittleEndian>(origin as u32));
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+            if self.buf.len() == self.block_size {
+                try!(self.encode_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            try!(self.encode_block());
+        }
+        self.w.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::super::super::rand::{random, Rng, thread_rng};
+    use super::{decode_simple, encode_simple, Decoder, Encoder, MAX_ORDER};
+
+    /// Sorts the rotations by their contexts, keeping their order on ties
+    fn naive(input: &[u8], order: usize) -> (Vec<u8>, usize) {
+        let n = input.len();
+        let mut rotations: Vec<usize> = (0..n).collect();
+        rotations.sort_by_key(|&p| (0..order).map(|j| input[(p + j) % n]).collect::<Vec<u8>>());
+        let output = rotations.iter().map(|&p| input[(p + n - 1) % n]).collect();
+        (output, rotations.iter().position(|&p| p == 0).unwrap_or(0))
+    }
+
+    #[test]
+    fn transforms() {
+        let mut rng = thread_rng();
+        let mut inputs: Vec<Vec<u8>> = vec![b"".to_vec(), b"a".to_vec(), b"abracadabra".to_vec(),
+                                            vec![0; 50], include_bytes!("../data/test.txt").to_vec()];
+        for _ in 0..100 {
+            let len = random::<usize>() % 300;
+            let alphabet = 1 + random::<u8>() % 4;
+            inputs.push((0..len).map(|_| rng.gen_range(0, alphabet)).collect());
+        }
+        for input in inputs.iter() {
+            for order in 1..(MAX_ORDER + 1) {
+                let (encoded, origin) = encode_simple(input, order);
+                assert!((encoded.clone(), origin) == naive(input, order));
+                assert!(&decode_simple(&encoded, order, origin) == input);
+            }
+        }
+    }
+
+    fn roundtrip(bytes: &[u8], order: usize, block_size: usize) {
+        let mut e = Encoder::new(Vec::new(), order, block_size);
+        e.write_all(bytes).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut d = Decoder::new(&encoded[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == bytes);
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"", 3, 100);
+        roundtrip(b"test", 4, 100);
+        roundtrip(include_bytes!("../data/test.txt"), 5, 1 << 10);
+        roundtrip(&include_bytes!("../data/test.large")[..(1 << 20)], 8, 1 << 18);
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut e = Encoder::new(Vec::new(), 3, 100);
+        e.write_all(b"test").unwrap();
+        let mut encoded = e.finish().0;
+        encoded[3] = 9;
+        let mut d = Decoder::new(&encoded[..]);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut e = Encoder::new(Vec::new(), 3, 100);
+        e.write_all(b"abracadabra abracadabra").unwrap();
+        let encoded = e.finish().0;
+        let mut wrong = encoded.clone();
+        wrong[14] = 0;
+        let mut d = Decoder::new(&wrong[..]);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+        // no single byte corruption makes the decoder panic
+        for i in 0..encoded.len() {
+            for &x in [0u8, 1, b' ', b'a', b'r', 0xff].iter() {
+                let mut wrong = encoded.clone();
+                wrong[i] = x;
+                let _ = Decoder::new(&wrong[..]).read_to_end(&mut Vec::new());
+            }
+        }
+    }
+}
@@ -0,0 +1,363 @@
+/*!
+
+BWT + DC + arithmetic coding compressor, in the style of ybs
+
+Every block of the input goes through the BWT, the distance coding of its
+output, and the arithmetic coding of the distances under the adaptive model
+of `dc::DistanceModel`, which predicts them from the symbol and its last rank.
+
+The stream starts with the magic bytes `YBS`, the format version and the
+largest block size. Each block is made of its size, its BWT origin and the
+CRC-32 of its content, followed by the arithmetic code of its initial symbol
+positions and distances. The end of the stream is the end of the last block.
+
+# Example
+
+```rust
+use std::io::{Read, Write};
+use compress::bwt::ybs;
+
+// Encode some text
+let text = b"some text, some more text";
+let mut e = ybs::Encoder::new(Vec::new(), ybs::DEFAULT_BLOCK_SIZE);
+e.write_all(text).unwrap();
+let (encoded, result) = e.finish();
+result.unwrap();
+
+// Decode the encoded text
+let mut d = ybs::Decoder::new(&encoded[..]);
+let mut decoded = Vec::new();
+d.read_to_end(&mut decoded).unwrap();
+assert_eq!(&decoded[..], &text[..]);
+```
+
+# Credit
+
+This is an original implementation, after the ybs compressor of Vadim Yoockin.
+
+*/
+
+use std::{cmp, io};
+use std::io::{Read, Write};
+
+use super::super::byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
+use super::super::entropy::ari;
+use super::super::checksum::crc32;
+use super::{alloc_block, check_crc, decode_interleaved, error, Symbol, SaIs, SuffixSorter,
+            TransformIterator};
+use super::dc::{self, DistanceModel, TOTAL_SYMBOLS};
+use super::mtf::MTF;
+
+/// Magic bytes starting a stream
+pub const MAGIC: &[u8; 3] = b"YBS";
+/// Current version of the format
+const VERSION: u8 = 1;
+/// Block size used by default
+pub const DEFAULT_BLOCK_SIZE: usize = 4 << 20;
+/// Largest block size allowed by the format, which bounds the memory of the
+/// decoder: the size of a block is trusted before its CRC can be checked
+pub const MAX_BLOCK_SIZE: usize = 1 << 26;
+
+
+/// This structure is used to decode a ybs stream. This is a wrapper around an
+/// internal reader which bytes will be read from.
+pub struct Decoder<R> {
+    r: R,
+    /// largest block size, once the header is read
+    block_size: Option<usize>,
+    transformed: Vec<Symbol>,
+    table: Vec<u32>,
+    output: Vec<Symbol>,
+    start: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new decoder which will read data from the given stream
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            block_size: None,
+            transformed: Vec::new(),
+            table: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Returns the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    fn read_header(&mut self) -> io::Result<usize> {
+        let mut magic = [0; 3];
+        try!(self.r.read_exact(&mut magic));
+        if &magic != MAGIC {
+            return error("not a ybs stream")
+        }
+        if try!(self.r.read_u8()) != VERSION {
+            return error("unsupported ybs stream version")
+        }
+        let block_size = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        if block_size > MAX_BLOCK_SIZE {
+            return error("ybs block size too large")
+        }
+        Ok(block_size)
+    }
+
+    fn decode_block(&mut self, block_size: usize) -> io::Result<bool> {
+        let n = match self.r.read_u32::<LittleEndian>() {
+            Ok(n) => n as usize,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false), // EOF
+            Err(e) => return Err(e),
+        };
+        let origin = try!(self.r.read_u32::<LittleEndian>()) as usize;
+        let crc = try!(self.r.read_u32::<LittleEndian>());
+        if n == 0 || n > block_size {
+            return error("invalid ybs block size")
+        }
+        if origin >= n {
+            return error("invalid ybs block origin")
+        }
+
+        {
+            let mut model = DistanceModel::new();
+            let mut coder = ari::Decoder::new(&mut self.r);
+            let mut init = [0; TOTAL_SYMBOLS];
+            for pos in init.iter_mut() {
+                *pos = try!(model.decode_init(&mut coder, n));
+            }
+            // a block starts with a symbol, and takes memory only then
+            if !init.contains(&0) {
+                return error("ybs block has no symbol at its start")
+            }
+            try!(alloc_block(&mut self.transformed, n));
+            try!(dc::decode(init, &mut self.transformed[..], &mut MTF::new(), |ctx| {
+                model.decode(&mut coder, &ctx)
+            }));
+            let (_, result) = coder.finish();
+            try!(result);
+        }
+
+        self.output.truncate(0);
+        try!(alloc_block(&mut self.table, n));
+        decode_interleaved(&self.transformed[..], origin, &mut self.table[..], &mut self.output);
+        try!(check_crc(&self.output, crc));
+
+        self.start = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let block_size = match self.block_size {
+            Some(size) => size,
+            None => {
+                let size = try!(self.read_header());
+                self.block_size = Some(size);
+                size
+            }
+        };
+        let mut amt = 0;
+        while amt < dst.len() {
+            if self.output.len() == self.start && !try!(self.decode_block(block_size)) {
+                break
+            }
+            let n = cmp::min(dst.len() - amt, self.output.len() - self.start);
+            dst[amt..amt + n].copy_from_slice(&self.output[self.start..self.start + n]);
+            self.start += n;
+            amt += n;
+        }
+        Ok(amt)
+    }
+}
+
+
+/// This structure is used to compress a stream of bytes into the ybs format.
+/// This is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: W,
+    buf: Vec<Symbol>,
+    suf: Vec<u32>,
+    transformed: Vec<Symbol>,
+    distances: Vec<u32>,
+    wrote_header: bool,
+    block_size: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder which will have its output written to the given
+    /// output stream, transforming the input by blocks of 'block_size' bytes.
+    /// Larger blocks compress better, at the cost of memory, about 13 bytes
+    /// per input byte when encoding and 6 when decoding.
+    /// The output stream can be re-acquired by calling `finish()`
+    pub fn new(w: W, block_size: usize) -> Encoder<W> {
+        assert!(block_size > 0 && block_size <= MAX_BLOCK_SIZE, "invalid ybs block size");
+        Encoder {
+            w,
+            buf: Vec::new(),
+            suf: Vec::new(),
+            transformed: Vec::new(),
+            distances: Vec::new(),
+            wrote_header: false,
+            block_size,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.wrote_header {
+            try!(self.w.write_all(MAGIC));
+            try!(self.w.write_u8(VERSION));
+            try!(self.w.write_u32::<LittleEndian>(self.block_size as u32));
+            self.wrote_header = true;
+        }
+        Ok(())
+    }
+
+    fn encode_block(&mut self) -> io::Result<()> {
+        let n = self.buf.len();
+        self.suf.truncate(0);
+        self.suf.resize(n, 0);
+        SaIs.sort(&self.buf[..], &mut self.suf[..]);
+        let origin = {
+            let mut iter = TransformIterator::new(&self.buf[..], &self.suf[..]);
+            self.transformed.truncate(0);
+            self.transformed.extend(iter.by_ref());
+            iter.get_origin()
+        };
+        let mut crc = crc32::State::new();
+        crc.feed(&self.buf);
+
+        try!(self.w.write_u32::<LittleEndian>(n as u32));
+        try!(self.w.write_u32::<LittleEndian>(origin as u32));
+        try!(self.w.write_u32::<LittleEndian>(crc.result()));
+
+        self.distances.truncate(0);
+        self.distances.resize(n, 0);
+        let iter = dc::encode(&self.transformed[..], &mut self.distances[..], &mut MTF::new());
+        let mut model = DistanceModel::new();
+        let mut coder = ari::Encoder::new(&mut self.w);
+        for &pos in iter.get_init().iter() {
+            try!(model.encode_init(&mut coder, pos, n));
+        }
+        for (dist, ctx) in iter {
+            try!(model.encode(&mut coder, dist as usize, &ctx));
+        }
+        let (_, result) = coder.finish();
+        try!(result);
+        self.buf.truncate(0);
+
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header().and_then(|_| self.flush());
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+
+        let len = buf.len();
+        while !buf.is_empty() {
+            let amt = cmp::min(self.block_size - self.buf.len(), buf.len());
+            self.buf.extend_from_slice(&buf[..amt]);
+
+            if self.buf.len() == self.block_size {
+                try!(self.encode_block());
+            }
+            buf = &buf[amt..];
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let ret = if !self.buf.is_empty() {
+            self.encode_block()
+        } else {
+            Ok(())
+        };
+        ret.and(self.w.flush())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::{Decoder, Encoder};
+
+    fn encode(bytes: &[u8], block_size: usize) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new(), block_size);
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        encoded
+    }
+
+    fn decode(encoded: &[u8]) -> ::std::io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        try!(Decoder::new(encoded).read_to_end(&mut decoded));
+        Ok(decoded)
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        let text = include_bytes!("../data/test.txt");
+        for &(bytes, block_size) in [(&b""[..], 10), (b"a", 10), (b"aaaaaaaaaaaa", 5),
+                                     (b"abracadabra", 4), (&text[..], 1000), (&text[..], 1<<20)].iter() {
+            assert_eq!(&decode(&encode(bytes, block_size)).unwrap()[..], bytes);
+        }
+    }
+
+    #[test]
+    #[cfg(feature="bzip2")]
+    fn ratio() {
+        let input = &include_bytes!("../data/test.large")[..(1 << 18)];
+        let mut e = ::bzip2::Encoder::new(Vec::new());
+        e.write_all(input).unwrap();
+        let (bzip2, err) = e.finish();
+        err.unwrap();
+        assert!(encode(input, 1 << 20).len() < bzip2.len());
+    }
+
+    #[test]
+    fn hostile_header() {
+        // a block of 2 GB, in a stream of blocks of 2 GB
+        let mut stream = b"YBS\x01".to_vec();
+        stream.extend_from_slice(&[0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 0x7f, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(decode(&stream).is_err());
+        // a block as large as allowed, with nothing to decode it from
+        let mut stream = b"YBS\x01".to_vec();
+        stream.extend_from_slice(&[0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(decode(&stream).is_err());
+    }
+
+    #[test]
+    fn corrupted() {
+        let text = include_bytes!("../data/test.txt");
+        let encoded = encode(text, 1<<10);
+        assert!(decode(&encoded[1..]).is_err());
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        // a block with another origin decodes to the wrong content
+        let mut wrong = encoded.clone();
+        wrong[12] ^= 1;
+        assert!(decode(&wrong).is_err());
+        // so does a flipped payload, caught by the arithmetic decoder or the CRC
+        let mut wrong = encoded.clone();
+        wrong[24] ^= 0x10;
+        assert!(decode(&wrong).is_err());
+        let encoded = encode(&text[..1<<10], 1<<10);
+        for i in 0..encoded.len() {
+            let mut wrong = encoded.clone();
+            wrong[i] ^= 1 << (i % 8);
+            let _ = decode(&wrong);
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//! BZIP2 encoder
+//!
+//! The input is run-length encoded (RLE1) into blocks of the size given by
+//! the level, whose rotations are sorted by the BWT. The MTF ranks of the
+//! result, with the zero runs in bijective base 2, are split in groups of 50
+//! symbols, and each group is coded with the best of up to 6 Huffman tables.
+//! The tables and the assignment of the groups to them (the selectors) are
+//! refined together over a few iterations, like bzip2 does.
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+
+use bwt;
+use bwt::mtf::MTF;
+use checksum::crc::{Crc, CRC_32_BZIP2};
+
+use super::{BLOCK_MAGIC, END_MAGIC, GROUP_SIZE, LEVEL_BLOCK_SIZE, MAX_GROUPS,
+            RNUMS, RUNA, RUNB, STREAM_MAGIC};
+
+/// Longest code the encoder makes, leaving some room to the decoders
+const MAX_CODE_LEN: usize = 17;
+/// Room left at the end of the blocks for the last run
+const BLOCK_SLACK: usize = 19;
+/// Rounds of the table optimisation
+const ITERATIONS: usize = 4;
+/// Code lengths of the initial tables, for the symbols in and out of their range
+const LESSER_COST: u8 = 0;
+const GREATER_COST: u8 = 15;
+/// Longest run of equal bytes RLE1 writes at once
+const MAX_RUN: usize = 4 + 255;
+
+/// Writes bits from the most significant one of each byte
+struct BitWriter<W> {
+    w: W,
+    buf: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    fn new(w: W) -> BitWriter<W> {
+        BitWriter {
+            w,
+            buf: Vec::new(),
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, value: u32, n: u32) {
+        debug_assert!(n <= 32);
+        self.bits = (self.bits << n) | value as u64;
+        self.count += n;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.buf.push((self.bits >> self.count) as u8);
+        }
+    }
+
+    fn bit(&mut self, value: bool) {
+        self.bits(value as u32, 1);
+    }
+
+    /// Pads the last byte with zeros
+    fn align(&mut self) {
+        if self.count != 0 {
+            let pad = 8 - self.count;
+            self.bits(0, pad);
+        }
+    }
+
+    /// Writes out the complete bytes
+    fn flush_bytes(&mut self) -> io::Result<()> {
+        let result = self.w.write_all(&self.buf);
+        self.buf.clear();
+        result
+    }
+}
+
+/// Compute the code lengths of a huffman code limited to 'max_len', for all
+/// the symbols. The frequencies are flattened until the code fits.
+fn build_lengths(freqs: &[u32], max_len: usize, lengths: &mut [u8]) {
+    let n = freqs.len();
+    let mut weights: Vec<u32> = freqs.iter().map(|&f| cmp::max(f, 1)).collect();
+    let mut parent = vec![0usize; 2 * n - 1];
+    loop {
+        // the heap holds (weight, node) pairs, lightest first
+        let mut heap: BinaryHeap<cmp::Reverse<(u64, usize)>> = weights.iter().enumerate()
+            .map(|(i, &w)| cmp::Reverse((w as u64, i)))
+            .collect();
+        let mut next = n;
+        while heap.len() > 1 {
+            let cmp::Reverse((wa, a)) = heap.pop().unwrap();
+            let cmp::Reverse((wb, b)) = heap.pop().unwrap();
+            parent[a] = next;
+            parent[b] = next;
+            heap.push(cmp::Reverse((wa + wb, next)));
+            next += 1;
+        }
+        // parents always come after children
+        let mut depth = vec![0usize; 2 * n - 1];
+        for i in (0..(2 * n - 2)).rev() {
+            depth[i] = depth[parent[i]] + 1;
+        }
+        if depth[..n].iter().all(|&d| d <= max_len) {
+            for (len, &d) in lengths.iter_mut().zip(depth.iter()) {
+                *len = d as u8;
+            }
+            return
+        }
+        for w in weights.iter_mut() {
+            *w = 1 + *w / 2;
+        }
+    }
+}
+
+/// Compute the canonical codes for the given lengths
+fn build_codes(lengths: &[u8], codes: &mut [u32]) {
+    let mut code = 0;
+    for len in 1..=MAX_CODE_LEN as u8 {
+        for (&l, c) in lengths.iter().zip(codes.iter_mut()) {
+            if l == len {
+                *c = code;
+                code += 1;
+            }
+        }
+        code <<= 1;
+    }
+}
+
+/// Number of tables used for a given count of symbols
+fn table_count(symbols: usize) -> usize {
+    match symbols {
+        0..=199 => 2,
+        200..=599 => 3,
+        600..=1199 => 4,
+        1200..=2399 => 5,
+        _ => MAX_GROUPS,
+    }
+}
+
+/// This structure is used to compress a stream of bytes into the bzip2
+/// format. This is a wrapper around an internal writer which bytes will be
+/// written to.
+pub struct Encoder<W> {
+    w: BitWriter<W>,
+    crc: Crc,
+    level: u32,
+    wrote_header: bool,
+    /// RLE1 encoded input of the current block
+    block: Vec<u8>,
+    block_crc: u64,
+    combined_crc: u32,
+    /// pending run of equal bytes
+    run_byte: u8,
+    run_len: usize,
+    /// whether blocks are randomised, for testing the decoder
+    randomised: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder with the default compression level (9), which
+    /// will have its output written to the given output stream. The output
+    /// stream can be re-acquired by calling `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, 9)
+    }
+
+    /// Creates a new encoder with the given compression level, from 1 to 9,
+    /// which selects blocks of 100k to 900k bytes. Larger blocks compress
+    /// better, but need more memory to compress and decompress.
+    pub fn with_level(w: W, level: u32) -> Encoder<W> {
+        assert!((1..=9).contains(&level), "invalid bzip2 compression level");
+        let crc = Crc::new(CRC_32_BZIP2);
+        let block_crc = crc.checksum(&[]);
+        Encoder {
+            w: BitWriter::new(w),
+            crc,
+            level,
+            wrote_header: false,
+            block: Vec::new(),
+            block_crc,
+            combined_crc: 0,
+            run_byte: 0,
+            run_len: 0,
+            randomised: false,
+        }
+    }
+
+    fn max_block_size(&self) -> usize {
+        self.level as usize * LEVEL_BLOCK_SIZE - BLOCK_SLACK
+    }
+
+    fn write_header(&mut self) {
+        if !self.wrote_header {
+            for &b in STREAM_MAGIC.iter() {
+                self.w.bits(b as u32, 8);
+            }
+            self.w.bits((b'0' + self.level as u8) as u32, 8);
+            self.wrote_header = true;
+        }
+    }
+
+    /// Adds the pending run to the block, four equal bytes being followed by
+    /// the count of the next ones
+    fn flush_run(&mut self) {
+        let len = self.run_len;
+        if len == 0 {
+            return
+        }
+        let bytes = [self.run_byte; MAX_RUN];
+        self.block_crc = self.crc.update(self.block_crc, &bytes[..len]);
+        if len < 4 {
+            self.block.extend_from_slice(&bytes[..len]);
+        } else {
+            self.block.extend_from_slice(&bytes[..4]);
+            self.block.push((len - 4) as u8);
+        }
+        self.run_len = 0;
+    }
+
+    /// Compresses the current block, if any
+    fn compress_block(&mut self) -> io::Result<()> {
+        self.flush_run();
+        if self.block.is_empty() {
+            return Ok(())
+        }
+        self.write_header();
+        if self.randomised {
+            randomise(&mut self.block);
+        }
+        let crc = self.block_crc as u32;
+        write_block(&mut self.w, &self.block, crc, self.randomised);
+        self.combined_crc = self.combined_crc.rotate_left(1) ^ crc;
+        self.block.clear();
+        self.block_crc = self.crc.checksum(&[]);
+        self.w.flush_bytes()
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let mut result = self.compress_block();
+        self.write_header();
+        self.w.bits((END_MAGIC >> 24) as u32, 24);
+        self.w.bits(END_MAGIC as u32 & 0xff_ffff, 24);
+        self.w.bits(self.combined_crc, 32);
+        self.w.align();
+        result = result.and(self.w.flush_bytes());
+        let mut w = self.w.w;
+        result = result.and(w.flush());
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let max_block_size = self.max_block_size();
+        for &b in buf.iter() {
+            if self.run_len != 0 && b == self.run_byte && self.run_len < MAX_RUN {
+                self.run_len += 1;
+                continue
+            }
+            self.flush_run();
+            if self.block.len() >= max_block_size {
+                try!(self.compress_block());
+            }
+            self.run_byte = b;
+            self.run_len = 1;
+        }
+        Ok(buf.len())
+    }
+
+    /// Compress all the pending data as a block, which makes everything
+    /// written so far decodable but for the last few bits
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.compress_block());
+        self.w.w.flush()
+    }
+}
+
+/// Flips the bytes old bzip2 versions flipped to break repetitions
+fn randomise(block: &mut [u8]) {
+    let mut rand_left = 0;
+    let mut rand_index = 0;
+    for b in block.iter_mut() {
+        if rand_left == 0 {
+            rand_left = RNUMS[rand_index];
+            rand_index = (rand_index + 1) % RNUMS.len();
+        }
+        rand_left -= 1;
+        if rand_left == 1 {
+            *b ^= 1;
+        }
+    }
+}
+
+/// Writes a block, given its CRC
+fn write_block<W: Write>(w: &mut BitWriter<W>, block: &[u8], crc: u32, randomised: bool) {
+    let mut transformed = Vec::with_capacity(block.len());
+    let origin = bwt::encode_rotations(block, &mut transformed);
+
+    // the bytes present in the block, and their index among these
+    let mut used = [false; 256];
+    for &b in transformed.iter() {
+        used[b as usize] = true;
+    }
+    let mut indices = [0u8; 256];
+    let mut in_use = 0;
+    for (index, _) in indices.iter_mut().zip(used.iter()).filter(|&(_, &u)| u) {
+        *index = in_use as u8;
+        in_use += 1;
+    }
+    let alphabet_size = in_use + 2;
+    let end_of_block = (in_use + 1) as u16;
+
+    // MTF ranks, with the zero runs in bijective base 2
+    let mut symbols = Vec::with_capacity(transformed.len() + 1);
+    let mut mtf = MTF::new();
+    mtf.reset_alphabetical();
+    let mut zeros = 0usize;
+    let push_zeros = |symbols: &mut Vec<u16>, mut zeros: usize| {
+        while zeros != 0 {
+            zeros -= 1;
+            symbols.push(if zeros & 1 != 0 { RUNB } else { RUNA });
+            zeros >>= 1;
+        }
+    };
+    for &b in transformed.iter() {
+        let rank = mtf.encode(indices[b as usize]);
+        if rank == 0 {
+            zeros += 1;
+        } else {
+            push_zeros(&mut symbols, zeros);
+            zeros = 0;
+            symbols.push(rank as u16 + 1);
+        }
+    }
+    push_zeros(&mut symbols, zeros);
+    symbols.push(end_of_block);
+
+    let groups = table_count(symbols.len());
+    let (lengths, selectors) = build_tables(&symbols, alphabet_size, groups);
+
+    w.bits((BLOCK_MAGIC >> 24) as u32, 24);
+    w.bits(BLOCK_MAGIC as u32 & 0xff_ffff, 24);
+    w.bits(crc, 32);
+    w.bit(randomised);
+    w.bits(origin as u32, 24);
+
+    let ranges = (0..16).fold(0, |acc, i| {
+        (acc << 1) | used[(i * 16)..(i * 16 + 16)].iter().any(|&u| u) as u32
+    });
+    w.bits(ranges, 16);
+    for range in used.chunks(16).filter(|range| range.iter().any(|&u| u)) {
+        w.bits(range.iter().fold(0, |acc, &u| (acc << 1) | u as u32), 16);
+    }
+
+    w.bits(groups as u32, 3);
+    w.bits(selectors.len() as u32, 15);
+    let mut mtf = MTF::new();
+    mtf.reset_alphabetical();
+    for &sel in selectors.iter() {
+        let rank = mtf.encode(sel as u8);
+        for _ in 0..rank {
+            w.bit(true);
+        }
+        w.bit(false);
+    }
+
+    // code lengths, as differences from the previous one
+    for table in lengths.iter() {
+        let mut current = table[0];
+        w.bits(current as u32, 5);
+        for &len in table.iter() {
+            while current < len {
+                w.bits(2, 2);
+                current += 1;
+            }
+            while current > len {
+                w.bits(3, 2);
+                current -= 1;
+            }
+            w.bit(false);
+        }
+    }
+
+    let codes: Vec<Vec<u32>> = lengths.iter().map(|table| {
+        let mut codes = vec![0; alphabet_size];
+        build_codes(table, &mut codes);
+        codes
+    }).collect();
+    for (group, &sel) in symbols.chunks(GROUP_SIZE).zip(selectors.iter()) {
+        let (lengths, codes) = (&lengths[sel], &codes[sel]);
+        for &sym in group.iter() {
+            w.bits(codes[sym as usize], lengths[sym as usize] as u32);
+        }
+    }
+}
+
+/// Code lengths of the tables and selectors of the groups
+type Tables = (Vec<Vec<u8>>, Vec<usize>);
+
+/// Chooses the tables, and the one coding each group of symbols
+fn build_tables(symbols: &[u16], alphabet_size: usize, groups: usize) -> Tables {
+    let mut freqs = vec![0u32; alphabet_size];
+    for &sym in symbols.iter() {
+        freqs[sym as usize] += 1;
+    }
+
+    // start with tables covering ranges of symbols of similar total
+    // frequencies, the way bzip2 does
+    let mut lengths = vec![vec![GREATER_COST; alphabet_size]; groups];
+    let mut remaining = symbols.len() as u32;
+    let mut low = 0;
+    for part in (1..=groups).rev() {
+        let target = remaining / part as u32;
+        let mut high = low;
+        let mut acc = 0;
+        while acc < target && high < alphabet_size {
+            acc += freqs[high];
+            high += 1;
+        }
+        if high > low + 1 && part != groups && part != 1 && (groups - part) % 2 == 1 {
+            high -= 1;
+            acc -= freqs[high];
+        }
+        for len in lengths[part - 1][low..high].iter_mut() {
+            *len = LESSER_COST;
+        }
+        low = high;
+        remaining -= acc;
+    }
+
+    let mut selectors = Vec::with_capacity(symbols.len().div_ceil(GROUP_SIZE));
+    for _ in 0..ITERATIONS {
+        selectors.clear();
+        let mut table_freqs = vec![vec![0u32; alphabet_size]; groups];
+        for group in symbols.chunks(GROUP_SIZE) {
+            let best = (0..groups).min_by_key(|&t| {
+                group.iter().map(|&sym| lengths[t][sym as usize] as u32).sum::<u32>()
+            }).unwrap();
+            for &sym in group.iter() {
+                table_freqs[best][sym as usize] += 1;
+            }
+            selectors.push(best);
+        }
+        for (table, freqs) in lengths.iter_mut().zip(table_freqs.iter()) {
+            build_lengths(freqs, MAX_CODE_LEN, table);
+        }
+    }
+    (lengths, selectors)
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::super::Decoder;
+    use super::{build_lengths, Encoder};
+
+    fn encode(bytes: &[u8], level: u32) -> Vec<u8> {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        for chunk in bytes.chunks(10_000) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    fn roundtrip(bytes: &[u8], level: u32) -> usize {
+        let encoded = encode(bytes, level);
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == bytes);
+        encoded.len()
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"", 9);
+        roundtrip(b"a", 9);
+        roundtrip(b"test", 1);
+        roundtrip(&[0; 100_000], 1);
+        roundtrip(include_bytes!("../data/test.txt"), 9);
+        let mut runs = Vec::new();
+        for len in 0..600 {
+            runs.extend((0..len).map(|_| len as u8));
+        }
+        roundtrip(&runs, 1);
+    }
+
+    #[test]
+    fn levels() {
+        let input = &include_bytes!("../data/test.large")[..(1 << 20)];
+        let fast = roundtrip(input, 1);
+        let best = roundtrip(input, 9);
+        assert!(best < fast);
+    }
+
+    #[test]
+    fn randomised_blocks() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        e.randomised = true;
+        e.write_all(input).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert!(decoded[..] == input[..]);
+    }
+
+    #[test]
+    fn limited_lengths() {
+        // Fibonacci frequencies make the deepest trees
+        let mut freqs = vec![1u32, 1];
+        for i in 2..40 {
+            let f = freqs[i - 1] + freqs[i - 2];
+            freqs.push(f);
+        }
+        let mut lengths = vec![0; freqs.len()];
+        build_lengths(&freqs, 17, &mut lengths);
+        assert!(lengths.iter().all(|l| (1..=17).contains(l)));
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+        assert!(kraft <= 1.0);
+    }
+}
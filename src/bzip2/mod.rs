@@ -0,0 +1,568 @@
+//! BZIP2 Compression and Decompression. Requires `bzip2` feature, enabled by default
+//!
+//! This module contains an implementation of the bzip2 file format. Each
+//! block of up to 900k bytes is run-length encoded (RLE1), transformed with
+//! the BWT, then the MTF ranks of the result are coded with up to 6 Huffman
+//! tables, zero runs being written in a bijective base 2 (RUNA/RUNB). Blocks
+//! and streams are checked with CRC-32/BZIP2. The decoder reads streams made
+//! of several concatenated ones as a whole, like the bzip2 tool does.
+//! Data is compressed with `Encoder`, which takes a compression level from 1
+//! to 9, selecting blocks of 100k to 900k bytes.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::bzip2;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::{BufReader, Read};
+//!
+//! let stream = File::open(&Path::new("path/to/file.bz2")).unwrap();
+//! let mut decompressed = Vec::new();
+//! bzip2::Decoder::new(BufReader::new(stream)).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * https://github.com/dsnet/compress/blob/master/doc/bzip2-format.pdf -
+//!   an unofficial specification of the format
+//! * https://sourceware.org/bzip2/ - the reference implementation
+
+use std::cmp;
+use std::io::{self, Read};
+
+use super::byteorder::ReadBytesExt;
+
+use bwt::mtf::MTF;
+use checksum::crc::{Crc, CRC_32_BZIP2};
+
+pub use self::encoder::Encoder;
+
+mod encoder;
+
+const STREAM_MAGIC: &[u8; 3] = b"BZh";
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+
+/// Block size unit of the compression levels
+const LEVEL_BLOCK_SIZE: usize = 100_000;
+const MIN_GROUPS: usize = 2;
+const MAX_GROUPS: usize = 6;
+/// Number of symbols coded with the same table
+const GROUP_SIZE: usize = 50;
+const MAX_CODE_LEN: usize = 20;
+/// Zero runs are written in bijective base 2, with these two symbols
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+
+/// Offsets between the bytes flipped in randomised blocks, which old
+/// versions of bzip2 produced for repetitive inputs
+static RNUMS: [u16; 512] = [
+    619, 720, 127, 481, 931, 816, 813, 233, 566, 247, 985, 724, 205, 454, 863, 491,
+    741, 242, 949, 214, 733, 859, 335, 708, 621, 574, 73, 654, 730, 472, 419, 436,
+    278, 496, 867, 210, 399, 680, 480, 51, 878, 465, 811, 169, 869, 675, 611, 697,
+    867, 561, 862, 687, 507, 283, 482, 129, 807, 591, 733, 623, 150, 238, 59, 379,
+    684, 877, 625, 169, 643, 105, 170, 607, 520, 932, 727, 476, 693, 425, 174, 647,
+    73, 122, 335, 530, 442, 853, 695, 249, 445, 515, 909, 545, 703, 919, 874, 474,
+    882, 500, 594, 612, 641, 801, 220, 162, 819, 984, 589, 513, 495, 799, 161, 604,
+    958, 533, 221, 400, 386, 867, 600, 782, 382, 596, 414, 171, 516, 375, 682, 485,
+    911, 276, 98, 553, 163, 354, 666, 933, 424, 341, 533, 870, 227, 730, 475, 186,
+    263, 647, 537, 686, 600, 224, 469, 68, 770, 919, 190, 373, 294, 822, 808, 206,
+    184, 943, 795, 384, 383, 461, 404, 758, 839, 887, 715, 67, 618, 276, 204, 918,
+    873, 777, 604, 560, 951, 160, 578, 722, 79, 804, 96, 409, 713, 940, 652, 934,
+    970, 447, 318, 353, 859, 672, 112, 785, 645, 863, 803, 350, 139, 93, 354, 99,
+    820, 908, 609, 772, 154, 274, 580, 184, 79, 626, 630, 742, 653, 282, 762, 623,
+    680, 81, 927, 626, 789, 125, 411, 521, 938, 300, 821, 78, 343, 175, 128, 250,
+    170, 774, 972, 275, 999, 639, 495, 78, 352, 126, 857, 956, 358, 619, 580, 124,
+    737, 594, 701, 612, 669, 112, 134, 694, 363, 992, 809, 743, 168, 974, 944, 375,
+    748, 52, 600, 747, 642, 182, 862, 81, 344, 805, 988, 739, 511, 655, 814, 334,
+    249, 515, 897, 955, 664, 981, 649, 113, 974, 459, 893, 228, 433, 837, 553, 268,
+    926, 240, 102, 654, 459, 51, 686, 754, 806, 760, 493, 403, 415, 394, 687, 700,
+    946, 670, 656, 610, 738, 392, 760, 799, 887, 653, 978, 321, 576, 617, 626, 502,
+    894, 679, 243, 440, 680, 879, 194, 572, 640, 724, 926, 56, 204, 700, 707, 151,
+    457, 449, 797, 195, 791, 558, 945, 679, 297, 59, 87, 824, 713, 663, 412, 693,
+    342, 606, 134, 108, 571, 364, 631, 212, 174, 643, 304, 329, 343, 97, 430, 751,
+    497, 314, 983, 374, 822, 928, 140, 206, 73, 263, 980, 736, 876, 478, 430, 305,
+    170, 514, 364, 692, 829, 82, 855, 953, 676, 246, 369, 970, 294, 750, 807, 827,
+    150, 790, 288, 923, 804, 378, 215, 828, 592, 281, 565, 555, 710, 82, 896, 831,
+    547, 261, 524, 462, 293, 465, 502, 56, 661, 821, 976, 991, 658, 869, 905, 758,
+    745, 193, 768, 550, 608, 933, 378, 286, 215, 979, 792, 961, 61, 688, 793, 644,
+    986, 403, 106, 366, 905, 644, 372, 567, 466, 434, 645, 210, 389, 550, 919, 135,
+    780, 773, 635, 389, 707, 100, 626, 958, 165, 504, 920, 176, 193, 713, 857, 265,
+    203, 50, 668, 108, 645, 990, 626, 197, 510, 357, 358, 850, 858, 364, 936, 638,
+];
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Reads bits from the most significant one of each byte
+struct BitReader<R> {
+    r: R,
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(r: R) -> BitReader<R> {
+        BitReader {
+            r,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        debug_assert!(n <= 32);
+        while self.count < n {
+            self.bits = (self.bits << 8) | try!(self.r.read_u8()) as u64;
+            self.count += 8;
+        }
+        self.count -= n;
+        Ok(((self.bits >> self.count) & ((1 << n) - 1)) as u32)
+    }
+
+    fn bit(&mut self) -> io::Result<bool> {
+        Ok(try!(self.bits(1)) != 0)
+    }
+
+    /// Skips to the next byte boundary
+    fn align(&mut self) {
+        self.count -= self.count % 8;
+    }
+
+    /// Tests if the underlying stream is over, at a byte boundary
+    fn at_eof(&mut self) -> io::Result<bool> {
+        if self.count >= 8 {
+            return Ok(false)
+        }
+        let mut byte = [0u8];
+        if try!(self.r.read(&mut byte)) == 0 {
+            return Ok(true)
+        }
+        self.bits = (self.bits << 8) | byte[0] as u64;
+        self.count += 8;
+        Ok(false)
+    }
+}
+
+/// A canonical Huffman code, codes being assigned by length then symbol
+struct HuffmanTable {
+    /// number of codes of each length
+    count: [u16; MAX_CODE_LEN + 1],
+    /// symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl HuffmanTable {
+    fn new(lengths: &[u8]) -> io::Result<HuffmanTable> {
+        let mut count = [0u16; MAX_CODE_LEN + 1];
+        for &len in lengths.iter() {
+            count[len as usize] += 1;
+        }
+        // the code must not be oversubscribed
+        let mut left = 1i32;
+        for &c in count[1..].iter() {
+            left = 2 * left - c as i32;
+            if left < 0 {
+                return error("invalid bzip2 huffman table");
+            }
+        }
+        let mut offsets = [0u16; MAX_CODE_LEN + 2];
+        for len in 1..=MAX_CODE_LEN {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            symbols[offsets[len as usize] as usize] = sym as u16;
+            offsets[len as usize] += 1;
+        }
+        Ok(HuffmanTable {
+            count,
+            symbols,
+        })
+    }
+
+    fn decode<R: Read>(&self, r: &mut BitReader<R>) -> io::Result<u16> {
+        // first code and index of the symbols of the current length
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in self.count[1..].iter() {
+            code |= try!(r.bits(1)) as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        error("invalid bzip2 huffman code")
+    }
+}
+
+/// Structure used to decode a bzip2 stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    r: BitReader<R>,
+    crc: Crc,
+    /// largest block of the current stream
+    max_block_size: usize,
+    /// combined checksum of the blocks of the current stream
+    combined_crc: u32,
+    in_stream: bool,
+    streams: usize,
+    eof: bool,
+
+    /// BWT of the current block
+    block: Vec<u8>,
+    /// inverse BWT table, each entry holding a symbol in its lower byte
+    table: Vec<u32>,
+    output: Vec<u8>,
+    start: usize,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new bzip2 decoder which will wrap the specified reader.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r: BitReader::new(r),
+            crc: Crc::new(CRC_32_BZIP2),
+            max_block_size: 0,
+            combined_crc: 0,
+            in_stream: false,
+            streams: 0,
+            eof: false,
+            block: Vec::new(),
+            table: Vec::new(),
+            output: Vec::new(),
+            start: 0,
+        }
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r.r
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Reads the header of the next stream, returning false if the input is
+    /// over instead
+    fn read_stream_header(&mut self) -> io::Result<bool> {
+        if self.streams != 0 && try!(self.r.at_eof()) {
+            return Ok(false)
+        }
+        let mut magic = [0u8; 3];
+        for b in magic.iter_mut() {
+            *b = try!(self.r.bits(8)) as u8;
+        }
+        if magic != *STREAM_MAGIC {
+            return error("invalid bzip2 header");
+        }
+        let level = try!(self.r.bits(8)) as u8;
+        if !(b'1'..=b'9').contains(&level) {
+            return error("invalid bzip2 block size");
+        }
+        self.max_block_size = (level - b'0') as usize * LEVEL_BLOCK_SIZE;
+        self.combined_crc = 0;
+        self.streams += 1;
+        Ok(true)
+    }
+
+    /// Decodes the next block into the output buffer, returning false at the
+    /// end of the stream instead
+    fn read_block(&mut self) -> io::Result<bool> {
+        let magic = (try!(self.r.bits(24)) as u64) << 24 | try!(self.r.bits(24)) as u64;
+        if magic == END_MAGIC {
+            let crc = try!(self.r.bits(32));
+            if crc != self.combined_crc {
+                return error("invalid checksum on bzip2 stream");
+            }
+            self.r.align();
+            return Ok(false)
+        }
+        if magic != BLOCK_MAGIC {
+            return error("invalid bzip2 block header");
+        }
+        let crc = try!(self.r.bits(32));
+        let randomised = try!(self.r.bit());
+        let origin = try!(self.r.bits(24)) as usize;
+
+        try!(self.read_symbols());
+        if origin >= self.block.len() {
+            return error("invalid bzip2 block origin");
+        }
+        self.inverse_bwt(origin, randomised);
+
+        if self.crc.checksum(&self.output) as u32 != crc {
+            return error("invalid checksum on bzip2 block");
+        }
+        self.combined_crc = self.combined_crc.rotate_left(1) ^ crc;
+        self.start = 0;
+        Ok(true)
+    }
+
+    /// Decodes the Huffman coded MTF ranks of a block into its BWT
+    fn read_symbols(&mut self) -> io::Result<()> {
+        let r = &mut self.r;
+
+        // the bytes present in the block
+        let mut used = Vec::with_capacity(256);
+        let ranges = try!(r.bits(16));
+        for i in 0..16 {
+            if ranges & (0x8000 >> i) != 0 {
+                let bits = try!(r.bits(16));
+                used.extend((0..16).filter(|j| bits & (0x8000 >> j) != 0)
+                                   .map(|j| (i * 16 + j) as u8));
+            }
+        }
+        if used.is_empty() {
+            return error("empty bzip2 block");
+        }
+        // all the ranks, the two run symbols and the end of block
+        let alphabet_size = used.len() + 2;
+        let end_of_block = (used.len() + 1) as u16;
+
+        let groups = try!(r.bits(3)) as usize;
+        if !(MIN_GROUPS..=MAX_GROUPS).contains(&groups) {
+            return error("invalid number of bzip2 huffman tables");
+        }
+        let selector_count = try!(r.bits(15)) as usize;
+        if selector_count == 0 {
+            return error("invalid number of bzip2 selectors");
+        }
+        let mut mtf = MTF::new();
+        mtf.reset_alphabetical();
+        let mut selectors = Vec::with_capacity(selector_count);
+        for _ in 0..selector_count {
+            let mut rank = 0;
+            while try!(r.bit()) {
+                rank += 1;
+                if rank >= groups {
+                    return error("invalid bzip2 selector");
+                }
+            }
+            selectors.push(mtf.decode(rank as u8) as usize);
+        }
+
+        let mut tables = Vec::with_capacity(groups);
+        let mut lengths = vec![0u8; alphabet_size];
+        for _ in 0..groups {
+            let mut len = try!(r.bits(5)) as i32;
+            for l in lengths.iter_mut() {
+                loop {
+                    if len < 1 || len > MAX_CODE_LEN as i32 {
+                        return error("invalid bzip2 code length");
+                    }
+                    if !try!(r.bit()) {
+                        break
+                    }
+                    len += if try!(r.bit()) { -1 } else { 1 };
+                }
+                *l = len as u8;
+            }
+            tables.push(try!(HuffmanTable::new(&lengths)));
+        }
+
+        let max_block_size = self.max_block_size;
+        let block = &mut self.block;
+        block.clear();
+        for (i, &b) in used.iter().enumerate() {
+            mtf.symbols[i] = b;
+        }
+        let mut run = 0;
+        let mut run_weight = 1;
+        let mut selectors = selectors.iter();
+        let mut table = &tables[0];
+        let mut group_left = 0;
+        loop {
+            if group_left == 0 {
+                match selectors.next() {
+                    Some(&sel) => table = &tables[sel],
+                    None => return error("missing bzip2 selectors"),
+                }
+                group_left = GROUP_SIZE;
+            }
+            group_left -= 1;
+
+            let sym = try!(table.decode(r));
+            if sym == RUNA || sym == RUNB {
+                if run_weight > max_block_size {
+                    return error("bzip2 block too large");
+                }
+                run += (sym as usize + 1) * run_weight;
+                run_weight <<= 1;
+                continue
+            }
+            if run != 0 {
+                if block.len() + run > max_block_size {
+                    return error("bzip2 block too large");
+                }
+                let len = block.len();
+                block.resize(len + run, mtf.symbols[0]);
+                run = 0;
+                run_weight = 1;
+            }
+            if sym == end_of_block {
+                return Ok(())
+            }
+            if block.len() == max_block_size {
+                return error("bzip2 block too large");
+            }
+            block.push(mtf.decode((sym - 1) as u8));
+        }
+    }
+
+    /// Inverts the BWT of the block, undoing the randomisation and the
+    /// initial RLE on the way.
+    ///
+    /// Unlike `bwt`, bzip2 sorts the rotations of the block, without an end
+    /// of block symbol.
+    fn inverse_bwt(&mut self, origin: usize, randomised: bool) {
+        let n = self.block.len();
+        let mut offsets = [0u32; 256];
+        for &b in self.block.iter() {
+            offsets[b as usize] += 1;
+        }
+        let mut total = 0;
+        for off in offsets.iter_mut() {
+            let count = *off;
+            *off = total;
+            total += count;
+        }
+        self.table.clear();
+        self.table.extend(self.block.iter().map(|&b| b as u32));
+        for (i, &b) in self.block.iter().enumerate() {
+            let slot = &mut offsets[b as usize];
+            self.table[*slot as usize] |= (i as u32) << 8;
+            *slot += 1;
+        }
+
+        let output = &mut self.output;
+        output.clear();
+        let mut pos = self.table[origin] >> 8;
+        let mut rand_left = 0;
+        let mut rand_index = 0;
+        let mut last = 0u8;
+        let mut run = 0;
+        for _ in 0..n {
+            let entry = self.table[pos as usize];
+            pos = entry >> 8;
+            let mut b = entry as u8;
+            if randomised {
+                if rand_left == 0 {
+                    rand_left = RNUMS[rand_index];
+                    rand_index = (rand_index + 1) % RNUMS.len();
+                }
+                rand_left -= 1;
+                if rand_left == 1 {
+                    b ^= 1;
+                }
+            }
+            // four equal bytes are followed by the count of the next ones
+            if run == 4 {
+                let len = output.len();
+                output.resize(len + b as usize, last);
+                run = 0;
+                continue
+            }
+            if b == last {
+                run += 1;
+            } else {
+                last = b;
+                run = 1;
+            }
+            output.push(b);
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.start < self.output.len() {
+                let n = cmp::min(buf.len(), self.output.len() - self.start);
+                buf[..n].copy_from_slice(&self.output[self.start..(self.start + n)]);
+                self.start += n;
+                return Ok(n)
+            }
+            if self.eof || buf.is_empty() {
+                return Ok(0)
+            }
+            if !self.in_stream {
+                if !try!(self.read_stream_header()) {
+                    self.eof = true;
+                    return Ok(0)
+                }
+                self.in_stream = true;
+            }
+            self.output.clear();
+            self.start = 0;
+            if !try!(self.read_block()) {
+                self.in_stream = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use super::Decoder;
+
+    fn decode(bytes: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(bytes);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.eof());
+        out
+    }
+
+    /// The input of test.gen.bz2, made by `bzip2 -1`: several blocks with
+    /// long runs
+    fn generated() -> Vec<u8> {
+        (0..300_000u32).map(|i| {
+            if (i / 1000) % 3 == 0 {
+                (i / 7000) as u8
+            } else {
+                (i.wrapping_mul(i) >> 7) as u8
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn decode_files() {
+        let reference = include_bytes!("../data/test.txt");
+        assert!(decode(include_bytes!("../data/test.txt.bz2"))[..] == reference[..]);
+        assert!(decode(include_bytes!("../data/test.gen.bz2")) == generated());
+    }
+
+    #[test]
+    fn multiple_streams() {
+        let reference = include_bytes!("../data/test.txt");
+        let mut input = include_bytes!("../data/test.txt.bz2").to_vec();
+        input.extend_from_slice(include_bytes!("../data/test.txt.bz2"));
+        let mut expected = reference.to_vec();
+        expected.extend_from_slice(reference);
+        assert!(decode(&input) == expected);
+    }
+
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("../data/test.txt.bz2");
+        for &(pos, bit) in [(2, 0), (3, 4), (20, 0), (200, 3), (input.len() - 3, 5)].iter() {
+            let mut input = input.to_vec();
+            input[pos] ^= 1 << bit;
+            let mut out = Vec::new();
+            assert!(Decoder::new(&input[..]).read_to_end(&mut out).is_err());
+        }
+        let mut out = Vec::new();
+        assert!(Decoder::new(&input[..(input.len() - 5)]).read_to_end(&mut out).is_err());
+        assert!(Decoder::new(&b""[..]).read_to_end(&mut out).is_err());
+    }
+}
@@ -5,6 +5,13 @@ Adler-32 checksum
 This implementation is based off the example found at
 http://en.wikipedia.org/wiki/Adler-32.
 
+The checksum is computed with the modulo deferred over runs of up to 5552
+bytes, and with SSSE3 or AVX2 instructions on x86_64 CPUs supporting them.
+
+Checksums of consecutive pieces of data, for instance compressed in parallel,
+are merged with `combine`. A `Rolling` checksum covers a window of fixed size
+sliding over the data, as needed for rsync-style block matching.
+
 # Example
 
 ```rust
@@ -12,11 +19,150 @@ use compress::checksum::adler;
 let mut state = adler::State32::new();
 state.feed(b"abracadabra");
 let checksum = state.result();
+
+let (mut a, mut b) = (adler::State32::new(), adler::State32::new());
+a.feed(b"abra");
+b.feed(b"cadabra");
+assert_eq!(adler::combine(a.result(), b.result(), 7), checksum);
+
+let mut rolling = adler::Rolling::new(4);
+rolling.feed(b"abracadabra");
+assert_eq!(rolling.result(), {
+    let mut last = adler::State32::new();
+    last.feed(b"abra");
+    last.result()
+});
 ```
 
 */
 
+use std::convert::TryInto;
+
 const MOD_ADLER: u32 = 65521;
+/// Largest number of bytes which can be summed before 'b' overflows 32 bits
+const NMAX: usize = 5552;
+
+/// Sums the bytes of 'buf' into (a, b), taking the modulo once per run of
+/// NMAX bytes
+fn update_scalar(mut a: u32, mut b: u32, buf: &[u8]) -> (u32, u32) {
+    for run in buf.chunks(NMAX) {
+        let mut chunks = run.chunks_exact(16);
+        for chunk in chunks.by_ref() {
+            // a loop of a constant count, unrolled by the compiler
+            let chunk: &[u8; 16] = chunk.try_into().unwrap();
+            for &byte in chunk.iter() {
+                a += byte as u32;
+                b += a;
+            }
+        }
+        for &byte in chunks.remainder().iter() {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (a, b)
+}
+
+#[cfg(target_arch = "x86_64")]
+fn update(a: u32, b: u32, buf: &[u8]) -> (u32, u32) {
+    if buf.len() >= 64 {
+        // only called once the CPU support is detected
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { update_avx2(a, b, buf) }
+        }
+        if is_x86_feature_detected!("ssse3") {
+            return unsafe { update_ssse3(a, b, buf) }
+        }
+    }
+    update_scalar(a, b, buf)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn update(a: u32, b: u32, buf: &[u8]) -> (u32, u32) {
+    update_scalar(a, b, buf)
+}
+
+/// Processes blocks of 32 bytes: 'a' gets the plain sum of a block, while
+/// 'b' gets its sum weighted from 32 down to 1, plus 32 times the value of
+/// 'a' before the block.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn update_ssse3(mut a: u32, mut b: u32, buf: &[u8]) -> (u32, u32) {
+    use std::arch::x86_64::*;
+    let tap1 = _mm_setr_epi8(32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17);
+    let tap2 = _mm_setr_epi8(16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1);
+    let zero = _mm_setzero_si128();
+    let ones = _mm_set1_epi16(1);
+    let mut blocks = buf.chunks_exact(32);
+    let rest = blocks.remainder();
+    let mut left = buf.len() / 32;
+    while left > 0 {
+        let n = ::std::cmp::min(left, NMAX / 32);
+        left -= n;
+        // the sum of the values of 'a' before every block
+        let mut v_ps = _mm_set_epi32(0, 0, 0, (a * n as u32) as i32);
+        let mut v_a = zero;
+        let mut v_b = _mm_set_epi32(0, 0, 0, b as i32);
+        for block in blocks.by_ref().take(n) {
+            let bytes1 = _mm_loadu_si128(block.as_ptr() as *const __m128i);
+            let bytes2 = _mm_loadu_si128(block.as_ptr().add(16) as *const __m128i);
+            v_ps = _mm_add_epi32(v_ps, v_a);
+            v_a = _mm_add_epi32(v_a, _mm_sad_epu8(bytes1, zero));
+            v_b = _mm_add_epi32(v_b, _mm_madd_epi16(_mm_maddubs_epi16(bytes1, tap1), ones));
+            v_a = _mm_add_epi32(v_a, _mm_sad_epu8(bytes2, zero));
+            v_b = _mm_add_epi32(v_b, _mm_madd_epi16(_mm_maddubs_epi16(bytes2, tap2), ones));
+        }
+        v_b = _mm_add_epi32(v_b, _mm_slli_epi32(v_ps, 5));
+        a = (a + sum_epi32(v_a)) % MOD_ADLER;
+        b = sum_epi32(v_b) % MOD_ADLER;
+    }
+    update_scalar(a, b, rest)
+}
+
+/// Same as 'update_ssse3', with a whole block per vector
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn update_avx2(mut a: u32, mut b: u32, buf: &[u8]) -> (u32, u32) {
+    use std::arch::x86_64::*;
+    let tap = _mm256_setr_epi8(32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17,
+                               16, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1);
+    let zero = _mm256_setzero_si256();
+    let ones = _mm256_set1_epi16(1);
+    let mut blocks = buf.chunks_exact(32);
+    let rest = blocks.remainder();
+    let mut left = buf.len() / 32;
+    while left > 0 {
+        let n = ::std::cmp::min(left, NMAX / 32);
+        left -= n;
+        let mut v_ps = _mm256_set_epi32(0, 0, 0, 0, 0, 0, 0, (a * n as u32) as i32);
+        let mut v_a = zero;
+        let mut v_b = _mm256_set_epi32(0, 0, 0, 0, 0, 0, 0, b as i32);
+        for block in blocks.by_ref().take(n) {
+            let bytes = _mm256_loadu_si256(block.as_ptr() as *const __m256i);
+            v_ps = _mm256_add_epi32(v_ps, v_a);
+            v_a = _mm256_add_epi32(v_a, _mm256_sad_epu8(bytes, zero));
+            v_b = _mm256_add_epi32(v_b, _mm256_madd_epi16(_mm256_maddubs_epi16(bytes, tap), ones));
+        }
+        v_b = _mm256_add_epi32(v_b, _mm256_slli_epi32(v_ps, 5));
+        let fold = |v: __m256i| _mm_add_epi32(_mm256_castsi256_si128(v),
+                                              _mm256_extracti128_si256(v, 1));
+        a = (a + sum_epi32(fold(v_a))) % MOD_ADLER;
+        b = sum_epi32(fold(v_b)) % MOD_ADLER;
+    }
+    update_scalar(a, b, rest)
+}
+
+/// Adds up the four lanes of a vector
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn sum_epi32(v: ::std::arch::x86_64::__m128i) -> u32 {
+    use std::arch::x86_64::*;
+    let v = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b10_11_00_01));
+    let v = _mm_add_epi32(v, _mm_shuffle_epi32(v, 0b01_00_11_10));
+    _mm_cvtsi128_si32(v) as u32
+}
 
 /// Adler state for 32 bits
 pub struct State32 {
@@ -32,10 +178,9 @@ impl State32 {
 
     /// Mutate the state for given data
     pub fn feed(&mut self, buf: &[u8]) {
-        for byte in buf.iter() {
-            self.a = (self.a + *byte as u32) % MOD_ADLER;
-            self.b = (self.a + self.b) % MOD_ADLER;
-        }
+        let (a, b) = update(self.a, self.b, buf);
+        self.a = a;
+        self.b = b;
     }
 
     /// Get checksum
@@ -49,3 +194,193 @@ impl State32 {
         self.b = 0;
     }
 }
+
+/// Computes the checksum of the concatenation of two pieces of data from
+/// their own checksums, 'len_b' being the length of the second piece. This
+/// is zlib's `adler32_combine`.
+pub fn combine(adler_a: u32, adler_b: u32, len_b: u64) -> u32 {
+    let modulo = MOD_ADLER as u64;
+    let rem = len_b % modulo;
+    let (a1, b1) = ((adler_a & 0xffff) as u64, (adler_a >> 16) as u64);
+    let (a2, b2) = ((adler_b & 0xffff) as u64, (adler_b >> 16) as u64);
+    // the sums of the second piece start from 1 rather than from the ones of
+    // the first piece, which adds 'a1 - 1' to every step of 'a'
+    let a = (a1 + a2 + modulo - 1) % modulo;
+    let b = (b1 + b2 + rem * a1 + modulo - rem) % modulo;
+    ((b << 16) | a) as u32
+}
+
+/// Adler-32 checksum of the last bytes of the data, in a window of fixed
+/// size. Every byte fed past the window size drops the oldest one.
+pub struct Rolling {
+    a: u32,
+    b: u32,
+    /// ring buffer of the window contents
+    window: Vec<u8>,
+    /// position of the oldest byte once the window is full
+    pos: usize,
+    size: usize,
+}
+
+impl Rolling {
+    /// Create a new rolling state over a window of 'size' bytes
+    pub fn new(size: usize) -> Rolling {
+        assert!(size > 0, "empty rolling window");
+        Rolling {
+            a: 1,
+            b: 0,
+            window: Vec::with_capacity(size),
+            pos: 0,
+            size,
+        }
+    }
+
+    /// Returns the number of bytes in the window, which is below the size
+    /// until enough data is fed
+    pub fn len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Tests if no data was fed yet
+    pub fn is_empty(&self) -> bool {
+        self.window.is_empty()
+    }
+
+    /// Adds a byte to the window, removing and returning the oldest one if
+    /// the window is full
+    pub fn roll(&mut self, byte: u8) -> Option<u8> {
+        if self.window.len() < self.size {
+            self.window.push(byte);
+            self.a = (self.a + byte as u32) % MOD_ADLER;
+            self.b = (self.a + self.b) % MOD_ADLER;
+            return None
+        }
+        let old = self.window[self.pos];
+        self.window[self.pos] = byte;
+        self.pos = (self.pos + 1) % self.size;
+        // the removed byte was counted 'size' times into 'b', along with
+        // the initial 1 of 'a'
+        let size = (self.size % MOD_ADLER as usize) as u32;
+        self.a = (self.a + MOD_ADLER - old as u32 + byte as u32) % MOD_ADLER;
+        self.b = (self.b + self.a + MOD_ADLER - 1 +
+                  MOD_ADLER - size * old as u32 % MOD_ADLER) % MOD_ADLER;
+        Some(old)
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        for &byte in buf.iter() {
+            self.roll(byte);
+        }
+    }
+
+    /// Get checksum of the window
+    pub fn result(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+
+    /// Reset the state, emptying the window
+    pub fn reset(&mut self) {
+        self.a = 1;
+        self.b = 0;
+        self.window.clear();
+        self.pos = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{combine, update_scalar, Rolling, State32, MOD_ADLER};
+
+    fn checksum(bytes: &[u8]) -> u32 {
+        let mut state = State32::new();
+        state.feed(bytes);
+        state.result()
+    }
+
+    #[test]
+    fn combined() {
+        let input = &include_bytes!("../data/test.large")[..100_000];
+        for &split in [0, 1, 100, 5552, 65521, 70000, input.len()].iter() {
+            let (a, b) = input.split_at(split);
+            let len_b = b.len() as u64;
+            assert_eq!(combine(checksum(a), checksum(b), len_b), checksum(input));
+        }
+        // a second piece longer than the modulo, made of 0xff bytes
+        let long = vec![0xffu8; 200_000];
+        let (a, b) = long.split_at(70_000);
+        assert_eq!(combine(checksum(a), checksum(b), b.len() as u64), checksum(&long));
+    }
+
+    #[test]
+    fn rolling() {
+        let input = &include_bytes!("../data/test.large")[..100_000];
+        for &size in [1, 16, 1000, 65521, 70000].iter() {
+            let mut state = Rolling::new(size);
+            for (i, &byte) in input.iter().enumerate() {
+                let old = state.roll(byte);
+                let start = (i + 1).saturating_sub(size);
+                assert_eq!(old, if i >= size { Some(input[i - size]) } else { None });
+                if i % 997 == 0 || i + 1 == input.len() {
+                    assert_eq!(state.result(), checksum(&input[start..(i + 1)]));
+                }
+            }
+            state.reset();
+            assert!(state.is_empty());
+            assert_eq!(state.result(), checksum(b""));
+        }
+        // the worst case of the modulo arithmetic
+        let mut state = Rolling::new(70000);
+        state.feed(&vec![0xffu8; 100_000]);
+        assert_eq!(state.result(), checksum(&vec![0xffu8; 70000]));
+    }
+
+    /// Reduces modulo on every byte, as the definition goes
+    fn reference(bytes: &[u8]) -> u32 {
+        let (mut a, mut b) = (1, 0);
+        for &byte in bytes.iter() {
+            a = (a + byte as u32) % MOD_ADLER;
+            b = (b + a) % MOD_ADLER;
+        }
+        (b << 16) | a
+    }
+
+    #[test]
+    fn reference_values() {
+        assert_eq!(checksum(b""), 1);
+        assert_eq!(checksum(b"Wikipedia"), 0x11e6_0398);
+        let input = include_bytes!("../data/test.large");
+        assert_eq!(checksum(&input[..100_000]), reference(&input[..100_000]));
+    }
+
+    #[test]
+    fn implementations() {
+        let input = &include_bytes!("../data/test.large")[..20_000];
+        let worst = vec![0xffu8; 20_000];
+        type Update = fn(u32, u32, &[u8]) -> (u32, u32);
+        let mut updates: Vec<Update> = vec![update_scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("ssse3") {
+                updates.push(|a, b, buf| unsafe { super::update_ssse3(a, b, buf) });
+            }
+            if is_x86_feature_detected!("avx2") {
+                updates.push(|a, b, buf| unsafe { super::update_avx2(a, b, buf) });
+            }
+        }
+        for update in updates.iter() {
+            for data in [input, &worst[..]].iter() {
+                for &(start, len) in [(0, 0), (1, 31), (3, 64), (5, 5551), (7, 5552),
+                                      (0, 5600), (9, 11_111), (0, 20_000)].iter() {
+                    let bytes = &data[start..(start + len)];
+                    // start from a non trivial state
+                    let (a, b) = update_scalar(1, 0, &data[..100]);
+                    let (a, b) = update(a, b, bytes);
+                    let expected = reference(&data[..100].iter().chain(bytes.iter())
+                                                         .cloned().collect::<Vec<u8>>());
+                    assert_eq!((b << 16) | a, expected);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+/*!
+
+Generic CRC engine, parameterized like the catalogue of CRC algorithms
+
+Any CRC of a width up to 64 bits is described by its `Params`: the
+polynomial, the initial value, whether the input and output bits are
+reflected, and the value XOR-ed into the result. A few standard variants are
+provided as constants, and listed in the `CATALOG`. The engine is table-driven
+and processes a byte at a time, except for CRC-32C, which goes through the
+SSE4.2 `crc32` instruction when the CPU supports it. The plain CRC-32 is
+served faster by the `crc32` module.
+
+# Example
+
+```rust
+use compress::checksum::crc;
+let engine = crc::Crc::new(crc::CRC_32_ISCSI);
+let mut state = engine.state();
+state.feed(b"abracadabra");
+let checksum = state.result();
+assert_eq!(checksum, engine.checksum(b"abracadabra"));
+```
+
+# Related links
+
+* http://reveng.sourceforge.net/crc-catalogue/ - the catalogue of parametrised
+  CRC algorithms
+
+*/
+
+/// Parameters of a CRC algorithm
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Params {
+    /// Name of the algorithm in the catalogue
+    pub name: &'static str,
+    /// Number of bits of the checksum, from 1 to 64
+    pub width: u8,
+    /// Generator polynomial, without its leading term
+    pub poly: u64,
+    /// Initial value of the register
+    pub init: u64,
+    /// Whether the bits of every input byte are reflected
+    pub refin: bool,
+    /// Whether the bits of the result are reflected
+    pub refout: bool,
+    /// Value XOR-ed into the result
+    pub xorout: u64,
+    /// Checksum of the ASCII string "123456789"
+    pub check: u64,
+}
+
+/// CRC-8/SMBUS, the plain CRC-8
+pub const CRC_8_SMBUS: Params = Params {
+    name: "CRC-8/SMBUS", width: 8, poly: 0x07, init: 0,
+    refin: false, refout: false, xorout: 0, check: 0xf4,
+};
+/// CRC-16/ARC, also known as CRC-16/IBM
+pub const CRC_16_ARC: Params = Params {
+    name: "CRC-16/ARC", width: 16, poly: 0x8005, init: 0,
+    refin: true, refout: true, xorout: 0, check: 0xbb3d,
+};
+/// CRC-16/IBM-3740, also known as CRC-16/CCITT-FALSE
+pub const CRC_16_IBM_3740: Params = Params {
+    name: "CRC-16/IBM-3740", width: 16, poly: 0x1021, init: 0xffff,
+    refin: false, refout: false, xorout: 0, check: 0x29b1,
+};
+/// CRC-16/KERMIT, also known as CRC-16/CCITT
+pub const CRC_16_KERMIT: Params = Params {
+    name: "CRC-16/KERMIT", width: 16, poly: 0x1021, init: 0,
+    refin: true, refout: true, xorout: 0, check: 0x2189,
+};
+/// CRC-16/XMODEM
+pub const CRC_16_XMODEM: Params = Params {
+    name: "CRC-16/XMODEM", width: 16, poly: 0x1021, init: 0,
+    refin: false, refout: false, xorout: 0, check: 0x31c3,
+};
+/// CRC-32/ISO-HDLC, the CRC-32 of gzip, ZIP and PNG
+pub const CRC_32_ISO_HDLC: Params = Params {
+    name: "CRC-32/ISO-HDLC", width: 32, poly: 0x04c1_1db7, init: 0xffff_ffff,
+    refin: true, refout: true, xorout: 0xffff_ffff, check: 0xcbf4_3926,
+};
+/// CRC-32/BZIP2, the unreflected CRC-32
+pub const CRC_32_BZIP2: Params = Params {
+    name: "CRC-32/BZIP2", width: 32, poly: 0x04c1_1db7, init: 0xffff_ffff,
+    refin: false, refout: false, xorout: 0xffff_ffff, check: 0xfc89_1918,
+};
+/// CRC-32/ISCSI, also known as CRC-32C (Castagnoli)
+pub const CRC_32_ISCSI: Params = Params {
+    name: "CRC-32/ISCSI", width: 32, poly: 0x1edc_6f41, init: 0xffff_ffff,
+    refin: true, refout: true, xorout: 0xffff_ffff, check: 0xe306_9283,
+};
+/// CRC-64/ECMA-182
+pub const CRC_64_ECMA_182: Params = Params {
+    name: "CRC-64/ECMA-182", width: 64, poly: 0x42f0_e1eb_a9ea_3693, init: 0,
+    refin: false, refout: false, xorout: 0, check: 0x6c40_df5f_0b49_7347,
+};
+/// CRC-64/XZ, also known as CRC-64/GO-ECMA
+pub const CRC_64_XZ: Params = Params {
+    name: "CRC-64/XZ", width: 64, poly: 0x42f0_e1eb_a9ea_3693, init: !0,
+    refin: true, refout: true, xorout: !0, check: 0x995d_c9bb_df19_39fa,
+};
+
+/// All the variants provided
+pub static CATALOG: [Params; 10] = [
+    CRC_8_SMBUS, CRC_16_ARC, CRC_16_IBM_3740, CRC_16_KERMIT, CRC_16_XMODEM,
+    CRC_32_ISO_HDLC, CRC_32_BZIP2, CRC_32_ISCSI, CRC_64_ECMA_182, CRC_64_XZ,
+];
+
+/// Finds a variant of the catalog by its name, ignoring the case
+pub fn find(name: &str) -> Option<&'static Params> {
+    CATALOG.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+fn mask(width: u8) -> u64 {
+    !0 >> (64 - width as u32)
+}
+
+/// Reverses the order of the lower 'width' bits
+fn reflect(value: u64, width: u8) -> u64 {
+    value.reverse_bits() >> (64 - width as u32)
+}
+
+/// A CRC engine for a given set of parameters, holding its lookup table
+pub struct Crc {
+    params: Params,
+    table: Vec<u64>,
+    /// whether CRC-32C goes through the SSE4.2 instructions
+    hardware: bool,
+}
+
+impl Crc {
+    /// Creates an engine, computing the lookup table of the parameters
+    pub fn new(params: Params) -> Crc {
+        assert!(params.width >= 1 && params.width <= 64, "invalid CRC width");
+        // Reflected registers are kept as-is, in the lower bits, while the
+        // other ones are aligned to the top of the 64 bits
+        let table = (0..256u64).map(|i| {
+            if params.refin {
+                let poly = reflect(params.poly, params.width);
+                (0..8).fold(i, |c, _| if c & 1 != 0 { (c >> 1) ^ poly } else { c >> 1 })
+            } else {
+                let poly = params.poly << (64 - params.width as u32);
+                (0..8).fold(i << 56, |c, _| if c >> 63 != 0 { (c << 1) ^ poly } else { c << 1 })
+            }
+        }).collect();
+        let hardware = params.width == 32 && params.poly == CRC_32_ISCSI.poly &&
+                       params.refin && sse42_available();
+        Crc {
+            params,
+            table,
+            hardware,
+        }
+    }
+
+    /// Returns the parameters of the engine
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Creates a new checksum state
+    pub fn state(&self) -> State<'_> {
+        State {
+            crc: self,
+            register: self.initial(),
+        }
+    }
+
+    /// Computes the checksum of the given data at once
+    pub fn checksum(&self, buf: &[u8]) -> u64 {
+        let mut state = self.state();
+        state.feed(buf);
+        state.result()
+    }
+
+    /// Continues the checksum 'crc' of some data with the following 'buf',
+    /// so that checksums can be kept without holding a `State`
+    pub fn update(&self, crc: u64, buf: &[u8]) -> u64 {
+        let p = &self.params;
+        let mut value = (crc ^ p.xorout) & mask(p.width);
+        if p.refin != p.refout {
+            value = reflect(value, p.width);
+        }
+        let register = if p.refin { value } else { value << (64 - p.width as u32) };
+        self.finish(self.update_register(register, buf))
+    }
+
+    fn initial(&self) -> u64 {
+        let p = &self.params;
+        if p.refin {
+            reflect(p.init & mask(p.width), p.width)
+        } else {
+            p.init << (64 - p.width as u32)
+        }
+    }
+
+    fn update_register(&self, mut register: u64, buf: &[u8]) -> u64 {
+        if self.hardware {
+            return sse42_crc32c(register as u32, buf) as u64
+        }
+        if self.params.refin {
+            for &byte in buf.iter() {
+                register = self.table[((register ^ byte as u64) & 0xff) as usize] ^
+                           (register >> 8);
+            }
+        } else {
+            for &byte in buf.iter() {
+                register = self.table[((register >> 56) ^ byte as u64) as usize] ^
+                           (register << 8);
+            }
+        }
+        register
+    }
+
+    fn finish(&self, register: u64) -> u64 {
+        let p = &self.params;
+        let mut value = if p.refin { register } else { register >> (64 - p.width as u32) };
+        if p.refin != p.refout {
+            value = reflect(value, p.width);
+        }
+        (value ^ p.xorout) & mask(p.width)
+    }
+}
+
+/// A running checksum of an engine
+pub struct State<'a> {
+    crc: &'a Crc,
+    register: u64,
+}
+
+impl<'a> State<'a> {
+    /// Returns the parameters of the engine
+    pub fn params(&self) -> &Params {
+        self.crc.params()
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        self.register = self.crc.update_register(self.register, buf);
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u64 {
+        self.crc.finish(self.register)
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.register = self.crc.initial();
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn sse42_available() -> bool {
+    is_x86_feature_detected!("sse4.2")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn sse42_available() -> bool {
+    false
+}
+
+#[cfg(target_arch = "x86_64")]
+fn sse42_crc32c(crc: u32, buf: &[u8]) -> u32 {
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn update(crc: u32, buf: &[u8]) -> u32 {
+        use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+        let mut chunks = buf.chunks_exact(8);
+        let mut wide = crc as u64;
+        for chunk in chunks.by_ref() {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            wide = _mm_crc32_u64(wide, u64::from_le_bytes(word));
+        }
+        let mut crc = wide as u32;
+        for &byte in chunks.remainder().iter() {
+            crc = _mm_crc32_u8(crc, byte);
+        }
+        crc
+    }
+    // only called once the CPU support was detected
+    unsafe { update(crc, buf) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn sse42_crc32c(_crc: u32, _buf: &[u8]) -> u32 {
+    unreachable!()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find, Crc, CATALOG, CRC_32_ISCSI, CRC_32_ISO_HDLC};
+
+    #[test]
+    fn catalog_checks() {
+        for params in CATALOG.iter() {
+            let crc = Crc::new(*params);
+            assert_eq!(crc.checksum(b"123456789"), params.check, "{}", params.name);
+        }
+        assert_eq!(find("crc-64/xz").unwrap().check, 0x995d_c9bb_df19_39fa);
+        assert!(find("CRC-0/NONE").is_none());
+    }
+
+    #[test]
+    fn incremental() {
+        let input = include_bytes!("../data/test.txt");
+        let crc = Crc::new(CRC_32_ISO_HDLC);
+        let mut state = crc.state();
+        for chunk in input.chunks(7) {
+            state.feed(chunk);
+        }
+        assert_eq!(state.result(), 0x617d_8ec9);
+        state.reset();
+        assert_eq!(state.result(), 0);
+    }
+
+    #[test]
+    fn continued() {
+        let input = include_bytes!("../data/test.txt");
+        for params in CATALOG.iter() {
+            let crc = Crc::new(*params);
+            let mut value = crc.checksum(&[]);
+            for chunk in input.chunks(100) {
+                value = crc.update(value, chunk);
+            }
+            assert_eq!(value, crc.checksum(input), "{}", params.name);
+        }
+    }
+
+    #[test]
+    fn crc32c_software() {
+        let input = include_bytes!("../data/test.txt");
+        let hardware = Crc::new(CRC_32_ISCSI);
+        let mut software = Crc::new(CRC_32_ISCSI);
+        software.hardware = false;
+        for offset in 0..8 {
+            assert_eq!(hardware.checksum(&input[offset..]), software.checksum(&input[offset..]));
+        }
+        assert_eq!(software.checksum(b"123456789"), CRC_32_ISCSI.check);
+    }
+}
@@ -0,0 +1,218 @@
+/*!
+
+CRC-32 (IEEE 802.3) checksum, as used by gzip, ZIP and PNG
+
+This is a table-driven implementation of the reflected CRC-32 with the
+polynomial 0x04C11DB7, which processes 8 bytes at a time ("slicing-by-8").
+Checksums of consecutive chunks, computed independently (possibly in
+parallel), are merged with `combine`.
+
+# Example
+
+```rust
+use compress::checksum::crc32;
+let mut state = crc32::State::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+
+let (mut a, mut b) = (crc32::State::new(), crc32::State::new());
+a.feed(b"abra");
+b.feed(b"cadabra");
+assert_eq!(crc32::combine(a.result(), b.result(), 7), checksum);
+```
+
+*/
+
+/// The reversed representation of the polynomial
+const POLY: u32 = 0xedb8_8320;
+
+/// Builds the slicing tables: the first one is the classic bytewise table,
+/// and the table k gives the CRC of a byte followed by k zero bytes.
+const fn make_tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { POLY ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        tables[0][i] = c;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 256 {
+        let mut k = 1;
+        while k < 8 {
+            let prev = tables[k - 1][i];
+            tables[k][i] = tables[0][(prev & 0xff) as usize] ^ (prev >> 8);
+            k += 1;
+        }
+        i += 1;
+    }
+    tables
+}
+
+static TABLES: [[u32; 256]; 8] = make_tables();
+
+/// Multiplies two polynomials modulo the CRC polynomial, in the reflected
+/// representation where the bit 31 stands for x^0
+const fn multmodp(a: u32, mut b: u32) -> u32 {
+    let mut m = 1u32 << 31;
+    let mut p = 0;
+    loop {
+        if a & m != 0 {
+            p ^= b;
+            if a & (m - 1) == 0 {
+                break
+            }
+        }
+        m >>= 1;
+        b = if b & 1 != 0 { (b >> 1) ^ POLY } else { b >> 1 };
+    }
+    p
+}
+
+/// The powers x^(2^k) modulo the CRC polynomial
+const fn make_x2n_table() -> [u32; 32] {
+    let mut table = [0u32; 32];
+    let mut p = 1u32 << 30; // x^1
+    table[0] = p;
+    let mut k = 1;
+    while k < 32 {
+        p = multmodp(p, p);
+        table[k] = p;
+        k += 1;
+    }
+    table
+}
+
+static X2N_TABLE: [u32; 32] = make_x2n_table();
+
+/// Returns x^(n * 2^k) modulo the CRC polynomial
+fn x2nmodp(mut n: u64, mut k: usize) -> u32 {
+    let mut p = 1u32 << 31; // x^0
+    while n != 0 {
+        if n & 1 != 0 {
+            p = multmodp(X2N_TABLE[k & 31], p);
+        }
+        n >>= 1;
+        k += 1;
+    }
+    p
+}
+
+/// Combines the checksums of two consecutive chunks of data into the checksum
+/// of their concatenation, given the length of the second chunk. This takes
+/// a time logarithmic in `len_b`.
+pub fn combine(crc_a: u32, crc_b: u32, len_b: u64) -> u32 {
+    multmodp(x2nmodp(len_b, 3), crc_a) ^ crc_b
+}
+
+/// CRC-32 state
+pub struct State {
+    crc: u32,
+}
+
+impl State {
+    /// Create a new state
+    pub fn new() -> State {
+        State { crc: !0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        let mut crc = self.crc;
+        let mut chunks = buf.chunks_exact(8);
+        for chunk in chunks.by_ref() {
+            let lo = crc ^ (chunk[0] as u32 | (chunk[1] as u32) << 8 |
+                            (chunk[2] as u32) << 16 | (chunk[3] as u32) << 24);
+            crc = TABLES[7][(lo & 0xff) as usize] ^
+                  TABLES[6][((lo >> 8) & 0xff) as usize] ^
+                  TABLES[5][((lo >> 16) & 0xff) as usize] ^
+                  TABLES[4][(lo >> 24) as usize] ^
+                  TABLES[3][chunk[4] as usize] ^
+                  TABLES[2][chunk[5] as usize] ^
+                  TABLES[1][chunk[6] as usize] ^
+                  TABLES[0][chunk[7] as usize];
+        }
+        for &byte in chunks.remainder().iter() {
+            crc = TABLES[0][((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.crc = crc;
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.crc = !0;
+    }
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{combine, State, TABLES};
+
+    fn checksum(bytes: &[u8]) -> u32 {
+        let mut state = State::new();
+        state.feed(bytes);
+        state.result()
+    }
+
+    #[test]
+    fn reference() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"123456789"), 0xcbf4_3926);
+        assert_eq!(checksum(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+    }
+
+    #[test]
+    fn incremental() {
+        let input = include_bytes!("../data/test.txt");
+        let mut state = State::new();
+        for chunk in input.chunks(7) {
+            state.feed(chunk);
+        }
+        assert_eq!(state.result(), checksum(input));
+        assert_eq!(state.result(), 0x617d_8ec9);
+        state.reset();
+        assert_eq!(state.result(), 0);
+    }
+
+    #[test]
+    fn slicing() {
+        let input = include_bytes!("../data/test.large");
+        let input = &input[..100_000];
+        // every alignment of the 8 byte chunks against the bytewise loop
+        for offset in 0..8 {
+            let mut bytewise = !0u32;
+            for &byte in input[offset..].iter() {
+                bytewise = TABLES[0][((bytewise ^ byte as u32) & 0xff) as usize] ^ (bytewise >> 8);
+            }
+            assert_eq!(checksum(&input[offset..]), !bytewise);
+        }
+    }
+
+    #[test]
+    fn combined() {
+        let input = include_bytes!("../data/test.txt");
+        let whole = checksum(input);
+        for &split in [0, 1, 7, 1000, input.len()].iter() {
+            let (a, b) = input.split_at(split);
+            assert_eq!(combine(checksum(a), checksum(b), b.len() as u64), whole);
+        }
+        // a zero-length second chunk changes nothing
+        assert_eq!(combine(whole, 0, 0), whole);
+    }
+}
@@ -0,0 +1,268 @@
+//! Checksum algorithms. Requires `checksum` feature, enabled by default
+//!
+//! Every checksum state implements the `Checksum` trait, along with
+//! `std::hash::Hasher`, so that code can be written once for all of them. The
+//! `ChecksumReader` and `ChecksumWriter` adapters compute a checksum of the
+//! data going through any stream.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::checksum::{ChecksumReader, Checksum};
+//! use compress::checksum::crc32;
+//! use std::io::Read;
+//!
+//! let mut r = ChecksumReader::new(&b"abracadabra"[..], crc32::State::new());
+//! let mut data = Vec::new();
+//! r.read_to_end(&mut data).unwrap();
+//! assert_eq!(r.checksum().result(), 0x17ea_f9b7);
+//! ```
+//!
+//! # Related links
+//!
+//! * http://en.wikipedia.org/wiki/Checksum
+
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+
+pub mod adler;
+pub mod crc;
+pub mod crc32;
+pub mod xxhash;
+
+/// A running checksum, fed with data piece by piece
+pub trait Checksum {
+    /// Type of the checksum value
+    type Output: Copy + Into<u64>;
+
+    /// Returns the number of bits of the checksum value
+    fn width(&self) -> u32;
+
+    /// Mutate the state for given data
+    fn feed(&mut self, buf: &[u8]);
+
+    /// Get checksum
+    fn result(&self) -> Self::Output;
+
+    /// Reset the state
+    fn reset(&mut self);
+}
+
+macro_rules! checksum_impls {
+    ($state:ty, $output:ty, $width:expr) => {
+        impl Checksum for $state {
+            type Output = $output;
+
+            fn width(&self) -> u32 {
+                $width
+            }
+
+            fn feed(&mut self, buf: &[u8]) {
+                <$state>::feed(self, buf)
+            }
+
+            fn result(&self) -> $output {
+                <$state>::result(self)
+            }
+
+            fn reset(&mut self) {
+                <$state>::reset(self)
+            }
+        }
+
+        impl Hasher for $state {
+            fn write(&mut self, bytes: &[u8]) {
+                <$state>::feed(self, bytes)
+            }
+
+            fn finish(&self) -> u64 {
+                <$state>::result(self) as u64
+            }
+        }
+    }
+}
+
+checksum_impls!(adler::State32, u32, 32);
+checksum_impls!(adler::Rolling, u32, 32);
+checksum_impls!(crc32::State, u32, 32);
+checksum_impls!(xxhash::State32, u32, 32);
+checksum_impls!(xxhash::State64, u64, 64);
+
+impl<'a> Checksum for crc::State<'a> {
+    type Output = u64;
+
+    fn width(&self) -> u32 {
+        self.params().width as u32
+    }
+
+    fn feed(&mut self, buf: &[u8]) {
+        crc::State::feed(self, buf)
+    }
+
+    fn result(&self) -> u64 {
+        crc::State::result(self)
+    }
+
+    fn reset(&mut self) {
+        crc::State::reset(self)
+    }
+}
+
+impl<'a> Hasher for crc::State<'a> {
+    fn write(&mut self, bytes: &[u8]) {
+        crc::State::feed(self, bytes)
+    }
+
+    fn finish(&self) -> u64 {
+        crc::State::result(self)
+    }
+}
+
+/// A reader computing a checksum of all the data read through it
+pub struct ChecksumReader<R, C> {
+    inner: R,
+    checksum: C,
+}
+
+impl<R: Read, C: Checksum> ChecksumReader<R, C> {
+    /// Creates a new reader, feeding the given checksum state
+    pub fn new(r: R, checksum: C) -> ChecksumReader<R, C> {
+        ChecksumReader {
+            inner: r,
+            checksum,
+        }
+    }
+
+    /// Returns the checksum of the data read so far
+    pub fn checksum(&self) -> &C {
+        &self.checksum
+    }
+
+    /// Returns a mutable reference to the checksum state
+    pub fn checksum_mut(&mut self) -> &mut C {
+        &mut self.checksum
+    }
+
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying reader. The data read
+    /// directly from it doesn't go into the checksum.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Destroys the adapter, returning the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read, C: Checksum> Read for ChecksumReader<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.checksum.feed(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// A writer computing a checksum of all the data written through it
+pub struct ChecksumWriter<W, C> {
+    inner: W,
+    checksum: C,
+}
+
+impl<W: Write, C: Checksum> ChecksumWriter<W, C> {
+    /// Creates a new writer, feeding the given checksum state
+    pub fn new(w: W, checksum: C) -> ChecksumWriter<W, C> {
+        ChecksumWriter {
+            inner: w,
+            checksum,
+        }
+    }
+
+    /// Returns the checksum of the data written so far
+    pub fn checksum(&self) -> &C {
+        &self.checksum
+    }
+
+    /// Returns a mutable reference to the checksum state
+    pub fn checksum_mut(&mut self) -> &mut C {
+        &mut self.checksum
+    }
+
+    /// Returns a reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying writer. The data written
+    /// directly to it doesn't go into the checksum.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Destroys the adapter, returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write, C: Checksum> Write for ChecksumWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        self.checksum.feed(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::hash::Hasher;
+    use std::io::{Read, Write};
+    use super::{adler, crc, crc32, xxhash, Checksum, ChecksumReader, ChecksumWriter};
+
+    /// Checks the adapters and the hasher against a direct computation
+    fn check<C: Checksum + Hasher>(mut state: C, width: u32) {
+        let input = include_bytes!("../data/test.txt");
+        assert_eq!(state.width(), width);
+        state.feed(input);
+        let expected = state.result();
+        state.reset();
+
+        let mut r = ChecksumReader::new(&input[..], state);
+        let mut buf = [0u8; 100];
+        while r.read(&mut buf).unwrap() != 0 {}
+        assert!(r.checksum().result().into() == expected.into());
+        let mut state = r.checksum;
+        state.reset();
+
+        let mut w = ChecksumWriter::new(Vec::new(), state);
+        for chunk in input.chunks(77) {
+            w.write_all(chunk).unwrap();
+        }
+        assert!(w.checksum().result().into() == expected.into());
+        assert!(w.get_ref()[..] == input[..]);
+        let mut state = w.checksum;
+        state.reset();
+
+        state.write(input);
+        assert_eq!(state.finish(), expected.into());
+    }
+
+    #[test]
+    fn all_checksums() {
+        check(adler::State32::new(), 32);
+        check(crc32::State::new(), 32);
+        check(xxhash::State32::new(), 32);
+        check(xxhash::State64::new(), 64);
+        let crc = crc::Crc::new(crc::CRC_16_XMODEM);
+        check(crc.state(), 16);
+    }
+}
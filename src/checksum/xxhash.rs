@@ -0,0 +1,358 @@
+/*!
+
+xxHash checksums, in their 32 and 64 bit variants
+
+xxHash is a very fast non-cryptographic hash, used among others by the LZ4
+frame format (xxHash32) and by Zstandard (xxHash64). Both variants take an
+optional seed.
+
+# Example
+
+```rust
+use compress::checksum::xxhash;
+let mut state = xxhash::State32::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+let mut state = xxhash::State64::with_seed(42);
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+# Related links
+
+* https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md - the xxHash
+  specification
+
+*/
+
+use super::super::byteorder::{ByteOrder, LittleEndian};
+
+const PRIME32_1: u32 = 2_654_435_761;
+const PRIME32_2: u32 = 2_246_822_519;
+const PRIME32_3: u32 = 3_266_489_917;
+const PRIME32_4: u32 = 668_265_263;
+const PRIME32_5: u32 = 374_761_393;
+
+const PRIME64_1: u64 = 11_400_714_785_074_694_791;
+const PRIME64_2: u64 = 14_029_467_366_897_019_727;
+const PRIME64_3: u64 = 1_609_587_929_392_839_161;
+const PRIME64_4: u64 = 9_650_029_242_287_828_579;
+const PRIME64_5: u64 = 2_870_177_450_012_600_261;
+
+fn round32(acc: u32, input: u32) -> u32 {
+    acc.wrapping_add(input.wrapping_mul(PRIME32_2))
+       .rotate_left(13)
+       .wrapping_mul(PRIME32_1)
+}
+
+fn round64(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2))
+       .rotate_left(31)
+       .wrapping_mul(PRIME64_1)
+}
+
+fn merge64(acc: u64, value: u64) -> u64 {
+    (acc ^ round64(0, value)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+/// xxHash32 state
+pub struct State32 {
+    seed: u32,
+    acc: [u32; 4],
+    /// input which doesn't fill a stripe yet
+    buf: [u8; 16],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl State32 {
+    /// Create a new state
+    pub fn new() -> State32 {
+        State32::with_seed(0)
+    }
+
+    /// Create a new state with the given seed
+    pub fn with_seed(seed: u32) -> State32 {
+        let mut state = State32 {
+            seed,
+            acc: [0; 4],
+            buf: [0; 16],
+            buf_len: 0,
+            total_len: 0,
+        };
+        state.reset();
+        state
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.acc.iter_mut().enumerate() {
+            *acc = round32(*acc, LittleEndian::read_u32(&stripe[(4 * i)..]));
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, mut buf: &[u8]) {
+        self.total_len += buf.len() as u64;
+        if self.buf_len != 0 {
+            let n = ::std::cmp::min(16 - self.buf_len, buf.len());
+            self.buf[self.buf_len..(self.buf_len + n)].copy_from_slice(&buf[..n]);
+            self.buf_len += n;
+            buf = &buf[n..];
+            if self.buf_len < 16 {
+                return
+            }
+            let stripe = self.buf;
+            self.stripe(&stripe);
+            self.buf_len = 0;
+        }
+        let mut stripes = buf.chunks_exact(16);
+        for stripe in stripes.by_ref() {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        let mut h = if self.total_len >= 16 {
+            self.acc[0].rotate_left(1)
+                .wrapping_add(self.acc[1].rotate_left(7))
+                .wrapping_add(self.acc[2].rotate_left(12))
+                .wrapping_add(self.acc[3].rotate_left(18))
+        } else {
+            self.seed.wrapping_add(PRIME32_5)
+        };
+        h = h.wrapping_add(self.total_len as u32);
+
+        let mut words = self.buf[..self.buf_len].chunks_exact(4);
+        for word in words.by_ref() {
+            h = h.wrapping_add(LittleEndian::read_u32(word).wrapping_mul(PRIME32_3))
+                 .rotate_left(17)
+                 .wrapping_mul(PRIME32_4);
+        }
+        for &byte in words.remainder().iter() {
+            h = h.wrapping_add((byte as u32).wrapping_mul(PRIME32_5))
+                 .rotate_left(11)
+                 .wrapping_mul(PRIME32_1);
+        }
+
+        h ^= h >> 15;
+        h = h.wrapping_mul(PRIME32_2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(PRIME32_3);
+        h ^ (h >> 16)
+    }
+
+    /// Reset the state, keeping the seed
+    pub fn reset(&mut self) {
+        let seed = self.seed;
+        self.acc = [
+            seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
+            seed.wrapping_add(PRIME32_2),
+            seed,
+            seed.wrapping_sub(PRIME32_1),
+        ];
+        self.buf_len = 0;
+        self.total_len = 0;
+    }
+}
+
+impl Default for State32 {
+    fn default() -> State32 {
+        State32::new()
+    }
+}
+
+/// xxHash64 state
+pub struct State64 {
+    seed: u64,
+    acc: [u64; 4],
+    /// input which doesn't fill a stripe yet
+    buf: [u8; 32],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl State64 {
+    /// Create a new state
+    pub fn new() -> State64 {
+        State64::with_seed(0)
+    }
+
+    /// Create a new state with the given seed
+    pub fn with_seed(seed: u64) -> State64 {
+        let mut state = State64 {
+            seed,
+            acc: [0; 4],
+            buf: [0; 32],
+            buf_len: 0,
+            total_len: 0,
+        };
+        state.reset();
+        state
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, acc) in self.acc.iter_mut().enumerate() {
+            *acc = round64(*acc, LittleEndian::read_u64(&stripe[(8 * i)..]));
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, mut buf: &[u8]) {
+        self.total_len += buf.len() as u64;
+        if self.buf_len != 0 {
+            let n = ::std::cmp::min(32 - self.buf_len, buf.len());
+            self.buf[self.buf_len..(self.buf_len + n)].copy_from_slice(&buf[..n]);
+            self.buf_len += n;
+            buf = &buf[n..];
+            if self.buf_len < 32 {
+                return
+            }
+            let stripe = self.buf;
+            self.stripe(&stripe);
+            self.buf_len = 0;
+        }
+        let mut stripes = buf.chunks_exact(32);
+        for stripe in stripes.by_ref() {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u64 {
+        let mut h = if self.total_len >= 32 {
+            let h = self.acc[0].rotate_left(1)
+                .wrapping_add(self.acc[1].rotate_left(7))
+                .wrapping_add(self.acc[2].rotate_left(12))
+                .wrapping_add(self.acc[3].rotate_left(18));
+            self.acc.iter().fold(h, |h, &acc| merge64(h, acc))
+        } else {
+            self.seed.wrapping_add(PRIME64_5)
+        };
+        h = h.wrapping_add(self.total_len);
+
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            h = (h ^ round64(0, LittleEndian::read_u64(rest)))
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h = (h ^ (LittleEndian::read_u32(rest) as u64).wrapping_mul(PRIME64_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest.iter() {
+            h = (h ^ (byte as u64).wrapping_mul(PRIME64_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME64_1);
+        }
+
+        h ^= h >> 33;
+        h = h.wrapping_mul(PRIME64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(PRIME64_3);
+        h ^ (h >> 32)
+    }
+
+    /// Reset the state, keeping the seed
+    pub fn reset(&mut self) {
+        let seed = self.seed;
+        self.acc = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        self.buf_len = 0;
+        self.total_len = 0;
+    }
+}
+
+impl Default for State64 {
+    fn default() -> State64 {
+        State64::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State32, State64, PRIME32_1};
+
+    /// The sanity buffer of the reference implementation
+    fn sanity_buffer() -> Vec<u8> {
+        let mut gen = PRIME32_1;
+        (0..101).map(|_| {
+            let byte = (gen >> 24) as u8;
+            gen = gen.wrapping_mul(gen);
+            byte
+        }).collect()
+    }
+
+    fn xxh32(bytes: &[u8], seed: u32) -> u32 {
+        let mut state = State32::with_seed(seed);
+        state.feed(bytes);
+        state.result()
+    }
+
+    fn xxh64(bytes: &[u8], seed: u64) -> u64 {
+        let mut state = State64::with_seed(seed);
+        state.feed(bytes);
+        state.result()
+    }
+
+    #[test]
+    fn reference32() {
+        let buf = sanity_buffer();
+        let seed = PRIME32_1;
+        assert_eq!(xxh32(b"", 0), 0x02cc_5d05);
+        assert_eq!(xxh32(&buf[..1], 0), 0xb85c_bee5);
+        assert_eq!(xxh32(&buf[..1], seed), 0xd584_5d64);
+        assert_eq!(xxh32(&buf[..14], 0), 0xe5aa_0ab4);
+        assert_eq!(xxh32(&buf[..14], seed), 0x4481_951d);
+        assert_eq!(xxh32(&buf, 0), 0x1f1a_a412);
+        assert_eq!(xxh32(&buf, seed), 0x498e_c8e2);
+    }
+
+    #[test]
+    fn reference64() {
+        let buf = sanity_buffer();
+        let seed = PRIME32_1 as u64;
+        assert_eq!(xxh64(b"", 0), 0xef46_db37_51d8_e999);
+        assert_eq!(xxh64(&buf[..1], 0), 0x4fce_394c_c889_52d8);
+        assert_eq!(xxh64(&buf[..1], seed), 0x7398_40cb_819f_a723);
+        assert_eq!(xxh64(&buf[..14], 0), 0xcffa_8db8_81bc_3a3d);
+        assert_eq!(xxh64(&buf[..14], seed), 0x5b96_1158_5efc_c9cb);
+        assert_eq!(xxh64(&buf, 0), 0x0eab_5433_84f8_78ad);
+        assert_eq!(xxh64(&buf, seed), 0xcaa6_5939_306f_1e21);
+    }
+
+    #[test]
+    fn incremental() {
+        let input = include_bytes!("../data/test.txt");
+        for &chunk_size in [1, 3, 15, 16, 17, 31, 33, 100].iter() {
+            let mut s32 = State32::with_seed(7);
+            let mut s64 = State64::with_seed(7);
+            for chunk in input.chunks(chunk_size) {
+                s32.feed(chunk);
+                s64.feed(chunk);
+            }
+            assert_eq!(s32.result(), xxh32(input, 7));
+            assert_eq!(s64.result(), xxh64(input, 7));
+            s32.reset();
+            s64.reset();
+            assert_eq!(s32.result(), xxh32(b"", 7));
+            assert_eq!(s64.result(), xxh64(b"", 7));
+        }
+    }
+}
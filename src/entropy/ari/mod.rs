@@ -249,6 +249,10 @@ impl<W: Write> Encoder<W> {
     }
 }
 
+fn corrupted<T>() -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, "arithmetic code out of range"))
+}
+
 /// An arithmetic decoder helper
 pub struct Decoder<R> {
     stream: R,
@@ -279,8 +283,25 @@ impl<R: Read> Decoder<R> {
 
     /// Decode an abstract value based on the given Model
     pub fn decode<V: Copy + Display, M: Model<V>>(&mut self, model: &M) -> io::Result<V> {
-        self.feed().unwrap();
-        let (value, shift) = model.decode(self.code, &mut self.range);
+        try!(self.feed());
+        // unlike `Model::decode`, the code is checked against the ranges, which
+        // a corrupted stream does not respect
+        let (code, total) = (self.code, model.get_denominator());
+        if code < self.range.low || code >= self.range.hai {
+            return corrupted()
+        }
+        let offset = self.range.query(total, code);
+        if offset >= total {
+            return corrupted()
+        }
+        let (value, lo, hi) = model.find_value(offset);
+        let mut out = [0 as Symbol; BORDER_BYTES];
+        let shift = self.range.process(total, lo, hi, &mut out[..]);
+        // the range may have been cut to a symbol border away from the code
+        if (if shift==0 {0} else {code>>(BORDER_BITS - shift*8)}) !=
+            out[..shift].iter().fold(0 as Border, |u,&b| (u<<8)+(b as Border)) {
+            return corrupted()
+        }
         self.bytes_pending = shift;
         Ok(value)
     }
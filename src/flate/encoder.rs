@@ -0,0 +1,834 @@
+//! DEFLATE encoder
+//!
+//! The input is parsed into literals and matches with hash chains and lazy
+//! matching, in the spirit of zlib, or with the much slower optimal parser of
+//! the `optimal` module. Each block is then written in the cheapest of the
+//! stored, fixed huffman and dynamic huffman forms.
+
+use std::cmp;
+use std::io::{self, Write};
+
+use super::{EXTRALENS, EXTRABITS, EXTRADIST, EXTRADBITS, ORDER, HISTORY, MAXBITS};
+use super::optimal;
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+/// Amount of input compressed into a single block
+const BLOCK_SIZE: usize = 64 * 1024;
+/// Amount of input given at once to the optimal parser, which then splits it
+/// into blocks on its own
+const OPTIMAL_BLOCK_SIZE: usize = 1024 * 1024;
+/// Number of cost model refinements of the level 10
+const OPTIMAL_ITERATIONS: u32 = 15;
+/// Largest length of a stored block
+const MAX_STORED: usize = 0xffff;
+/// Matches of the minimal length are not worth it past this distance
+const TOO_FAR: usize = 4096;
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const NIL: u32 = !0;
+
+const END_OF_BLOCK: usize = 256;
+pub const LCODES: usize = 286;
+pub const DCODES: usize = 30;
+const CLCODES: usize = 19;
+/// Maximum bit length of the code length codes
+const MAX_CL_BITS: usize = 7;
+
+/// A single element of the LZ77 parse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// A byte copied as-is
+    Literal(u8),
+    /// A (length, distance) reference to the previous data
+    Match(u16, u16),
+}
+
+/// Matcher parameters of a compression level
+#[derive(Clone, Copy)]
+struct Config {
+    /// lazy matching is done only for matches shorter than that
+    lazy: usize,
+    /// stop searching once a match of this length is found
+    nice: usize,
+    /// maximum number of hash chain links to follow
+    chain: usize,
+}
+
+static CONFIGS: [Config; 10] = [
+    Config { lazy: 0, nice: 0, chain: 0 }, // stored only
+    Config { lazy: 0, nice: 8, chain: 4 },
+    Config { lazy: 0, nice: 16, chain: 8 },
+    Config { lazy: 0, nice: 32, chain: 32 },
+    Config { lazy: 4, nice: 16, chain: 16 },
+    Config { lazy: 16, nice: 32, chain: 32 },
+    Config { lazy: 16, nice: 128, chain: 128 },
+    Config { lazy: 32, nice: 128, chain: 256 },
+    Config { lazy: 128, nice: 258, chain: 1024 },
+    Config { lazy: 258, nice: 258, chain: 4096 },
+];
+
+/// Returns the index of the length code (0 for the code 257) of a match length
+pub fn length_code(len: usize) -> usize {
+    match EXTRALENS.binary_search(&(len as u16)) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    }
+}
+
+/// Returns the distance code of a match distance
+pub fn dist_code(dist: usize) -> usize {
+    match EXTRADIST[..DCODES].binary_search(&(dist as u16)) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    }
+}
+
+/// Hash chains over the positions of a buffer, used to find matches
+pub struct Matcher {
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    next: usize,
+    /// maximum number of chain links to follow
+    pub chain: usize,
+    /// length of a match which is good enough to stop searching
+    pub nice: usize,
+}
+
+impl Matcher {
+    /// Create a new matcher with the given search limits
+    pub fn new(chain: usize, nice: usize) -> Matcher {
+        Matcher {
+            head: vec![NIL; HASH_SIZE],
+            prev: Vec::new(),
+            next: 0,
+            chain,
+            nice,
+        }
+    }
+
+    /// Forget all positions, preparing for a buffer of the given length
+    pub fn reset(&mut self, len: usize) {
+        for h in self.head.iter_mut() {
+            *h = NIL;
+        }
+        self.prev.truncate(0);
+        self.prev.resize(len, NIL);
+        self.next = 0;
+    }
+
+    fn hash(data: &[u8], pos: usize) -> usize {
+        let h = ((data[pos] as usize) << 10) ^ ((data[pos + 1] as usize) << 5) ^
+                (data[pos + 2] as usize);
+        h & (HASH_SIZE - 1)
+    }
+
+    /// Register all positions before 'pos' in the hash chains
+    pub fn insert_upto(&mut self, data: &[u8], pos: usize) {
+        while self.next < pos {
+            if self.next + MIN_MATCH <= data.len() {
+                let h = Matcher::hash(data, self.next);
+                self.prev[self.next] = self.head[h];
+                self.head[h] = self.next as u32;
+            }
+            self.next += 1;
+        }
+    }
+
+    /// Calls 'fun' with every (length, distance) pair along the hash chain of
+    /// 'pos', where the length is the longest match not crossing 'end'
+    pub fn for_each_match<F>(&self, data: &[u8], pos: usize, end: usize, mut fun: F)
+        where F: FnMut(usize, usize) -> bool
+    {
+        let max_len = cmp::min(MAX_MATCH, end - pos);
+        if max_len < MIN_MATCH {
+            return
+        }
+        let mut cand = self.head[Matcher::hash(data, pos)];
+        let mut chain = self.chain;
+        while cand != NIL && chain > 0 {
+            let c = cand as usize;
+            let dist = pos - c;
+            if dist > HISTORY {
+                break
+            }
+            let len = data[c..(c + max_len)].iter().zip(data[pos..(pos + max_len)].iter())
+                                             .take_while(|&(a, b)| a == b).count();
+            if len >= MIN_MATCH && (!fun(len, dist) || len == max_len) {
+                break
+            }
+            cand = self.prev[c];
+            chain -= 1;
+        }
+    }
+
+    /// Find the longest match for 'pos', returning (length, distance),
+    /// or a zero length if nothing is found
+    pub fn find(&self, data: &[u8], pos: usize, end: usize) -> (usize, usize) {
+        let nice = self.nice;
+        let mut best = (0, 0);
+        self.for_each_match(data, pos, end, |len, dist| {
+            if len > best.0 {
+                best = (len, dist);
+            }
+            len < nice
+        });
+        if best.0 == MIN_MATCH && best.1 > TOO_FAR {
+            (0, 0)
+        } else {
+            best
+        }
+    }
+}
+
+/// Parse 'data[start..end]' into tokens, 'data[..start]' being the history
+fn parse(matcher: &mut Matcher, config: &Config, data: &[u8],
+         start: usize, end: usize, tokens: &mut Vec<Token>) {
+    matcher.insert_upto(data, start);
+    let mut i = start;
+    while i < end {
+        matcher.insert_upto(data, i);
+        let (mut len, mut dist) = matcher.find(data, i, end);
+        if len >= MIN_MATCH && len < config.lazy && i + 1 < end {
+            // see if the next position has a better match
+            matcher.insert_upto(data, i + 1);
+            let (len2, dist2) = matcher.find(data, i + 1, end);
+            if len2 > len {
+                tokens.push(Token::Literal(data[i]));
+                i += 1;
+                len = len2;
+                dist = dist2;
+            }
+        }
+        if len >= MIN_MATCH {
+            tokens.push(Token::Match(len as u16, dist as u16));
+            i += len;
+        } else {
+            tokens.push(Token::Literal(data[i]));
+            i += 1;
+        }
+    }
+}
+
+/// Compute the code lengths of a huffman code limited to 'max_bits',
+/// unused symbols get a zero length
+pub fn build_lengths(freqs: &[u32], max_bits: usize, lengths: &mut [u8]) {
+    for len in lengths.iter_mut() {
+        *len = 0;
+    }
+    let mut symbols: Vec<(u32, usize)> = freqs.iter().enumerate()
+        .filter(|&(_, &f)| f != 0).map(|(s, &f)| (f, s)).collect();
+    symbols.sort();
+    let n = symbols.len();
+    if n == 0 {
+        return
+    }
+    if n == 1 {
+        lengths[symbols[0].1] = 1;
+        return
+    }
+
+    // Build the tree with two queues: the sorted leaves and the internal
+    // nodes, which are created in a non-decreasing weight order
+    let mut weight: Vec<u64> = symbols.iter().map(|&(f, _)| f as u64).collect();
+    weight.resize(2 * n - 1, 0);
+    let mut parent = vec![0; 2 * n - 1];
+    let (mut leaf, mut node) = (0, n);
+    for next in n..(2 * n - 1) {
+        let mut pick = || {
+            if leaf < n && (node >= next || weight[leaf] <= weight[node]) {
+                leaf += 1;
+                leaf - 1
+            } else {
+                node += 1;
+                node - 1
+            }
+        };
+        let (a, b) = (pick(), pick());
+        weight[next] = weight[a] + weight[b];
+        parent[a] = next;
+        parent[b] = next;
+    }
+    // Parents always come after children, so depths are computed backwards
+    let mut depth = vec![0; 2 * n - 1];
+    for i in (0..(2 * n - 2)).rev() {
+        depth[i] = depth[parent[i]] + 1;
+    }
+
+    // Count the leaves per length, folding the too long codes into the
+    // longest allowed length, and then fix the Kraft sum by moving leaves
+    // down the tree (the miniz way)
+    let mut counts = vec![0usize; max_bits + 1];
+    for &d in depth[..n].iter() {
+        counts[cmp::min(d, max_bits)] += 1;
+    }
+    let mut total: usize = (1..(max_bits + 1)).map(|i| counts[i] << (max_bits - i)).sum();
+    while total > 1 << max_bits {
+        counts[max_bits] -= 1;
+        for i in (1..max_bits).rev() {
+            if counts[i] != 0 {
+                counts[i] -= 1;
+                counts[i + 1] += 2;
+                break
+            }
+        }
+        total -= 1;
+    }
+
+    // The least frequent symbols get the longest codes
+    let mut sym = symbols.iter();
+    for len in (1..(max_bits + 1)).rev() {
+        for _ in 0..counts[len] {
+            lengths[sym.next().unwrap().1] = len as u8;
+        }
+    }
+}
+
+/// Compute the canonical huffman codes for the given lengths, with the bits
+/// reversed to be written out LSB first
+pub fn build_codes(lengths: &[u8], codes: &mut [u16]) {
+    let mut count = [0u16; MAXBITS + 1];
+    for &len in lengths.iter() {
+        count[len as usize] += 1;
+    }
+    count[0] = 0;
+    let mut next = [0u16; MAXBITS + 1];
+    let mut code = 0u16;
+    for len in 1..(MAXBITS + 1) {
+        code = (code + count[len - 1]) << 1;
+        next[len] = code;
+    }
+    for (&len, c) in lengths.iter().zip(codes.iter_mut()) {
+        if len != 0 {
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            *c = code.reverse_bits() >> (16 - len as u32);
+        }
+    }
+}
+
+/// The run-length encoded form of the literal/length and distance code
+/// lengths, as (symbol, extra bits value) pairs
+fn encode_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == len).count();
+        i += run;
+        if len == 0 {
+            while run >= 11 {
+                let r = cmp::min(run, 138);
+                out.push((18, (r - 11) as u8));
+                run -= r;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((len, 0));
+            run -= 1;
+            while run >= 3 {
+                let r = cmp::min(run, 6);
+                out.push((16, (r - 3) as u8));
+                run -= r;
+            }
+        }
+        for _ in 0..run {
+            out.push((len, 0));
+        }
+    }
+    out
+}
+
+/// Symbol frequencies of the literal/length and distance alphabets in a block
+/// of tokens, including its end of block code
+pub fn frequencies(tokens: &[Token]) -> ([u32; LCODES + 2], [u32; DCODES]) {
+    let mut lit_freqs = [0u32; LCODES + 2];
+    let mut dist_freqs = [0u32; DCODES];
+    lit_freqs[END_OF_BLOCK] = 1;
+    for t in tokens.iter() {
+        match *t {
+            Token::Literal(b) => lit_freqs[b as usize] += 1,
+            Token::Match(len, dist) => {
+                lit_freqs[257 + length_code(len as usize)] += 1;
+                dist_freqs[dist_code(dist as usize)] += 1;
+            }
+        }
+    }
+    (lit_freqs, dist_freqs)
+}
+
+/// Size in bits of a block of tokens, in the cheapest of the huffman forms
+pub fn block_cost(tokens: &[Token]) -> usize {
+    let (lit_freqs, dist_freqs) = frequencies(tokens);
+    let dynamic = Codes::dynamic(&lit_freqs, &dist_freqs);
+    let header = DynamicHeader::new(&dynamic);
+    cmp::min(3 + Codes::fixed().tokens_cost(tokens),
+             3 + header.cost() + dynamic.tokens_cost(tokens))
+}
+
+/// Huffman codes for the literal/length and distance alphabets
+pub struct Codes {
+    lit_lengths: [u8; LCODES + 2],
+    lit_codes: [u16; LCODES + 2],
+    dist_lengths: [u8; DCODES],
+    dist_codes: [u16; DCODES],
+}
+
+impl Codes {
+    /// The codes of the fixed huffman blocks
+    pub fn fixed() -> Codes {
+        let mut codes = Codes {
+            lit_lengths: [0; LCODES + 2],
+            lit_codes: [0; LCODES + 2],
+            dist_lengths: [5; DCODES],
+            dist_codes: [0; DCODES],
+        };
+        for (i, len) in codes.lit_lengths.iter_mut().enumerate() {
+            *len = match i {
+                0..=143 => 8,
+                144..=255 => 9,
+                256..=279 => 7,
+                _ => 8,
+            };
+        }
+        build_codes(&codes.lit_lengths, &mut codes.lit_codes);
+        build_codes(&codes.dist_lengths, &mut codes.dist_codes);
+        codes
+    }
+
+    /// Optimal codes for the given symbol frequencies
+    pub fn dynamic(lit_freqs: &[u32], dist_freqs: &[u32]) -> Codes {
+        let mut codes = Codes {
+            lit_lengths: [0; LCODES + 2],
+            lit_codes: [0; LCODES + 2],
+            dist_lengths: [0; DCODES],
+            dist_codes: [0; DCODES],
+        };
+        build_lengths(&lit_freqs[..LCODES], MAXBITS, &mut codes.lit_lengths[..LCODES]);
+        build_lengths(&dist_freqs[..DCODES], MAXBITS, &mut codes.dist_lengths);
+        // some decoders dislike an empty distance code
+        if codes.dist_lengths.iter().all(|&l| l == 0) {
+            codes.dist_lengths[0] = 1;
+        }
+        build_codes(&codes.lit_lengths, &mut codes.lit_codes);
+        build_codes(&codes.dist_lengths, &mut codes.dist_codes);
+        codes
+    }
+
+    /// Number of bits taken by a literal
+    pub fn literal_cost(&self, byte: u8) -> usize {
+        self.lit_lengths[byte as usize] as usize
+    }
+
+    /// Number of bits taken by a match
+    pub fn match_cost(&self, len: usize, dist: usize) -> usize {
+        let lc = length_code(len);
+        let dc = dist_code(dist);
+        self.lit_lengths[257 + lc] as usize + EXTRABITS[lc] as usize +
+            self.dist_lengths[dc] as usize + EXTRADBITS[dc] as usize
+    }
+
+    fn tokens_cost(&self, tokens: &[Token]) -> usize {
+        tokens.iter().fold(self.lit_lengths[END_OF_BLOCK] as usize, |sum, t| sum + match *t {
+            Token::Literal(b) => self.literal_cost(b),
+            Token::Match(len, dist) => self.match_cost(len as usize, dist as usize),
+        })
+    }
+}
+
+/// The header of a dynamic huffman block
+struct DynamicHeader {
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    runs: Vec<(u8, u8)>,
+    cl_lengths: [u8; CLCODES],
+    cl_codes: [u16; CLCODES],
+}
+
+impl DynamicHeader {
+    fn new(codes: &Codes) -> DynamicHeader {
+        let hlit = cmp::max(257, LCODES - codes.lit_lengths[..LCODES].iter().rev()
+                                                .take_while(|&&l| l == 0).count());
+        let hdist = cmp::max(1, DCODES - codes.dist_lengths.iter().rev()
+                                             .take_while(|&&l| l == 0).count());
+        let mut all = codes.lit_lengths[..hlit].to_vec();
+        all.extend_from_slice(&codes.dist_lengths[..hdist]);
+        let runs = encode_lengths(&all);
+
+        let mut freqs = [0u32; CLCODES];
+        for &(sym, _) in runs.iter() {
+            freqs[sym as usize] += 1;
+        }
+        let mut cl_lengths = [0u8; CLCODES];
+        build_lengths(&freqs, MAX_CL_BITS, &mut cl_lengths);
+        let mut cl_codes = [0u16; CLCODES];
+        build_codes(&cl_lengths, &mut cl_codes);
+        let hclen = cmp::max(4, CLCODES - ORDER.iter().rev()
+                                              .take_while(|&&s| cl_lengths[s] == 0).count());
+        DynamicHeader {
+            hlit,
+            hdist,
+            hclen,
+            runs,
+            cl_lengths,
+            cl_codes,
+        }
+    }
+
+    fn cost(&self) -> usize {
+        14 + 3 * self.hclen + self.runs.iter().fold(0, |sum, &(sym, _)| {
+            sum + self.cl_lengths[sym as usize] as usize + match sym {
+                16 => 2,
+                17 => 3,
+                18 => 7,
+                _ => 0,
+            }
+        })
+    }
+}
+
+/// A sink of bits, packed LSB first
+pub struct BitWriter<W> {
+    w: W,
+    out: Vec<u8>,
+    buf: u64,
+    cnt: usize,
+}
+
+impl<W: Write> BitWriter<W> {
+    /// Create a new bit writer on top of the given writer
+    pub fn new(w: W) -> BitWriter<W> {
+        BitWriter {
+            w,
+            out: Vec::new(),
+            buf: 0,
+            cnt: 0,
+        }
+    }
+
+    /// Append the 'cnt' lower bits of 'value'
+    pub fn bits(&mut self, value: u32, cnt: usize) {
+        self.buf |= (value as u64) << self.cnt;
+        self.cnt += cnt;
+        while self.cnt >= 8 {
+            self.out.push(self.buf as u8);
+            self.buf >>= 8;
+            self.cnt -= 8;
+        }
+    }
+
+    /// Pad the output to the byte boundary
+    pub fn align(&mut self) {
+        if self.cnt != 0 {
+            self.out.push(self.buf as u8);
+            self.buf = 0;
+            self.cnt = 0;
+        }
+    }
+
+    /// Write all the complete bytes into the underlying writer
+    pub fn flush_bytes(&mut self) -> io::Result<()> {
+        let result = self.w.write_all(&self.out);
+        self.out.truncate(0);
+        result
+    }
+
+    /// Return the underlying writer, dropping any unflushed bits
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+
+    fn stored(&mut self, raw: &[u8], last: bool) {
+        let mut chunks = raw.chunks(MAX_STORED).peekable();
+        // an empty block still needs a header
+        if raw.is_empty() {
+            self.bits(last as u32, 1);
+            self.bits(0, 2);
+            self.align();
+            self.out.extend_from_slice(&[0, 0, 0xff, 0xff]);
+        }
+        while let Some(chunk) = chunks.next() {
+            self.bits((last && chunks.peek().is_none()) as u32, 1);
+            self.bits(0, 2);
+            self.align();
+            let len = chunk.len() as u16;
+            self.out.extend_from_slice(&[len as u8, (len >> 8) as u8,
+                                         !len as u8, (!len >> 8) as u8]);
+            self.out.extend_from_slice(chunk);
+        }
+    }
+
+    fn tokens(&mut self, codes: &Codes, tokens: &[Token]) {
+        for t in tokens.iter() {
+            match *t {
+                Token::Literal(b) => {
+                    let b = b as usize;
+                    self.bits(codes.lit_codes[b] as u32, codes.lit_lengths[b] as usize);
+                }
+                Token::Match(len, dist) => {
+                    let (len, dist) = (len as usize, dist as usize);
+                    let lc = length_code(len);
+                    self.bits(codes.lit_codes[257 + lc] as u32,
+                              codes.lit_lengths[257 + lc] as usize);
+                    self.bits((len - EXTRALENS[lc] as usize) as u32, EXTRABITS[lc] as usize);
+                    let dc = dist_code(dist);
+                    self.bits(codes.dist_codes[dc] as u32, codes.dist_lengths[dc] as usize);
+                    self.bits((dist - EXTRADIST[dc] as usize) as u32, EXTRADBITS[dc] as usize);
+                }
+            }
+        }
+        self.bits(codes.lit_codes[END_OF_BLOCK] as u32,
+                  codes.lit_lengths[END_OF_BLOCK] as usize);
+    }
+
+    fn dynamic_header(&mut self, header: &DynamicHeader) {
+        self.bits((header.hlit - 257) as u32, 5);
+        self.bits((header.hdist - 1) as u32, 5);
+        self.bits((header.hclen - 4) as u32, 4);
+        for &sym in ORDER[..header.hclen].iter() {
+            self.bits(header.cl_lengths[sym] as u32, 3);
+        }
+        for &(sym, extra) in header.runs.iter() {
+            let s = sym as usize;
+            self.bits(header.cl_codes[s] as u32, header.cl_lengths[s] as usize);
+            match sym {
+                16 => self.bits(extra as u32, 2),
+                17 => self.bits(extra as u32, 3),
+                18 => self.bits(extra as u32, 7),
+                _ => (),
+            }
+        }
+    }
+
+    /// Write a block of tokens, which expand to 'raw', in the cheapest form
+    pub fn block(&mut self, tokens: &[Token], raw: &[u8], last: bool) {
+        let (lit_freqs, dist_freqs) = frequencies(tokens);
+        let fixed = Codes::fixed();
+        let dynamic = Codes::dynamic(&lit_freqs, &dist_freqs);
+        let header = DynamicHeader::new(&dynamic);
+
+        let fixed_cost = 3 + fixed.tokens_cost(tokens);
+        let dynamic_cost = 3 + header.cost() + dynamic.tokens_cost(tokens);
+        let chunks = cmp::max(1, raw.len().div_ceil(MAX_STORED));
+        let stored_cost = chunks * (3 + 7 + 32) + 8 * raw.len();
+
+        if stored_cost < cmp::min(fixed_cost, dynamic_cost) {
+            self.stored(raw, last);
+        } else if fixed_cost <= dynamic_cost {
+            self.bits(last as u32, 1);
+            self.bits(1, 2);
+            self.tokens(&fixed, tokens);
+        } else {
+            self.bits(last as u32, 1);
+            self.bits(2, 2);
+            self.dynamic_header(&header);
+            self.tokens(&dynamic, tokens);
+        }
+    }
+
+    /// Write an empty stored block, aligning the output to a byte boundary
+    pub fn sync_flush(&mut self) {
+        self.stored(&[], false);
+    }
+}
+
+
+/// This structure is used to compress a stream of bytes with DEFLATE.
+/// This is a wrapper around an internal writer which bytes will be written to.
+pub struct Encoder<W> {
+    w: BitWriter<W>,
+    /// history window followed by the pending input
+    buf: Vec<u8>,
+    start: usize,
+    level: usize,
+    /// number of iterations of the optimal parser, if used
+    iterations: u32,
+    matcher: Matcher,
+    tokens: Vec<Token>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new encoder with the default compression level (6), which
+    /// will have its output written to the given output stream. The output
+    /// stream can be re-acquired by calling `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, 6)
+    }
+
+    /// Creates a new encoder with the given compression level, from 0 (no
+    /// compression) to 9 (best compression). The level 10 selects the optimal
+    /// parser, with its default number of iterations.
+    pub fn with_level(w: W, level: u32) -> Encoder<W> {
+        if level >= 10 {
+            return Encoder::optimal(w, OPTIMAL_ITERATIONS)
+        }
+        let config = CONFIGS[level as usize];
+        Encoder {
+            w: BitWriter::new(w),
+            buf: Vec::new(),
+            start: 0,
+            level: level as usize,
+            iterations: 0,
+            matcher: Matcher::new(config.chain, config.nice),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Creates a new encoder using the optimal parser, in the style of
+    /// Zopfli. Every iteration refines the cost model the input is parsed
+    /// with, so more of them compress better, and slower.
+    pub fn optimal(w: W, iterations: u32) -> Encoder<W> {
+        Encoder {
+            w: BitWriter::new(w),
+            buf: Vec::new(),
+            start: 0,
+            level: 10,
+            iterations: cmp::max(iterations, 1),
+            matcher: Matcher::new(optimal::MAX_CHAIN, MAX_MATCH),
+            tokens: Vec::new(),
+        }
+    }
+
+    /// Returns a mutable reference to the underlying writer. Writing to it
+    /// directly corrupts the output, unless done before anything was written
+    /// to the encoder.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.w.w
+    }
+
+    fn block_size(&self) -> usize {
+        if self.iterations != 0 { OPTIMAL_BLOCK_SIZE } else { BLOCK_SIZE }
+    }
+
+    fn compress_block(&mut self, end: usize, last: bool) -> io::Result<()> {
+        self.tokens.truncate(0);
+        if self.level == 0 {
+            self.w.stored(&self.buf[self.start..end], last);
+        } else if self.iterations != 0 {
+            self.matcher.reset(self.buf.len());
+            optimal::compress(&mut self.matcher, &self.buf, self.start, end,
+                              self.iterations, &mut self.w, last);
+        } else {
+            let config = CONFIGS[self.level];
+            self.matcher.reset(self.buf.len());
+            parse(&mut self.matcher, &config, &self.buf, self.start, end, &mut self.tokens);
+            self.w.block(&self.tokens, &self.buf[self.start..end], last);
+        }
+        self.start = end;
+        if self.start > HISTORY {
+            let cut = self.start - HISTORY;
+            self.buf.drain(..cut);
+            self.start = HISTORY;
+        }
+        self.w.flush_bytes()
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let end = self.buf.len();
+        let mut result = self.compress_block(end, true);
+        self.w.align();
+        result = result.and(self.w.flush_bytes());
+        let mut w = self.w.into_inner();
+        result = result.and(w.flush());
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        let block_size = self.block_size();
+        while self.buf.len() - self.start >= block_size {
+            let end = self.start + block_size;
+            try!(self.compress_block(end, false));
+        }
+        Ok(buf.len())
+    }
+
+    /// Compress all the pending data and align the output with a sync flush,
+    /// so that everything written so far can be decoded
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.len() > self.start {
+            let end = self.buf.len();
+            try!(self.compress_block(end, false));
+        }
+        self.w.sync_flush();
+        try!(self.w.flush_bytes());
+        self.w.w.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use super::super::Decoder;
+    use super::{build_lengths, Encoder};
+
+    fn roundtrip(bytes: &[u8], level: u32) -> usize {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == bytes);
+        encoded.len()
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        for level in 0..10 {
+            roundtrip(b"test", level);
+            roundtrip(b"", level);
+            roundtrip(include_bytes!("../data/test.txt"), level);
+        }
+    }
+
+    #[test]
+    fn large_roundtrips() {
+        let input = include_bytes!("../data/test.large");
+        let input = &input[..(1 << 20)];
+        assert!(roundtrip(input, 9) < roundtrip(input, 1));
+        let zeroes = vec![0u8; 300000];
+        assert!(roundtrip(&zeroes, 6) < 1000);
+    }
+
+    #[test]
+    fn sync_flush() {
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(b"hello ").unwrap();
+        e.flush().unwrap();
+        e.write_all(b"hello world").unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], b"hello hello world");
+    }
+
+    #[test]
+    fn limited_lengths() {
+        // fibonacci frequencies produce the deepest huffman trees
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            let n = freqs.len();
+            let f = freqs[n - 1] + freqs[n - 2];
+            freqs.push(f);
+        }
+        let mut lengths = vec![0u8; freqs.len()];
+        build_lengths(&freqs, 15, &mut lengths);
+        assert!(lengths.iter().all(|&l| (1..=15).contains(&l)));
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+        assert!((kraft - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,313 @@
+//! Random access into DEFLATE and gzip streams
+//!
+//! An `Index` is built in a single pass over a compressed stream, in the
+//! style of zlib's zran.c: every `span` bytes of output, a `Checkpoint` is
+//! taken on the next block boundary, recording the input and output offsets
+//! along with the sliding window. An `IndexedReader` then serves reads at any
+//! offset by resuming a `Decoder` from the closest checkpoint before it, so
+//! at most about `span` bytes are decoded in vain per seek.
+//!
+//! Gzip streams made of several members are supported. The member trailers
+//! are skipped, and their CRC-32 isn't verified.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::flate::index::{Format, Index, IndexedReader};
+//! use std::fs::File;
+//! use std::io::{Read, Seek, SeekFrom};
+//!
+//! let index = Index::build(File::open("path/to/log.gz").unwrap(),
+//!                          Format::Gzip, 1 << 20).unwrap();
+//! let mut reader = IndexedReader::new(File::open("path/to/log.gz").unwrap(), index);
+//! reader.seek(SeekFrom::Start(123_456_789)).unwrap();
+//! let mut buf = [0u8; 4096];
+//! reader.read(&mut buf).unwrap();
+//! ```
+//!
+//! # Related links
+//!
+//! * https://github.com/madler/zlib/blob/master/examples/zran.c - the zlib
+//!   random access example
+//! * http://tools.ietf.org/html/rfc1952 - the gzip file format
+
+use std::cmp;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use super::Decoder;
+use super::super::ReadExact;
+use super::super::gzip_header;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// A point of a compressed stream where decoding can be resumed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Offset of the first input byte with unconsumed bits
+    pub input: u64,
+    /// Number of bits of that byte which were already consumed, from 0 to 7
+    pub bits: u8,
+    /// Offset in the decoded data
+    pub output: u64,
+    /// Last decoded bytes, up to the size of the sliding window
+    pub window: Vec<u8>,
+}
+
+/// Format of an indexed stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Raw DEFLATE data
+    Deflate,
+    /// One or more gzip members
+    Gzip,
+}
+
+/// Moves the decoder on to the next gzip member, if any, once the current
+/// one is over.
+fn next_member<R: Read>(d: &mut Decoder<R>) -> io::Result<bool> {
+    let mut trailer = Vec::new();
+    try!(d.r.push_exactly(gzip_header::TRAILER_SIZE, &mut trailer));
+    let header = match try!(gzip_header::skip(&mut d.r)) {
+        Some(size) => size,
+        None => return Ok(false),
+    };
+    let point = Checkpoint {
+        input: d.total_in() + gzip_header::TRAILER_SIZE + header,
+        bits: 0,
+        output: d.total_out(),
+        window: Vec::new(),
+    };
+    try!(d.resume(&point));
+    Ok(true)
+}
+
+/// Reads decoded data, crossing the gzip member boundaries
+fn read_decoded<R: Read>(d: &mut Decoder<R>, format: Format, buf: &mut [u8])
+                         -> io::Result<usize> {
+    loop {
+        let n = try!(d.read(buf));
+        if n != 0 || buf.is_empty() || format == Format::Deflate {
+            return Ok(n)
+        }
+        if !try!(next_member(d)) {
+            return Ok(0)
+        }
+    }
+}
+
+/// A list of checkpoints over a compressed stream
+#[derive(Clone, Debug)]
+pub struct Index {
+    format: Format,
+    points: Vec<Checkpoint>,
+    size: u64,
+}
+
+impl Index {
+    /// Decodes the whole stream, taking a checkpoint on the first block
+    /// boundary after every `span` bytes of output.
+    pub fn build<R: Read>(r: R, format: Format, span: u64) -> io::Result<Index> {
+        let mut d = Decoder::new(BufReader::new(r));
+        let start = match format {
+            Format::Deflate => 0,
+            Format::Gzip => match try!(gzip_header::skip(&mut d.r)) {
+                Some(size) => size,
+                None => return error("empty gzip stream"),
+            },
+        };
+        let first = Checkpoint {
+            input: start,
+            bits: 0,
+            output: 0,
+            window: Vec::new(),
+        };
+        try!(d.resume(&first));
+        let mut points = vec![first];
+
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            if try!(read_decoded(&mut d, format, &mut buf)) == 0 {
+                break
+            }
+            if d.total_out() - points[points.len() - 1].output < span {
+                continue
+            }
+            if let Some(point) = d.checkpoint() {
+                points.push(point);
+            }
+        }
+        Ok(Index {
+            format,
+            points,
+            size: d.total_out(),
+        })
+    }
+
+    /// Returns the format of the indexed stream
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the checkpoints, in the stream order
+    pub fn points(&self) -> &[Checkpoint] {
+        &self.points
+    }
+
+    /// Returns the size of the decoded data
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Finds the last checkpoint at or before the given output offset
+    fn find(&self, offset: u64) -> &Checkpoint {
+        let i = match self.points.binary_search_by(|p| p.output.cmp(&offset)) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        &self.points[i]
+    }
+}
+
+/// A seekable reader of the decoded data of an indexed stream
+pub struct IndexedReader<R> {
+    d: Decoder<BufReader<R>>,
+    index: Index,
+    pos: u64,
+    /// whether the decoder is yet to be brought to a checkpoint
+    needs_seek: bool,
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// Creates a new reader over the stream the index was built from
+    pub fn new(r: R, index: Index) -> IndexedReader<R> {
+        IndexedReader {
+            d: Decoder::new(BufReader::new(r)),
+            index,
+            pos: 0,
+            needs_seek: true,
+        }
+    }
+
+    /// Returns the index used by this reader
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Destroys the reader, returning the underlying stream
+    pub fn into_inner(self) -> R {
+        self.d.r.into_inner()
+    }
+
+    /// Brings the decoder to the current position, resuming from a checkpoint
+    /// unless decoding on is cheaper
+    fn reposition(&mut self) -> io::Result<()> {
+        let current = self.d.total_out();
+        if current == self.pos && !self.needs_seek {
+            return Ok(())
+        }
+        let format = self.index.format;
+        {
+            let point = self.index.find(self.pos);
+            if self.needs_seek || current > self.pos || current < point.output {
+                try!(self.d.r.seek(SeekFrom::Start(point.input)));
+                try!(self.d.resume(point));
+                self.needs_seek = false;
+            }
+        }
+        let mut buf = [0u8; 4096];
+        while self.d.total_out() < self.pos {
+            let n = cmp::min(buf.len() as u64, self.pos - self.d.total_out()) as usize;
+            if try!(read_decoded(&mut self.d, format, &mut buf[..n])) == 0 {
+                return error("indexed stream is shorter than expected");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.index.size {
+            return Ok(0)
+        }
+        try!(self.reposition());
+        let n = try!(read_decoded(&mut self.d, self.index.format, buf));
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.index.size.checked_add_signed(delta),
+        };
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => error("invalid seek to a negative position"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+    use super::super::super::rand::random;
+    use super::super::Encoder;
+    use super::{Format, Index, IndexedReader};
+
+    fn check_reads(compressed: Vec<u8>, format: Format, reference: &[u8], span: u64)
+                   -> usize {
+        let index = Index::build(Cursor::new(&compressed[..]), format, span).unwrap();
+        assert_eq!(index.size(), reference.len() as u64);
+        let points = index.points().len();
+        let mut r = IndexedReader::new(Cursor::new(compressed), index);
+
+        let mut buf = vec![0u8; 1000];
+        for _ in 0..50 {
+            let offset = random::<usize>() % reference.len();
+            r.seek(SeekFrom::Start(offset as u64)).unwrap();
+            let n = r.read(&mut buf).unwrap();
+            assert!(n > 0);
+            assert!(buf[..n] == reference[offset..(offset + n)]);
+        }
+
+        r.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        r.read_to_end(&mut tail).unwrap();
+        assert!(tail[..] == reference[(reference.len() - 10)..]);
+        r.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert!(&all[..] == reference);
+        points
+    }
+
+    #[test]
+    fn deflate() {
+        let reference = &include_bytes!("../data/test.large")[..(1 << 20)];
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(reference).unwrap();
+        let (compressed, result) = e.finish();
+        result.unwrap();
+        assert!(check_reads(compressed, Format::Deflate, reference, 100_000) > 5);
+    }
+
+    #[test]
+    fn gzip_members() {
+        let member = include_bytes!("../data/test.txt.gz");
+        let text = include_bytes!("../data/test.txt");
+        let mut compressed = member.to_vec();
+        compressed.extend_from_slice(member);
+        let mut reference = text.to_vec();
+        reference.extend_from_slice(text);
+        check_reads(compressed, Format::Gzip, &reference, 100);
+    }
+}
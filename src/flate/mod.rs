@@ -2,6 +2,10 @@
 //!
 //! This module contains an implementation of the DEFLATE compression scheme.
 //! This format is often used as the underpinning of other compression formats.
+//! Data is compressed with `Encoder`, which takes a compression level from 0
+//! (stored blocks only) to 9, and decompressed with `Decoder`. The extra level
+//! 10 trades a lot of speed for a few percents of size with an optimal
+//! parser, like Zopfli does.
 //!
 //! # Example
 //!
@@ -16,6 +20,15 @@
 //! flate::Decoder::new(stream).read_to_end(&mut decompressed);
 //! ```
 //!
+//! The Deflate64 variant found in ZIP archives (method 9) is decoded by a
+//! decoder created with `Decoder::new_deflate64`, while `Decoder::new_messages`
+//! decodes the WebSocket permessage-deflate framing, where consecutive
+//! messages share the window. A preset dictionary may be given to either one
+//! through `Decoder::set_dictionary`.
+//!
+//! Decoding can also be resumed in the middle of a stream from a `Checkpoint`,
+//! which is the basis of the random access provided by the `index` module.
+//!
 //! # Related links
 //!
 //! * http://tools.ietf.org/html/rfc1951 - RFC that this implementation is based
@@ -24,20 +37,54 @@
 //!   used as a reference
 //! * http://svn.ghostscript.com/ghostscript/trunk/gs/zlib/contrib/puff/puff.c -
 //!   Much of this code is based on the puff.c implementation found here
+//! * http://tools.ietf.org/html/rfc7692 - Compression extensions for WebSocket
 
 use std::cmp;
+use std::mem;
 use std::ptr::copy_nonoverlapping;
 use std::io::{self, Read};
 use std::vec::Vec;
 
-use super::byteorder::{LittleEndian, ReadBytesExt};
+use super::byteorder::ReadBytesExt;
 use super::ReadExact;
 
 const MAXBITS: usize = 15;
 const MAXLCODES: u16 = 286;
 const MAXDCODES: u16 = 30;
-const MAXCODES: u16 = MAXLCODES + MAXDCODES;
+const MAXDCODES64: u16 = 32;
+const MAXCODES: u16 = MAXLCODES + MAXDCODES64;
 const HISTORY: usize = 32 * 1024;
+const HISTORY64: usize = 64 * 1024;
+
+/// The tail of a sync flush (an empty stored block), which the WebSocket
+/// permessage-deflate extension strips from the end of every message.
+pub const SYNC_FLUSH_MARKER: [u8; 4] = [0, 0, 0xff, 0xff];
+
+// extra base length for codes 257-285
+static EXTRALENS: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51,
+    59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+// extra bits to read for codes 257-285
+static EXTRABITS: [u16; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4,
+    4, 5, 5, 5, 5, 0,
+];
+// base offset for distance codes, the last two are Deflate64 only
+static EXTRADIST: [u16; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+    513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+    24577, 32769, 49153,
+];
+// number of bits to read for distance codes (to add to the offset)
+static EXTRADBITS: [u16; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
+    10, 10, 11, 11, 12, 12, 13, 13, 14, 14,
+];
+// order in which the code length code lengths are transmitted
+static ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
 
 enum Error {
     HuffmanTreeTooLarge,
@@ -159,6 +206,13 @@ fn main() {
     println!("{:?}", HuffmanTree::construct(arr[..MAXDCODES]));
 }
 
+pub use self::encoder::Encoder;
+use self::index::Checkpoint;
+
+mod encoder;
+pub mod index;
+mod optimal;
+
 /// The structure that is used to decode an LZ4 data stream. This wraps an
 /// internal reader which is used as the source of all data.
 pub struct Decoder<R> {
@@ -174,27 +228,95 @@ pub struct Decoder<R> {
     bitbuf: usize,
     bitcnt: usize,
     eof: bool,
+
+    history: usize,
+    deflate64: bool,
+
+    messages: bool,
+
+    total_in: u64,
+    total_out: u64,
 }
 
 impl<R: Read> Decoder<R> {
     /// Creates a new flate decoder which will read data from the specified
     /// source
     pub fn new(r: R) -> Decoder<R> {
+        Decoder::with_history(r, HISTORY, false)
+    }
+
+    /// Creates a new decoder for the Deflate64 (enhanced deflate) variant,
+    /// which is stored as method 9 in ZIP archives. It differs from DEFLATE
+    /// by a 64KB window, a 16-bit extra length for the code 285, and the
+    /// additional distance codes 30 and 31.
+    pub fn new_deflate64(r: R) -> Decoder<R> {
+        Decoder::with_history(r, HISTORY64, true)
+    }
+
+    fn with_history(r: R, history: usize, deflate64: bool) -> Decoder<R> {
         Decoder {
             r: r,
-            output: Vec::with_capacity(HISTORY),
+            output: Vec::with_capacity(history),
             outpos: 0,
             block: Vec::new(),
             pos: 0,
             bitbuf: 0,
             bitcnt: 0,
             eof: false,
+            history,
+            deflate64,
+            messages: false,
+            total_in: 0,
+            total_out: 0,
         }
     }
 
+    /// Creates a decoder for a sequence of raw deflate messages which share
+    /// the sliding window, as used by the WebSocket permessage-deflate
+    /// extension (RFC 7692) with context takeover. Each message ends with a
+    /// sync flush, and its `SYNC_FLUSH_MARKER` may or may not be stripped.
+    /// The decoder reports EOF at the end of every message; decoding of the
+    /// next one is started with `next_message`.
+    pub fn new_messages(r: R) -> Decoder<R> {
+        let mut d = Decoder::new(r);
+        d.messages = true;
+        d
+    }
+
+    /// Starts decoding the next message from the given reader, keeping the
+    /// window of the previous messages. Returns the previous reader.
+    pub fn next_message(&mut self, r: R) -> R {
+        self.reset();
+        mem::replace(&mut self.r, r)
+    }
+
+    /// Primes the sliding window with a preset dictionary, replacing the
+    /// current history. Only the last window-sized part of it is used.
+    pub fn set_dictionary(&mut self, dict: &[u8]) {
+        let dict = &dict[dict.len().saturating_sub(self.history)..];
+        self.output.truncate(0);
+        self.output.extend_from_slice(dict);
+        self.outpos = self.output.len() % self.history;
+    }
+
     fn block(&mut self) -> io::Result<()> {
         self.pos = 0;
         self.block = Vec::with_capacity(4096);
+        if self.messages && self.bitcnt == 0 {
+            // a message is over once its input runs out on a block boundary
+            match self.r.read_u8() {
+                Ok(byte) => {
+                    self.total_in += 1;
+                    self.bitbuf = byte as usize;
+                    self.bitcnt = 8;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.eof = true;
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            }
+        }
         if try!(self.bits(1)) == 1 { self.eof = true; }
         match try!(self.bits(2)) {
             0 => self.statik(),
@@ -207,16 +329,16 @@ impl<R: Read> Decoder<R> {
 
     fn update_output(&mut self, mut from: usize) {
         let to = self.block.len();
-        if to - from > HISTORY {
-            from = to - HISTORY;
+        if to - from > self.history {
+            from = to - self.history;
         }
         let amt = to - from;
-        let remaining = HISTORY - self.outpos;
+        let remaining = self.history - self.outpos;
         let n = cmp::min(amt, remaining);
-        if self.output.len() < HISTORY {
+        if self.output.len() < self.history {
             self.output.extend(self.block[from..(from + n)].iter().map(|b| *b));
         } else if n > 0 {
-            assert_eq!(self.output.len(), HISTORY);
+            assert_eq!(self.output.len(), self.history);
             unsafe { copy_nonoverlapping(
                 &self.block[from],
                 &mut self.output[self.outpos],
@@ -235,21 +357,41 @@ impl<R: Read> Decoder<R> {
     }
 
     fn statik(&mut self) -> io::Result<()> {
-        let len = try!(self.r.read_u16::<LittleEndian>());
-        let nlen = try!(self.r.read_u16::<LittleEndian>());
+        // a message may end right after the header of the sync flush which
+        // completes it, once its marker is stripped
+        let mut header = SYNC_FLUSH_MARKER;
+        match self.byte() {
+            Ok(byte) => {
+                header[0] = byte;
+                for b in header[1..].iter_mut() {
+                    *b = try!(self.byte());
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && self.messages => (),
+            Err(e) => return Err(e),
+        }
+        let len = header[0] as u16 | (header[1] as u16) << 8;
+        let nlen = header[2] as u16 | (header[3] as u16) << 8;
         if !nlen != len { return error(Error::InvalidStaticSize) }
         try!(self.r.push_exactly(len as u64, &mut self.block));
+        self.total_in += len as u64;
         self.update_output(0);
         self.bitcnt = 0;
         self.bitbuf = 0;
         Ok(())
     }
 
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = try!(self.r.read_u8());
+        self.total_in += 1;
+        Ok(byte)
+    }
+
     // Bytes in the stream are LSB first, so the bitbuf is appended to from the
     // left and consumed from the right.
     fn bits(&mut self, cnt: usize) -> io::Result<u16> {
         while self.bitcnt < cnt {
-            let byte = try!(self.r.read_u8());
+            let byte = try!(self.byte());
             self.bitbuf |= (byte as usize) << self.bitcnt;
             self.bitcnt += 8;
         }
@@ -261,27 +403,7 @@ impl<R: Read> Decoder<R> {
 
     fn codes(&mut self, lens: &HuffmanTree,
              dist: &HuffmanTree) -> io::Result<()> {
-        // extra base length for codes 257-285
-        static EXTRALENS: [u16; 29] = [
-            3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51,
-            59, 67, 83, 99, 115, 131, 163, 195, 227, 258
-        ];
-        // extra bits to read for codes 257-285
-        static EXTRABITS: [u16; 29] = [
-            0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4,
-            4, 5, 5, 5, 5, 0,
-        ];
-        // base offset for distance codes.
-        static EXTRADIST: [u16; 30] = [
-            1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
-            513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
-            24577,
-        ];
-        // number of bits to read for distance codes (to add to the offset)
-        static EXTRADBITS: [u16; 30] = [
-            0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
-            10, 10, 11, 11, 12, 12, 13, 13,
-        ];
+        let max_dcodes = self.max_dcodes() as usize;
         let mut last_updated = 0;
         loop {
             let sym = try!(lens.decode(self));
@@ -290,19 +412,25 @@ impl<R: Read> Decoder<R> {
                 256 => break,
                 n if n < 290 => {
                     // figure out len/dist that we're working with
-                    let n = n - 257;
-                    if n as usize > EXTRALENS.len() {
+                    let n = (n - 257) as usize;
+                    if n >= EXTRALENS.len() {
                         return error(Error::InvalidHuffmanCode)
                     }
-                    let len = EXTRALENS[n as usize] +
-                              try!(self.bits(EXTRABITS[n as usize] as usize));
-
-                    let len = len as usize;
+                    // Deflate64 turns the code 285 into a 16-bit extra length
+                    let (base, extra) = if self.deflate64 && n == 28 {
+                        (3, 16)
+                    } else {
+                        (EXTRALENS[n], EXTRABITS[n])
+                    };
+                    let len = base as usize +
+                              try!(self.bits(extra as usize)) as usize;
 
                     let dist = try!(dist.decode(self)) as usize;
-                    let dist = EXTRADIST[dist] +
-                               try!(self.bits(EXTRADBITS[dist] as usize));
-                    let dist = dist as usize;
+                    if dist >= max_dcodes {
+                        return error(Error::InvalidHuffmanCode)
+                    }
+                    let dist = EXTRADIST[dist] as usize +
+                               try!(self.bits(EXTRADBITS[dist] as usize)) as usize;
 
                     // update the output buffer with any data we haven't pushed
                     // into it yet
@@ -320,13 +448,13 @@ impl<R: Read> Decoder<R> {
                     let mut finger = if self.outpos >= dist {
                         self.outpos - dist
                     } else {
-                        HISTORY - (dist - self.outpos)
+                        self.history - (dist - self.outpos)
                     };
                     let min = cmp::min(dist, len);
                     let start = self.block.len();
                     for _ in 0..min {
                         self.block.push(self.output[finger]);
-                        finger = (finger + 1) % HISTORY;
+                        finger = (finger + 1) % self.history;
                     }
                     for i in min..len {
                         let b = self.block[start + i - min];
@@ -366,7 +494,7 @@ impl<R: Read> Decoder<R> {
                 223, 224, 225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235,
                 236, 237, 238, 239, 240, 241, 242, 243, 244, 245, 246, 247, 248,
                 249, 250, 251, 252, 253, 254, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
             ]
         };
         static DIST: HuffmanTree = HuffmanTree {
@@ -387,10 +515,15 @@ impl<R: Read> Decoder<R> {
                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
                 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                0, 0, 0, 0, 0, 0, 0, 0
+                0, 0, 0, 0, 0, 0, 0, 0, 0, 0
             ]
         };
 
+        if self.deflate64 {
+            // Deflate64 makes use of all 32 fixed distance codes
+            let dist = try!(HuffmanTree::construct(&[5; MAXDCODES64 as usize]));
+            return self.codes(&LEN, &dist)
+        }
         self.codes(&LEN, &DIST)
     }
 
@@ -398,16 +531,13 @@ impl<R: Read> Decoder<R> {
         let hlit = try!(self.bits(5)) + 257; // number of length codes
         let hdist = try!(self.bits(5)) + 1;  // number of distance codes
         let hclen = try!(self.bits(4)) + 4;  // number of code length codes
-        if hlit > MAXLCODES || hdist > MAXDCODES {
+        if hlit > MAXLCODES || hdist > self.max_dcodes() {
             return error(Error::HuffmanTreeTooLarge);
         }
 
         // Read off the code length codes, and then build the huffman tree which
         // is then used to decode the actual huffman tree for the rest of the
         // data.
-        static ORDER: [usize; 19] = [
-            16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
-        ];
         let mut lengths = [0; 19];
         for i in 0..(hclen as usize) {
             lengths[ORDER[i]] = try!(self.bits(3));
@@ -449,11 +579,61 @@ impl<R: Read> Decoder<R> {
         self.codes(&lencode, &distcode)
     }
 
+    fn max_dcodes(&self) -> u16 {
+        if self.deflate64 { MAXDCODES64 } else { MAXDCODES }
+    }
+
     /// Returns whether this deflate stream has reached the EOF marker
     pub fn eof(&self) -> bool {
         self.eof && self.pos == self.block.len()
     }
 
+    /// Returns the number of compressed bytes read so far, counting from the
+    /// input offset of the last checkpoint resumed from
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Returns the number of bytes decoded so far, counting from the output
+    /// offset of the last checkpoint resumed from
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Captures the decoder state if it stands on a block boundary, with all
+    /// the decoded data read out. Decoding can be resumed from there later on.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        if self.eof || self.pos != self.block.len() {
+            return None
+        }
+        let mut window = self.output[self.outpos..].to_vec();
+        window.extend_from_slice(&self.output[..self.outpos]);
+        // a partially consumed byte is read again on resume
+        let partial = (self.bitcnt != 0) as u64;
+        Some(Checkpoint {
+            input: self.total_in - partial,
+            bits: ((8 - self.bitcnt) % 8) as u8,
+            output: self.total_out,
+            window,
+        })
+    }
+
+    /// Resumes decoding from a checkpoint. The wrapped reader has to be
+    /// positioned on the `input` offset of the checkpoint.
+    pub fn resume(&mut self, point: &Checkpoint) -> io::Result<()> {
+        self.reset();
+        self.set_dictionary(&point.window);
+        self.total_in = point.input;
+        self.total_out = point.output;
+        if point.bits != 0 {
+            let byte = try!(self.r.read_u8());
+            self.total_in += 1;
+            self.bitbuf = (byte >> point.bits) as usize;
+            self.bitcnt = 8 - point.bits as usize;
+        }
+        Ok(())
+    }
+
     /// Resets this flate decoder. Note that this could corrupt an in-progress
     /// decoding of a stream.
     pub fn reset(&mut self) {
@@ -467,7 +647,8 @@ impl<R: Read> Decoder<R> {
 
 impl<R: Read> Read for Decoder<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.pos == self.block.len() {
+        // empty blocks (such as sync flushes) don't signal the end of data
+        while self.pos == self.block.len() {
             if self.eof { return Ok(0) }
             try!(self.block());
         }
@@ -481,6 +662,7 @@ impl<R: Read> Read for Decoder<R> {
                     n
                 )};
                 self.pos += n;
+                self.total_out += n as u64;
                 Ok(n)
             }
         }
@@ -490,7 +672,7 @@ impl<R: Read> Read for Decoder<R> {
 #[cfg(test)]
 #[allow(warnings)]
 mod test {
-    use std::io::{BufReader, BufWriter, Read, Write};
+    use std::io::{self, BufReader, BufWriter, Read, Write};
     use super::super::rand::{random};
     use super::super::byteorder::{LittleEndian, BigEndian, WriteBytesExt, ReadBytesExt};
     use std::str;
@@ -527,29 +709,29 @@ mod test {
 
     #[test]
     fn decode() {
-        let reference = include_bytes!("data/test.txt");
-        test_decode(include_bytes!("data/test.z.0"), reference);
-        test_decode(include_bytes!("data/test.z.1"), reference);
-        test_decode(include_bytes!("data/test.z.2"), reference);
-        test_decode(include_bytes!("data/test.z.3"), reference);
-        test_decode(include_bytes!("data/test.z.4"), reference);
-        test_decode(include_bytes!("data/test.z.5"), reference);
-        test_decode(include_bytes!("data/test.z.6"), reference);
-        test_decode(include_bytes!("data/test.z.7"), reference);
-        test_decode(include_bytes!("data/test.z.8"), reference);
-        test_decode(include_bytes!("data/test.z.9"), reference);
-        test_decode_pure(include_bytes!("data/test.z.go"), reference);
+        let reference = include_bytes!("../data/test.txt");
+        test_decode(include_bytes!("../data/test.z.0"), reference);
+        test_decode(include_bytes!("../data/test.z.1"), reference);
+        test_decode(include_bytes!("../data/test.z.2"), reference);
+        test_decode(include_bytes!("../data/test.z.3"), reference);
+        test_decode(include_bytes!("../data/test.z.4"), reference);
+        test_decode(include_bytes!("../data/test.z.5"), reference);
+        test_decode(include_bytes!("../data/test.z.6"), reference);
+        test_decode(include_bytes!("../data/test.z.7"), reference);
+        test_decode(include_bytes!("../data/test.z.8"), reference);
+        test_decode(include_bytes!("../data/test.z.9"), reference);
+        test_decode_pure(include_bytes!("../data/test.z.go"), reference);
     }
 
     #[test]
     fn large() {
-        let reference = include_bytes!("data/test.large");
-        test_decode(include_bytes!("data/test.large.z.5"), reference);
+        let reference = include_bytes!("../data/test.large");
+        test_decode(include_bytes!("../data/test.large.z.5"), reference);
     }
 
     #[test]
     fn one_byte_at_a_time() {
-        let input = include_bytes!("data/test.z.1");
+        let input = include_bytes!("../data/test.z.1");
         let mut d = Decoder::new(BufReader::new(fixup(input)));
         assert!(!d.eof());
         let mut out = Vec::new();
@@ -561,12 +743,12 @@ mod test {
         }
 
         assert!(d.eof());
-        assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
     }
 
     #[test]
     fn random_byte_lengths() {
-        let input = include_bytes!("data/test.z.1");
+        let input = include_bytes!("../data/test.z.1");
         let mut d = Decoder::new(BufReader::new(fixup(input)));
         let mut out = Vec::new();
         let mut buf = [0u8; 40];
@@ -578,7 +760,173 @@ mod test {
                 }
             }
         }
-        assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+    }
+
+    /// A bare LSB-first bit writer, used to hand-craft Deflate64 streams
+    struct BitWriter {
+        out: Vec<u8>,
+        buf: u32,
+        cnt: usize,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter { out: Vec::new(), buf: 0, cnt: 0 }
+        }
+
+        fn bits(&mut self, value: u32, cnt: usize) {
+            for i in 0..cnt {
+                self.buf |= ((value >> i) & 1) << self.cnt;
+                self.cnt += 1;
+                if self.cnt == 8 {
+                    self.out.push(self.buf as u8);
+                    self.buf = 0;
+                    self.cnt = 0;
+                }
+            }
+        }
+
+        // huffman codes are packed starting from the most significant bit
+        fn code(&mut self, code: u32, len: usize) {
+            for i in (0..len).rev() {
+                self.bits((code >> i) & 1, 1);
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            let pad = (8 - self.cnt) % 8;
+            self.bits(0, pad);
+            self.out
+        }
+    }
+
+    /// Produces a stored block with `prefix`, followed by a fixed block with
+    /// a single match of length `len` (code 285) and the given distance code.
+    fn deflate64_stream(prefix: &[u8], len: usize, dcode: u32,
+                        dbits: usize, dextra: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.bits(0, 3);
+        let mut out = w.finish();
+        out.write_u16::<LittleEndian>(prefix.len() as u16).unwrap();
+        out.write_u16::<LittleEndian>(!(prefix.len() as u16)).unwrap();
+        out.extend(prefix.iter().map(|b| *b));
+
+        let mut w = BitWriter::new();
+        w.bits(1, 1);
+        w.bits(1, 2);
+        w.code(0xc0 + 285 - 280, 8);
+        w.bits((len - 3) as u32, 16);
+        w.code(dcode, 5);
+        w.bits(dextra, dbits);
+        w.code(0, 7);
+        out.extend(w.finish().into_iter());
+        out
+    }
+
+    fn expand(prefix: &[u8], len: usize, dist: usize) -> Vec<u8> {
+        let mut out = prefix.to_vec();
+        let start = out.len() - dist;
+        for i in 0..len {
+            let b = out[start + i];
+            out.push(b);
+        }
+        out
+    }
+
+    #[test]
+    fn deflate64_far_distance() {
+        let prefix: Vec<u8> = (0..40000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+        // distance code 30 covers 32769-49152
+        let input = deflate64_stream(&prefix, 300, 30, 14, 40000 - 32769);
+        let mut d = Decoder::new_deflate64(BufReader::new(&input[..]));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(buf == expand(&prefix, 300, 40000));
+
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn deflate64_long_length() {
+        let input = deflate64_stream(b"abc", 65538, 2, 0, 0);
+        let mut d = Decoder::new_deflate64(BufReader::new(&input[..]));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len(), 3 + 65538);
+        assert!(buf == expand(b"abc", 65538, 3));
+    }
+
+    // Generated by zlib with a raw window and a Z_SYNC_FLUSH after every
+    // message, the trailing marker being stripped
+    static MESSAGES: [(&'static [u8], &'static [u8]); 3] = [
+        (b"Hello, permessage-deflate!",
+         &[242, 72, 205, 201, 201, 215, 81, 40, 72, 45, 202, 77, 45, 46, 78,
+           76, 79, 213, 77, 73, 77, 203, 73, 44, 73, 85, 4, 0]),
+        (b"Hello, permessage-deflate! Hello again.",
+         &[242, 192, 41, 163, 0, 150, 82, 72, 76, 79, 204, 204, 211, 3, 0]),
+        (b"Hello, permessage-deflate! Hello again.",
+         &[34, 82, 25, 0]),
+    ];
+
+    fn decode_messages(with_marker: bool) {
+        let payload = |i: usize| {
+            let mut input = MESSAGES[i].1.to_vec();
+            if with_marker {
+                input.extend(super::SYNC_FLUSH_MARKER.iter().map(|b| *b));
+            }
+            input
+        };
+        let mut d = Decoder::new_messages(io::Cursor::new(payload(0)));
+        for i in 0..MESSAGES.len() {
+            if i != 0 {
+                d.next_message(io::Cursor::new(payload(i)));
+            }
+            let mut buf = Vec::new();
+            d.read_to_end(&mut buf).unwrap();
+            assert!(d.eof());
+            assert_eq!(&buf[..], MESSAGES[i].0);
+        }
+    }
+
+    #[test]
+    fn messages_stripped() {
+        decode_messages(false);
+    }
+
+    #[test]
+    fn messages_with_marker() {
+        decode_messages(true);
+    }
+
+    #[test]
+    fn messages_truncated() {
+        // 22 times 'a', cut before its end of block code, which the zeros of
+        // a made-up marker would complete
+        let input = [74, 76, 196, 6, 0, 0];
+        let mut d = Decoder::new_messages(io::Cursor::new(input[..4].to_vec()));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+
+        let input = MESSAGES[0].1;
+        let mut with_marker = input.to_vec();
+        with_marker.extend(super::SYNC_FLUSH_MARKER.iter().map(|b| *b));
+        // cut in the middle of the compressed block, or of the marker
+        for truncated in [&input[..10], &input[..(input.len() - 2)],
+                          &with_marker[..(with_marker.len() - 2)]].iter() {
+            let mut d = Decoder::new_messages(io::Cursor::new(truncated.to_vec()));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn dictionary() {
+        let input = [67, 102, 163, 171, 65, 51, 2, 0];
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        d.set_dictionary(b"the quick brown fox jumps over the lazy dog");
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert_eq!(&buf[..], &b"the lazy dog jumps over the quick brown fox"[..]);
     }
 
     //fn roundtrip(bytes: &[u8]) {
@@ -595,13 +943,13 @@ mod test {
     //fn some_roundtrips() {
     //    roundtrip(bytes!("test"));
     //    roundtrip(bytes!(""));
-    //    roundtrip(include_bytes!("data/test.txt"));
+    //    roundtrip(include_bytes!("../data/test.txt"));
     //}
 
     #[cfg(feature="unstable")]
     #[bench]
     fn decompress_speed(bh: &mut test::Bencher) {
-        let input = include_bytes!("data/test.z.9");
+        let input = include_bytes!("../data/test.z.9");
         let mut d = Decoder::new(BufReader::new(fixup(input)));
         let mut output = [0u8; 65536];
         let mut output_size = 0;
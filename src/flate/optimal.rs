@@ -0,0 +1,290 @@
+//! Optimal parsing of DEFLATE blocks, in the style of Zopfli
+//!
+//! All the matches of every position are found once and cached. The input is
+//! parsed greedily first, and the block boundaries are chosen on that parse.
+//! Every block is then parsed again as a shortest path problem, where the
+//! price of a literal or a match is its size in bits under a cost model. The
+//! model of each iteration is taken from the statistics of the previous
+//! parse, and the smallest parse found wins.
+//!
+//! # Related links
+//!
+//! * https://github.com/google/zopfli - the Zopfli compression library
+
+use std::cmp;
+use std::f64;
+use std::io::Write;
+
+use super::{EXTRABITS, EXTRADBITS};
+use super::encoder::{block_cost, dist_code, frequencies, length_code, BitWriter, Matcher,
+                     Token, DCODES, MAX_MATCH, MIN_MATCH};
+
+/// Longest hash chain walked when looking for matches
+pub const MAX_CHAIN: usize = 2048;
+/// Maximum number of blocks the input of a single call is split into
+const MAX_BLOCKS: usize = 15;
+/// Blocks of less tokens than that aren't split any further
+const MIN_SPLIT: usize = 64;
+/// Number of candidate points probed by every round of the split search
+const PROBES: usize = 9;
+
+/// The matches of every position, as a list of (length, distance) pairs of
+/// increasing lengths. The lengths above the one of the previous pair, up to
+/// the one of the pair, are reachable with its distance.
+struct MatchCache {
+    offsets: Vec<usize>,
+    pairs: Vec<(u16, u16)>,
+}
+
+impl MatchCache {
+    fn new(matcher: &mut Matcher, data: &[u8], start: usize, end: usize) -> MatchCache {
+        let mut cache = MatchCache {
+            offsets: vec![0],
+            pairs: Vec::new(),
+        };
+        matcher.insert_upto(data, start);
+        for i in start..end {
+            matcher.insert_upto(data, i);
+            let mut best = MIN_MATCH - 1;
+            let pairs = &mut cache.pairs;
+            // candidates come in the order of increasing distances
+            matcher.for_each_match(data, i, end, |len, dist| {
+                if len > best {
+                    pairs.push((len as u16, dist as u16));
+                    best = len;
+                }
+                true
+            });
+            cache.offsets.push(pairs.len());
+        }
+        cache
+    }
+
+    fn at(&self, i: usize) -> &[(u16, u16)] {
+        &self.pairs[self.offsets[i]..self.offsets[i + 1]]
+    }
+}
+
+/// Price in bits of every literal, match length and distance code
+struct CostModel {
+    literal: [f64; 256],
+    length: [f64; MAX_MATCH + 1],
+    dist: [f64; DCODES],
+}
+
+impl CostModel {
+    /// Prices matching the entropy of the symbols of a parse
+    fn new(tokens: &[Token]) -> CostModel {
+        let (lit_freqs, dist_freqs) = frequencies(tokens);
+        let lit_bits = entropy(&lit_freqs);
+        let dist_bits = entropy(&dist_freqs);
+        let mut model = CostModel {
+            literal: [0.0; 256],
+            length: [0.0; MAX_MATCH + 1],
+            dist: [0.0; DCODES],
+        };
+        model.literal.copy_from_slice(&lit_bits[..256]);
+        for len in MIN_MATCH..(MAX_MATCH + 1) {
+            let lc = length_code(len);
+            model.length[len] = lit_bits[257 + lc] + EXTRABITS[lc] as f64;
+        }
+        for (dc, cost) in model.dist.iter_mut().enumerate() {
+            *cost = dist_bits[dc] + EXTRADBITS[dc] as f64;
+        }
+        model
+    }
+}
+
+/// The ideal code lengths of the given frequencies, unused symbols being
+/// priced as if they occurred once
+fn entropy(freqs: &[u32]) -> Vec<f64> {
+    let total: u32 = freqs.iter().sum();
+    let log_total = (cmp::max(total, 1) as f64).log2();
+    freqs.iter().map(|&f| log_total - (cmp::max(f, 1) as f64).log2()).collect()
+}
+
+/// Takes the longest match at every position
+fn greedy_parse(cache: &MatchCache, data: &[u8], start: usize, end: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = start;
+    while i < end {
+        match cache.at(i - start).last() {
+            Some(&(len, dist)) => {
+                tokens.push(Token::Match(len, dist));
+                i += len as usize;
+            }
+            None => {
+                tokens.push(Token::Literal(data[i]));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Finds the cheapest parse of 'data[from..to]' under the given model,
+/// 'start' being the position the match cache begins at
+fn optimal_parse(cache: &MatchCache, model: &CostModel, data: &[u8],
+                 start: usize, from: usize, to: usize) -> Vec<Token> {
+    let n = to - from;
+    let mut cost = vec![f64::INFINITY; n + 1];
+    // the (length, distance) of the last step of the cheapest path, the
+    // length of a literal being 1
+    let mut step = vec![(0u16, 0u16); n + 1];
+    cost[0] = 0.0;
+    for i in 0..n {
+        let base = cost[i];
+        let pos = from + i;
+        let literal = base + model.literal[data[pos] as usize];
+        if literal < cost[i + 1] {
+            cost[i + 1] = literal;
+            step[i + 1] = (1, 0);
+        }
+        let max_len = cmp::min(MAX_MATCH, n - i);
+        let mut prev = MIN_MATCH - 1;
+        for &(len, dist) in cache.at(pos - start).iter() {
+            let len = cmp::min(len as usize, max_len);
+            if len <= prev {
+                continue
+            }
+            let dist_cost = base + model.dist[dist_code(dist as usize)];
+            for l in (prev + 1)..(len + 1) {
+                let c = dist_cost + model.length[l];
+                if c < cost[i + l] {
+                    cost[i + l] = c;
+                    step[i + l] = (l as u16, dist);
+                }
+            }
+            prev = len;
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut i = n;
+    while i > 0 {
+        let (len, dist) = step[i];
+        if len == 1 {
+            tokens.push(Token::Literal(data[from + i - 1]));
+        } else {
+            tokens.push(Token::Match(len, dist));
+        }
+        i -= len as usize;
+    }
+    tokens.reverse();
+    tokens
+}
+
+/// Finds the token index in 'a+1..b' which splits the tokens into the two
+/// cheapest blocks, narrowing the search around the best of a few probes.
+/// Returns the index along with the total cost.
+fn best_split(tokens: &[Token], a: usize, b: usize) -> (usize, usize) {
+    let split_cost = |s: usize| block_cost(&tokens[a..s]) + block_cost(&tokens[s..b]);
+    let (mut lo, mut hi) = (a + 1, b);
+    loop {
+        if hi - lo <= PROBES {
+            return (lo..hi).map(|s| (s, split_cost(s)))
+                           .min_by_key(|&(_, c)| c).unwrap();
+        }
+        let probes: Vec<usize> = (0..PROBES).map(|k| lo + (hi - lo) * (k + 1) / (PROBES + 1))
+                                            .collect();
+        let best = (0..PROBES).min_by_key(|&k| split_cost(probes[k])).unwrap();
+        if best > 0 {
+            lo = probes[best - 1];
+        }
+        if best + 1 < PROBES {
+            hi = probes[best + 1];
+        }
+    }
+}
+
+/// Collects the token indices where blocks should be split, in order
+fn split_blocks(tokens: &[Token], a: usize, b: usize, splits: &mut Vec<usize>) {
+    if splits.len() + 1 >= MAX_BLOCKS || b - a < 2 * MIN_SPLIT {
+        return
+    }
+    let (s, cost) = best_split(tokens, a, b);
+    if cost >= block_cost(&tokens[a..b]) {
+        return
+    }
+    split_blocks(tokens, a, s, splits);
+    splits.push(s);
+    split_blocks(tokens, s, b, splits);
+}
+
+/// Compress 'data[start..end]', 'data[..start]' being the history, into as
+/// many blocks as it is worth splitting it into
+pub fn compress<W: Write>(matcher: &mut Matcher, data: &[u8], start: usize, end: usize,
+                          iterations: u32, w: &mut BitWriter<W>, last: bool) {
+    if start == end {
+        w.block(&[], &[], last);
+        return
+    }
+    let cache = MatchCache::new(matcher, data, start, end);
+    let greedy = greedy_parse(&cache, data, start, end);
+    let mut splits = Vec::new();
+    split_blocks(&greedy, 0, greedy.len(), &mut splits);
+    splits.push(greedy.len());
+
+    let mut from_token = 0;
+    let mut from = start;
+    for (i, &to_token) in splits.iter().enumerate() {
+        let block = &greedy[from_token..to_token];
+        let to = from + block.iter().fold(0, |sum, t| sum + match *t {
+            Token::Literal(_) => 1,
+            Token::Match(len, _) => len as usize,
+        });
+
+        let mut best = block.to_vec();
+        let mut best_cost = block_cost(&best);
+        let mut model = CostModel::new(&best);
+        for _ in 0..iterations {
+            let tokens = optimal_parse(&cache, &model, data, start, from, to);
+            let cost = block_cost(&tokens);
+            model = CostModel::new(&tokens);
+            if cost < best_cost {
+                best = tokens;
+                best_cost = cost;
+            }
+        }
+        w.block(&best, &data[from..to], last && i + 1 == splits.len());
+
+        from_token = to_token;
+        from = to;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use super::super::{Decoder, Encoder};
+
+    fn compress(bytes: &[u8], encoder: Encoder<Vec<u8>>) -> Vec<u8> {
+        let mut e = encoder;
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == bytes);
+        encoded
+    }
+
+    #[test]
+    fn smaller_than_level_9() {
+        let text = include_bytes!("../data/test.txt");
+        let best = compress(text, Encoder::with_level(Vec::new(), 9)).len();
+        assert!(compress(text, Encoder::optimal(Vec::new(), 5)).len() < best);
+
+        let input = &include_bytes!("../data/test.large")[..(32 * 1024)];
+        let best = compress(input, Encoder::with_level(Vec::new(), 9)).len();
+        assert!(compress(input, Encoder::optimal(Vec::new(), 2)).len() < best);
+    }
+
+    #[test]
+    fn roundtrips() {
+        compress(b"", Encoder::optimal(Vec::new(), 1));
+        compress(b"a", Encoder::optimal(Vec::new(), 1));
+        compress(&[7u8; 100000], Encoder::optimal(Vec::new(), 1));
+    }
+}
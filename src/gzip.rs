@@ -0,0 +1,250 @@
+//! GZIP Compression and Decompression. Requires `gzip` feature, enabled by default
+//!
+//! This module contains an implementation of the gzip file format, which
+//! wraps a DEFLATE stream with a header and a CRC-32 checked trailer. The
+//! decoder reads streams made of several concatenated members as a whole, like
+//! the gzip tool does.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::gzip;
+//! use std::fs::File;
+//! use std::path::Path;
+//! use std::io::Read;
+//!
+//! let stream = File::open(&Path::new("path/to/file.gz")).unwrap();
+//! let mut decompressed = Vec::new();
+//! gzip::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! # Related links
+//!
+//! * http://tools.ietf.org/html/rfc1952 - RFC that this implementation is based
+//!   on
+
+use std::io::{self, Read, Write};
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use checksum::crc32;
+use flate;
+use gzip_header::{self, MAGIC, METHOD_DEFLATE};
+
+/// Operating system of the header, which is left unknown
+const OS_UNKNOWN: u8 = 255;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Structure used to decode a gzip stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    inner: flate::Decoder<R>,
+    hash: crc32::State,
+    size: u32,
+    read_header: bool,
+    members: usize,
+    eof: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new gzip decoder which will wrap the specified reader.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            inner: flate::Decoder::new(r),
+            hash: crc32::State::new(),
+            size: 0,
+            read_header: false,
+            members: 0,
+            eof: false,
+        }
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.inner.r
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.eof
+    }
+
+    /// Reads the header of the next member, returning false if the stream is
+    /// over instead
+    fn validate_header(&mut self) -> io::Result<bool> {
+        match try!(gzip_header::skip(&mut self.inner.r)) {
+            Some(_) => {
+                self.members += 1;
+                Ok(true)
+            }
+            None if self.members != 0 => Ok(false),
+            None => error("empty gzip stream"),
+        }
+    }
+
+    fn validate_trailer(&mut self) -> io::Result<()> {
+        let crc = try!(self.inner.r.read_u32::<LittleEndian>());
+        let size = try!(self.inner.r.read_u32::<LittleEndian>());
+        if crc != self.hash.result() {
+            return error("invalid checksum on gzip stream");
+        }
+        if size != self.size {
+            return error("invalid size of gzip stream");
+        }
+        self.inner.reset();
+        self.hash.reset();
+        self.size = 0;
+        self.read_header = false;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.eof {
+                return Ok(0)
+            }
+            if !self.read_header {
+                if !try!(self.validate_header()) {
+                    self.eof = true;
+                    return Ok(0)
+                }
+                self.read_header = true;
+            }
+            let n = try!(self.inner.read(buf));
+            if n != 0 || buf.is_empty() {
+                self.hash.feed(&buf[..n]);
+                self.size = self.size.wrapping_add(n as u32);
+                return Ok(n)
+            }
+            try!(self.validate_trailer());
+        }
+    }
+}
+
+/// Structure used to compress a stream into the gzip format. The wrapped
+/// stream can be re-acquired through the finish() method.
+pub struct Encoder<W> {
+    inner: flate::Encoder<W>,
+    hash: crc32::State,
+    size: u32,
+    level: u32,
+    wrote_header: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new gzip encoder with the default compression level, which
+    /// will have its output written to the given stream.
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, 6)
+    }
+
+    /// Creates a new gzip encoder with the given compression level, from 0
+    /// to 10 as for `flate::Encoder`.
+    pub fn with_level(w: W, level: u32) -> Encoder<W> {
+        Encoder {
+            inner: flate::Encoder::with_level(w, level),
+            hash: crc32::State::new(),
+            size: 0,
+            level,
+            wrote_header: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.wrote_header {
+            return Ok(())
+        }
+        self.wrote_header = true;
+        // no flags and no modification time
+        let extra_flags = match self.level {
+            1 => 4,
+            9..=10 => 2,
+            _ => 0,
+        };
+        let header = [MAGIC[0], MAGIC[1], METHOD_DEFLATE, 0, 0, 0, 0, 0,
+                      extra_flags, OS_UNKNOWN];
+        self.inner.get_mut().write_all(&header)
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header();
+        let (checksum, size) = (self.hash.result(), self.size);
+        let (mut w, inner) = self.inner.finish();
+        let result = result.and(inner)
+                           .and_then(|_| w.write_u32::<LittleEndian>(checksum))
+                           .and_then(|_| w.write_u32::<LittleEndian>(size))
+                           .and_then(|_| w.flush());
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        let n = try!(self.inner.write(buf));
+        self.hash.feed(&buf[..n]);
+        self.size = self.size.wrapping_add(n as u32);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Read, Write};
+    use super::{Decoder, Encoder};
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(d.eof());
+        buf
+    }
+
+    #[test]
+    fn decode_members() {
+        let member = include_bytes!("data/test.txt.gz");
+        let text = include_bytes!("data/test.txt");
+        assert!(decode(member)[..] == text[..]);
+
+        let mut input = member.to_vec();
+        input.extend_from_slice(member);
+        let mut reference = text.to_vec();
+        reference.extend_from_slice(text);
+        assert!(decode(&input) == reference);
+    }
+
+    #[test]
+    fn corrupted() {
+        let mut input = include_bytes!("data/test.txt.gz").to_vec();
+        let len = input.len();
+        input[len - 5] ^= 1;
+        let mut d = Decoder::new(BufReader::new(&input[..]));
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        for &level in [0, 1, 6, 9, 10].iter() {
+            for input in [&b""[..], &b"test"[..], &include_bytes!("data/test.txt")[..]].iter() {
+                let mut e = Encoder::with_level(Vec::new(), level);
+                e.write_all(input).unwrap();
+                let (encoded, result) = e.finish();
+                result.unwrap();
+                assert!(&decode(&encoded)[..] == *input);
+            }
+        }
+    }
+}
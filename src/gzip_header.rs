@@ -0,0 +1,66 @@
+//! The gzip member header, shared by the gzip decoder and the indexes of
+//! gzip streams
+//!
+//! # Related links
+//!
+//! * http://tools.ietf.org/html/rfc1952 - the gzip file format
+
+use std::io::{self, Read};
+use super::byteorder::{LittleEndian, ReadBytesExt};
+use super::ReadExact;
+
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+pub const METHOD_DEFLATE: u8 = 8;
+/// Size of the CRC-32 and size trailer of a member
+pub const TRAILER_SIZE: u64 = 8;
+
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Skips a member header, returning its size, or `None` if the input is
+/// already over
+pub fn skip<R: Read>(r: &mut R) -> io::Result<Option<u64>> {
+    let mut magic = [0u8; 2];
+    match try!(r.read(&mut magic[..1])) {
+        0 => return Ok(None),
+        _ => magic[1] = try!(r.read_u8()),
+    }
+    if magic != MAGIC {
+        return error("invalid gzip header");
+    }
+    if try!(r.read_u8()) != METHOD_DEFLATE {
+        return error("unsupported gzip compression method");
+    }
+    let flags = try!(r.read_u8());
+    // modification time, extra flags and operating system
+    let mut skipped = Vec::new();
+    try!(r.push_exactly(6, &mut skipped));
+    let mut size = 10;
+    if flags & FEXTRA != 0 {
+        let len = try!(r.read_u16::<LittleEndian>()) as u64;
+        try!(r.push_exactly(len, &mut skipped));
+        size += 2 + len;
+    }
+    for &flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            // zero-terminated strings
+            loop {
+                size += 1;
+                if try!(r.read_u8()) == 0 {
+                    break
+                }
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        try!(r.read_u16::<LittleEndian>());
+        size += 2;
+    }
+    Ok(Some(size))
+}
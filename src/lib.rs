@@ -19,20 +19,27 @@ use std::io::{self, Read};
 /// Public exports
 #[cfg(feature="checksum")]
 pub use self::checksum::adler::State32 as Adler32;
+#[cfg(feature="checksum")]
+pub use self::checksum::crc32::State as Crc32;
 
 #[cfg(feature="checksum")]
-/// Checksum algorithms. Requires `checksum` feature, enabled by default
-// http://en.wikipedia.org/wiki/Checksum
-pub mod checksum {
-    pub mod adler;
-}
+pub mod checksum;
 
 #[cfg(feature="bwt")]
 pub mod bwt;
 
+#[cfg(feature="bzip2")]
+pub mod bzip2;
+
 #[cfg(feature="flate")]
 pub mod flate;
 
+#[cfg(feature="gzip")]
+pub mod gzip;
+
+#[cfg(feature="flate")]
+mod gzip_header;
+
 #[cfg(feature="lz4")]
 pub mod lz4;
 
@@ -49,6 +56,9 @@ pub mod entropy {
 #[cfg(feature="rle")]
 pub mod rle;
 
+#[cfg(feature="zip")]
+pub mod zip;
+
 #[cfg(any(feature = "lz4", feature = "entropy", feature = "bwt"))]
 fn byteorder_err_to_io(err: io::Error) -> io::Error {
     match err {
@@ -100,6 +100,60 @@ pub fn main() {
         }),
         info: "Move-To-Front Transformation".to_string(),
     });
+    passes.insert("mtf1".to_string(), Pass {
+        encode: Box::new(|w,_c| {
+            Box::new(bwt::mtf::Encoder::with_coder(w, bwt::mtf::Mtf1::new())) as Box<dyn Write + 'static>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(bwt::mtf::Decoder::with_coder(r, bwt::mtf::Mtf1::new())) as Box<dyn Read + 'static>
+        }),
+        info: "Move-To-Front Transformation, MTF-1 variant".to_string(),
+    });
+    passes.insert("mtf2".to_string(), Pass {
+        encode: Box::new(|w,_c| {
+            Box::new(bwt::mtf::Encoder::with_coder(w, bwt::mtf::Mtf2::new())) as Box<dyn Write + 'static>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(bwt::mtf::Decoder::with_coder(r, bwt::mtf::Mtf2::new())) as Box<dyn Read + 'static>
+        }),
+        info: "Move-To-Front Transformation, MTF-2 variant".to_string(),
+    });
+    passes.insert("wfc".to_string(), Pass {
+        encode: Box::new(|w,_c| {
+            Box::new(bwt::mtf::Encoder::with_coder(w, bwt::mtf::Wfc::new())) as Box<dyn Write + 'static>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(bwt::mtf::Decoder::with_coder(r, bwt::mtf::Wfc::new())) as Box<dyn Read + 'static>
+        }),
+        info: "Weighted Frequency Coding".to_string(),
+    });
+    passes.insert("dc".to_string(), Pass {
+        encode: Box::new(|w,c| {
+            Box::new(bwt::dc::Encoder::new(w, c.block_size)) as Box<dyn Write + 'static>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(bwt::dc::Decoder::new(r)) as Box<dyn Read + 'static>
+        }),
+        info: "Distance Coding with an adaptive arithmetic model".to_string(),
+    });
+    passes.insert("if".to_string(), Pass {
+        encode: Box::new(|w,c| {
+            Box::new(bwt::inverse_freq::Encoder::new(w, c.block_size)) as Box<dyn Write + 'static>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(bwt::inverse_freq::Decoder::new(r)) as Box<dyn Read + 'static>
+        }),
+        info: "Inverse Frequencies with an adaptive arithmetic model".to_string(),
+    });
+    passes.insert("ybs".to_string(), Pass {
+        encode: Box::new(|w,c| {
+            Box::new(bwt::ybs::Encoder::new(w, c.block_size)) as Box<dyn Write + 'static>
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(bwt::ybs::Decoder::new(r)) as Box<dyn Read + 'static>
+        }),
+        info: "BWT + DC + arithmetic coding compressor".to_string(),
+    });
     /* // looks like we are missing the encoder implementation
     passes.insert(~"flate", Pass {
         encode: |w,_c| {
@@ -0,0 +1,906 @@
+//! ZIP archive reading and writing. Requires `zip` feature, enabled by default
+//!
+//! This module contains a reader and a writer of ZIP archives, including the
+//! ZIP64 extensions. The archive is indexed through its central directory, and
+//! every entry can then be opened as a standard `Read` stream. Stored entries
+//! are read as-is, while deflated (and Deflate64) entries are decoded through
+//! `flate::Decoder`. The CRC-32 of every entry is verified once it is read to
+//! the end.
+//!
+//! The writer only needs a `Write` stream: entries of unknown size are
+//! streamed, with their sizes and CRC-32 following the data in a ZIP64 data
+//! descriptor, and ZIP64 records are used whenever a size or offset overflows.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::zip;
+//! use std::fs::File;
+//! use std::io::Read;
+//!
+//! let file = File::open("path/to/file.zip").unwrap();
+//! let mut archive = zip::Archive::new(file).unwrap();
+//! for i in 0..archive.entries().len() {
+//!     println!("{}", archive.entries()[i].name);
+//!     let mut contents = Vec::new();
+//!     archive.open(i).unwrap().read_to_end(&mut contents).unwrap();
+//! }
+//! ```
+//!
+//! ```rust
+//! use compress::zip;
+//! use std::io::Write;
+//!
+//! let modified = zip::DateTime { year: 2020, month: 7, day: 21,
+//!                                hour: 12, minute: 34, second: 56 };
+//! let mut writer = zip::Writer::new(Vec::new());
+//! writer.add_entry("hello.txt", zip::METHOD_STORED, modified, b"hello").unwrap();
+//! writer.start_entry("streamed.txt", zip::METHOD_DEFLATED, modified).unwrap();
+//! writer.write_all(b"some streamed data").unwrap();
+//! let (archive, result) = writer.finish();
+//! result.unwrap();
+//! ```
+//!
+//! # Related links
+//!
+//! * https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT - the ZIP
+//!   file format specification
+
+use std::cmp;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+
+use super::byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::ReadExact;
+use checksum::crc32;
+use flate;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR: u32 = 0x0706_4b50;
+const DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+
+/// Size of the end of central directory record without the comment
+const END_SIZE: u64 = 22;
+/// Size of the ZIP64 end of central directory record after its size field
+const END64_SIZE: u64 = 44;
+const LOCATOR_SIZE: u64 = 20;
+const LOCAL_HEADER_SIZE: u64 = 30;
+/// Sizes and offsets from this value on are stored in ZIP64 records
+const ZIP64_LIMIT: u64 = 0xffff_ffff;
+
+/// Version needed to extract, or made by: 1.0, 2.0 (deflate) and 4.5 (ZIP64)
+const VERSION_STORED: u16 = 10;
+const VERSION_DEFLATED: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Entries are marked as made on Unix, so that names are not taken for the
+/// IBM PC code page and the attributes hold permissions in the upper half
+const HOST_UNIX: u16 = 3 << 8;
+const ATTR_FILE: u32 = 0o100_644 << 16;
+/// Unix permissions along with the MS-DOS directory attribute
+const ATTR_DIRECTORY: u32 = (0o40_755 << 16) | 0x10;
+
+/// Entry data is stored without compression
+pub const METHOD_STORED: u16 = 0;
+/// Entry data is compressed with DEFLATE
+pub const METHOD_DEFLATED: u16 = 8;
+/// Entry data is compressed with Deflate64
+pub const METHOD_DEFLATE64: u16 = 9;
+
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+
+fn error<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// A timestamp in the MS-DOS format used by ZIP, with a 2 second precision
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    /// Year, from 1980 to 2107
+    pub year: u16,
+    /// Month of the year, starting from 1
+    pub month: u8,
+    /// Day of the month, starting from 1
+    pub day: u8,
+    /// Hour of the day
+    pub hour: u8,
+    /// Minute of the hour
+    pub minute: u8,
+    /// Second of the minute, always even
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Unpack the MS-DOS date and time fields
+    pub fn from_dos(date: u16, time: u16) -> DateTime {
+        DateTime {
+            year: 1980 + (date >> 9),
+            month: ((date >> 5) & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3f) as u8,
+            second: ((time & 0x1f) * 2) as u8,
+        }
+    }
+
+    /// Pack into the MS-DOS (date, time) fields, failing on a timestamp the
+    /// format can't hold: a year out of 1980 to 2107, or any other field out
+    /// of its range. Odd seconds are rounded down.
+    pub fn to_dos(self) -> io::Result<(u16, u16)> {
+        if self.year < 1980 || self.year > 2107 || self.month < 1 || self.month > 12 ||
+           self.day < 1 || self.day > 31 || self.hour > 23 || self.minute > 59 ||
+           self.second > 59 {
+            return error("zip timestamp out of range");
+        }
+        let date = ((self.year - 1980) << 9) | ((self.month as u16) << 5) | self.day as u16;
+        let time = ((self.hour as u16) << 11) | ((self.minute as u16) << 5) |
+                   (self.second as u16 / 2);
+        Ok((date, time))
+    }
+}
+
+/// An entry of the archive, as listed in the central directory
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// Path of the entry inside the archive, directories end with a '/'
+    pub name: String,
+    /// Compression method of the data
+    pub method: u16,
+    /// Size of the stored (compressed) data
+    pub compressed_size: u64,
+    /// Size of the original data
+    pub size: u64,
+    /// CRC-32 of the original data
+    pub crc32: u32,
+    /// Last modification time
+    pub modified: DateTime,
+    flags: u16,
+    header_offset: u64,
+}
+
+impl Entry {
+    /// Returns whether the entry is a directory
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// Returns whether the entry data is encrypted, which is not supported
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    /// Returns whether the sizes and CRC-32 follow the data
+    fn is_streamed(&self) -> bool {
+        self.flags & FLAG_DATA_DESCRIPTOR != 0
+    }
+
+    fn parse<R: Read>(r: &mut R) -> io::Result<Entry> {
+        if try!(r.read_u32::<LittleEndian>()) != CENTRAL_HEADER {
+            return error("invalid zip central directory header");
+        }
+        let _version_made = try!(r.read_u16::<LittleEndian>());
+        let _version_needed = try!(r.read_u16::<LittleEndian>());
+        let flags = try!(r.read_u16::<LittleEndian>());
+        let method = try!(r.read_u16::<LittleEndian>());
+        let time = try!(r.read_u16::<LittleEndian>());
+        let date = try!(r.read_u16::<LittleEndian>());
+        let crc = try!(r.read_u32::<LittleEndian>());
+        let mut compressed_size = try!(r.read_u32::<LittleEndian>()) as u64;
+        let mut size = try!(r.read_u32::<LittleEndian>()) as u64;
+        let name_len = try!(r.read_u16::<LittleEndian>()) as u64;
+        let extra_len = try!(r.read_u16::<LittleEndian>()) as u64;
+        let comment_len = try!(r.read_u16::<LittleEndian>()) as u64;
+        let _disk = try!(r.read_u16::<LittleEndian>());
+        let _internal_attr = try!(r.read_u16::<LittleEndian>());
+        let _external_attr = try!(r.read_u32::<LittleEndian>());
+        let mut header_offset = try!(r.read_u32::<LittleEndian>()) as u64;
+
+        let mut name = Vec::new();
+        try!(r.push_exactly(name_len, &mut name));
+        let mut extra = Vec::new();
+        try!(r.push_exactly(extra_len, &mut extra));
+        try!(io::copy(&mut r.take(comment_len), &mut io::sink()));
+
+        // The ZIP64 extra field holds only the values which overflowed
+        let mut extra = &extra[..];
+        while extra.len() >= 4 {
+            let id = try!(extra.read_u16::<LittleEndian>());
+            let len = cmp::min(try!(extra.read_u16::<LittleEndian>()) as usize, extra.len());
+            let (mut field, rest) = extra.split_at(len);
+            extra = rest;
+            if id != ZIP64_EXTRA {
+                continue
+            }
+            if size == 0xffff_ffff {
+                size = try!(field.read_u64::<LittleEndian>());
+            }
+            if compressed_size == 0xffff_ffff {
+                compressed_size = try!(field.read_u64::<LittleEndian>());
+            }
+            if header_offset == 0xffff_ffff {
+                header_offset = try!(field.read_u64::<LittleEndian>());
+            }
+        }
+
+        // Names which aren't flagged as UTF-8 are in the IBM PC code page,
+        // which matches UTF-8 for the ASCII range
+        let name = if flags & FLAG_UTF8 != 0 {
+            match String::from_utf8(name) {
+                Ok(name) => name,
+                Err(_) => return error("invalid UTF-8 zip entry name"),
+            }
+        } else {
+            String::from_utf8_lossy(&name).into_owned()
+        };
+
+        Ok(Entry {
+            name,
+            method,
+            compressed_size,
+            size,
+            crc32: crc,
+            modified: DateTime::from_dos(date, time),
+            flags,
+            header_offset,
+        })
+    }
+}
+
+/// A ZIP archive opened for reading. The entries are listed on creation,
+/// and the contents of each one can be read through `open`.
+pub struct Archive<R> {
+    r: R,
+    entries: Vec<Entry>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Opens an archive by reading its central directory
+    pub fn new(mut r: R) -> io::Result<Archive<R>> {
+        let (cd_offset, cd_entries) = try!(Archive::find_central_directory(&mut r));
+        try!(r.seek(SeekFrom::Start(cd_offset)));
+        let mut entries = Vec::new();
+        {
+            let mut cd = BufReader::new(&mut r);
+            for _ in 0..cd_entries {
+                entries.push(try!(Entry::parse(&mut cd)));
+            }
+        }
+        Ok(Archive {
+            r,
+            entries,
+        })
+    }
+
+    /// Locates the end of central directory record, and returns the offset
+    /// and the number of entries of the central directory
+    fn find_central_directory(r: &mut R) -> io::Result<(u64, u64)> {
+        // The record is followed by a comment of up to 64KB
+        let file_len = try!(r.seek(SeekFrom::End(0)));
+        if file_len < END_SIZE {
+            return error("zip archive is too small");
+        }
+        let tail_len = cmp::min(file_len, END_SIZE + 0xffff);
+        try!(r.seek(SeekFrom::Start(file_len - tail_len)));
+        let mut tail = Vec::new();
+        try!(r.push_exactly(tail_len, &mut tail));
+
+        let pos = match (0..(tail.len() - END_SIZE as usize + 1)).rev()
+                          .find(|&i| LittleEndian::read_u32(&tail[i..]) == END_OF_CENTRAL_DIRECTORY) {
+            Some(pos) => pos,
+            None => return error("zip end of central directory not found"),
+        };
+        let end_offset = file_len - tail_len + pos as u64;
+
+        let mut end = &tail[(pos + 4)..];
+        let _disk = try!(end.read_u16::<LittleEndian>());
+        let _cd_disk = try!(end.read_u16::<LittleEndian>());
+        let _disk_entries = try!(end.read_u16::<LittleEndian>());
+        let entries = try!(end.read_u16::<LittleEndian>()) as u64;
+        let _cd_size = try!(end.read_u32::<LittleEndian>());
+        let offset = try!(end.read_u32::<LittleEndian>()) as u64;
+
+        // A ZIP64 locator right before the record points to the ZIP64 one
+        if end_offset < LOCATOR_SIZE {
+            return Ok((offset, entries));
+        }
+        try!(r.seek(SeekFrom::Start(end_offset - LOCATOR_SIZE)));
+        if try!(r.read_u32::<LittleEndian>()) != ZIP64_LOCATOR {
+            return Ok((offset, entries));
+        }
+        let _disk = try!(r.read_u32::<LittleEndian>());
+        let end64_offset = try!(r.read_u64::<LittleEndian>());
+        try!(r.seek(SeekFrom::Start(end64_offset)));
+        if try!(r.read_u32::<LittleEndian>()) != ZIP64_END_OF_CENTRAL_DIRECTORY {
+            return error("invalid zip64 end of central directory");
+        }
+        let _size = try!(r.read_u64::<LittleEndian>());
+        let _version_made = try!(r.read_u16::<LittleEndian>());
+        let _version_needed = try!(r.read_u16::<LittleEndian>());
+        let _disk = try!(r.read_u32::<LittleEndian>());
+        let _cd_disk = try!(r.read_u32::<LittleEndian>());
+        let _disk_entries = try!(r.read_u64::<LittleEndian>());
+        let entries = try!(r.read_u64::<LittleEndian>());
+        let _cd_size = try!(r.read_u64::<LittleEndian>());
+        let offset = try!(r.read_u64::<LittleEndian>());
+        Ok((offset, entries))
+    }
+
+    /// Returns the entries of the archive, in the central directory order
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Finds the index of an entry by its name
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.name == name)
+    }
+
+    /// Opens the entry with the given index for reading
+    pub fn open(&mut self, index: usize) -> io::Result<EntryReader<&mut R>> {
+        let entry = &self.entries[index];
+        if entry.is_encrypted() {
+            return error("encrypted zip entries are not supported");
+        }
+        try!(self.r.seek(SeekFrom::Start(entry.header_offset)));
+        if try!(self.r.read_u32::<LittleEndian>()) != LOCAL_HEADER {
+            return error("invalid zip local header");
+        }
+        // Sizes in the local header may be deferred to a data descriptor,
+        // so only the variable lengths are taken from it
+        try!(self.r.seek(SeekFrom::Start(entry.header_offset + 26)));
+        let name_len = try!(self.r.read_u16::<LittleEndian>()) as u64;
+        let extra_len = try!(self.r.read_u16::<LittleEndian>()) as u64;
+        let data_offset = entry.header_offset + LOCAL_HEADER_SIZE + name_len + extra_len;
+        try!(self.r.seek(SeekFrom::Start(data_offset)));
+
+        let data = (&mut self.r).take(entry.compressed_size);
+        let inner = match entry.method {
+            METHOD_STORED => Inner::Stored(data),
+            METHOD_DEFLATED => Inner::Deflated(flate::Decoder::new(BufReader::new(data))),
+            METHOD_DEFLATE64 => Inner::Deflated(flate::Decoder::new_deflate64(BufReader::new(data))),
+            _ => return error("unsupported zip compression method"),
+        };
+        Ok(EntryReader {
+            inner,
+            hash: crc32::State::new(),
+            crc32: entry.crc32,
+            size: entry.size,
+            read: 0,
+        })
+    }
+
+    /// Destroys the archive, returning the underlying reader
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+enum Inner<R> {
+    Stored(io::Take<R>),
+    Deflated(flate::Decoder<BufReader<io::Take<R>>>),
+}
+
+/// A stream of the original data of an archive entry. The size and CRC-32 are
+/// verified once the end of the data is reached.
+pub struct EntryReader<R> {
+    inner: Inner<R>,
+    hash: crc32::State,
+    crc32: u32,
+    size: u64,
+    read: u64,
+}
+
+impl<R: Read> Read for EntryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = try!(match self.inner {
+            Inner::Stored(ref mut r) => r.read(buf),
+            Inner::Deflated(ref mut r) => r.read(buf),
+        });
+        if n == 0 && !buf.is_empty() {
+            if self.read != self.size {
+                return error("invalid zip entry size");
+            }
+            if self.hash.result() != self.crc32 {
+                return error("invalid checksum on zip entry");
+            }
+        }
+        self.hash.feed(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// A stream which counts the bytes written, giving the archive offsets
+struct Counter<W> {
+    w: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.w.write(buf));
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+enum Sink<W> {
+    Plain(Counter<W>),
+    Deflated(flate::Encoder<Counter<W>>),
+    Swapping,
+}
+
+/// The entry being streamed by a `Writer`
+struct Current {
+    entry: Entry,
+    hash: crc32::State,
+    data_offset: u64,
+}
+
+/// A ZIP archive writer. Entries are either added at once with `add_entry`,
+/// or started with `start_entry` and then streamed through the `Write`
+/// implementation. The central directory is written out by `finish`.
+pub struct Writer<W> {
+    sink: Sink<W>,
+    current: Option<Current>,
+    entries: Vec<Entry>,
+    level: u32,
+    /// use ZIP64 records even when nothing overflows
+    force_zip64: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a new archive writer, which deflates entries with the default
+    /// compression level
+    pub fn new(w: W) -> Writer<W> {
+        Writer::with_level(w, 6)
+    }
+
+    /// Creates a new archive writer, which deflates entries with the given
+    /// compression level, from 0 to 9
+    pub fn with_level(w: W, level: u32) -> Writer<W> {
+        Writer {
+            sink: Sink::Plain(Counter { w, count: 0 }),
+            current: None,
+            entries: Vec::new(),
+            level,
+            force_zip64: false,
+        }
+    }
+
+    fn counter(&mut self) -> &mut Counter<W> {
+        match self.sink {
+            Sink::Plain(ref mut c) => c,
+            _ => panic!("zip entry data is still being compressed"),
+        }
+    }
+
+    fn new_entry(&mut self, name: &str, method: u16, modified: DateTime) -> io::Result<Entry> {
+        try!(self.finish_entry());
+        if method != METHOD_STORED && method != METHOD_DEFLATED {
+            return error("unsupported zip compression method");
+        }
+        if name.len() > 0xffff {
+            return error("zip entry name is too long");
+        }
+        try!(modified.to_dos());
+        let flags = if name.is_ascii() { 0 } else { FLAG_UTF8 };
+        Ok(Entry {
+            name: name.to_string(),
+            method,
+            compressed_size: 0,
+            size: 0,
+            crc32: 0,
+            modified,
+            flags,
+            header_offset: self.counter().count,
+        })
+    }
+
+    fn write_local_header(&mut self, entry: &Entry) -> io::Result<()> {
+        // The sizes of a streamed entry may overflow, which takes a ZIP64
+        // extra field for its data descriptor to hold them, zeroed here
+        let zip64 = entry.is_streamed() || entry.size >= ZIP64_LIMIT ||
+                    entry.compressed_size >= ZIP64_LIMIT;
+        let (date, time) = try!(entry.modified.to_dos());
+        let w = self.counter();
+        try!(w.write_u32::<LittleEndian>(LOCAL_HEADER));
+        try!(w.write_u16::<LittleEndian>(version_needed(entry, zip64)));
+        try!(w.write_u16::<LittleEndian>(entry.flags));
+        try!(w.write_u16::<LittleEndian>(entry.method));
+        try!(w.write_u16::<LittleEndian>(time));
+        try!(w.write_u16::<LittleEndian>(date));
+        try!(w.write_u32::<LittleEndian>(entry.crc32));
+        if zip64 {
+            try!(w.write_u32::<LittleEndian>(ZIP64_LIMIT as u32));
+            try!(w.write_u32::<LittleEndian>(ZIP64_LIMIT as u32));
+        } else {
+            try!(w.write_u32::<LittleEndian>(entry.compressed_size as u32));
+            try!(w.write_u32::<LittleEndian>(entry.size as u32));
+        }
+        try!(w.write_u16::<LittleEndian>(entry.name.len() as u16));
+        try!(w.write_u16::<LittleEndian>(if zip64 { 20 } else { 0 }));
+        try!(w.write_all(entry.name.as_bytes()));
+        if zip64 {
+            try!(w.write_u16::<LittleEndian>(ZIP64_EXTRA));
+            try!(w.write_u16::<LittleEndian>(16));
+            try!(w.write_u64::<LittleEndian>(entry.size));
+            try!(w.write_u64::<LittleEndian>(entry.compressed_size));
+        }
+        Ok(())
+    }
+
+    /// Adds an entry with the given contents. The sizes and CRC-32 are known
+    /// beforehand, so no data descriptor is needed.
+    pub fn add_entry(&mut self, name: &str, method: u16, modified: DateTime, data: &[u8])
+                     -> io::Result<()> {
+        let mut entry = try!(self.new_entry(name, method, modified));
+        let mut hash = crc32::State::new();
+        hash.feed(data);
+        entry.crc32 = hash.result();
+        entry.size = data.len() as u64;
+
+        let compressed;
+        let payload = if method == METHOD_DEFLATED {
+            let mut e = flate::Encoder::with_level(Vec::new(), self.level);
+            try!(e.write_all(data));
+            let (buf, result) = e.finish();
+            try!(result);
+            compressed = buf;
+            &compressed[..]
+        } else {
+            data
+        };
+        entry.compressed_size = payload.len() as u64;
+
+        try!(self.write_local_header(&entry));
+        try!(self.counter().write_all(payload));
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Starts a new entry, which contents are then written to this writer.
+    /// The sizes and CRC-32 follow the data in a ZIP64 data descriptor, so
+    /// the entry takes a reader supporting ZIP64.
+    pub fn start_entry(&mut self, name: &str, method: u16, modified: DateTime)
+                       -> io::Result<()> {
+        let mut entry = try!(self.new_entry(name, method, modified));
+        entry.flags |= FLAG_DATA_DESCRIPTOR;
+        try!(self.write_local_header(&entry));
+        let data_offset = self.counter().count;
+        if method == METHOD_DEFLATED {
+            self.sink = match mem::replace(&mut self.sink, Sink::Swapping) {
+                Sink::Plain(c) => Sink::Deflated(flate::Encoder::with_level(c, self.level)),
+                _ => unreachable!(),
+            };
+        }
+        self.current = Some(Current {
+            entry,
+            hash: crc32::State::new(),
+            data_offset,
+        });
+        Ok(())
+    }
+
+    /// Completes the streamed entry, if any, with its data descriptor
+    fn finish_entry(&mut self) -> io::Result<()> {
+        let mut current = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        if let Sink::Deflated(_) = self.sink {
+            let (c, result) = match mem::replace(&mut self.sink, Sink::Swapping) {
+                Sink::Deflated(e) => e.finish(),
+                _ => unreachable!(),
+            };
+            self.sink = Sink::Plain(c);
+            try!(result);
+        }
+        let entry = &mut current.entry;
+        entry.crc32 = current.hash.result();
+        entry.compressed_size = self.counter().count - current.data_offset;
+
+        // The local header has a ZIP64 extra field, so readers expect 8 byte
+        // sizes whatever they are (APPNOTE 4.3.9.2)
+        let w = self.counter();
+        try!(w.write_u32::<LittleEndian>(DATA_DESCRIPTOR));
+        try!(w.write_u32::<LittleEndian>(entry.crc32));
+        try!(w.write_u64::<LittleEndian>(entry.compressed_size));
+        try!(w.write_u64::<LittleEndian>(entry.size));
+        self.entries.push(current.entry);
+        Ok(())
+    }
+
+    fn write_central_directory(&mut self) -> io::Result<()> {
+        let force = self.force_zip64;
+        let entries = mem::take(&mut self.entries);
+        let offset = self.counter().count;
+        for entry in entries.iter() {
+            // Only the overflowing values go to the ZIP64 extra field
+            let mut extra = Vec::new();
+            let mut limit = |value: u64| {
+                if force || value >= ZIP64_LIMIT {
+                    extra.write_u64::<LittleEndian>(value).unwrap();
+                    ZIP64_LIMIT as u32
+                } else {
+                    value as u32
+                }
+            };
+            let size = limit(entry.size);
+            let compressed_size = limit(entry.compressed_size);
+            let header_offset = limit(entry.header_offset);
+            let zip64 = !extra.is_empty();
+            let (date, time) = try!(entry.modified.to_dos());
+            let version = version_needed(entry, zip64 || entry.is_streamed());
+
+            let w = self.counter();
+            try!(w.write_u32::<LittleEndian>(CENTRAL_HEADER));
+            try!(w.write_u16::<LittleEndian>(HOST_UNIX | version));
+            try!(w.write_u16::<LittleEndian>(version));
+            try!(w.write_u16::<LittleEndian>(entry.flags));
+            try!(w.write_u16::<LittleEndian>(entry.method));
+            try!(w.write_u16::<LittleEndian>(time));
+            try!(w.write_u16::<LittleEndian>(date));
+            try!(w.write_u32::<LittleEndian>(entry.crc32));
+            try!(w.write_u32::<LittleEndian>(compressed_size));
+            try!(w.write_u32::<LittleEndian>(size));
+            try!(w.write_u16::<LittleEndian>(entry.name.len() as u16));
+            try!(w.write_u16::<LittleEndian>(if zip64 { 4 + extra.len() as u16 } else { 0 }));
+            try!(w.write_u16::<LittleEndian>(0)); // comment
+            try!(w.write_u16::<LittleEndian>(0)); // disk
+            try!(w.write_u16::<LittleEndian>(0)); // internal attributes
+            try!(w.write_u32::<LittleEndian>(if entry.is_dir() { ATTR_DIRECTORY } else { ATTR_FILE }));
+            try!(w.write_u32::<LittleEndian>(header_offset));
+            try!(w.write_all(entry.name.as_bytes()));
+            if zip64 {
+                try!(w.write_u16::<LittleEndian>(ZIP64_EXTRA));
+                try!(w.write_u16::<LittleEndian>(extra.len() as u16));
+                try!(w.write_all(&extra));
+            }
+        }
+
+        let count = entries.len() as u64;
+        let end64_offset = self.counter().count;
+        let size = end64_offset - offset;
+        let w = self.counter();
+        if force || count >= 0xffff || size >= ZIP64_LIMIT || offset >= ZIP64_LIMIT {
+            try!(w.write_u32::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY));
+            try!(w.write_u64::<LittleEndian>(END64_SIZE));
+            try!(w.write_u16::<LittleEndian>(VERSION_ZIP64));
+            try!(w.write_u16::<LittleEndian>(VERSION_ZIP64));
+            try!(w.write_u32::<LittleEndian>(0)); // disk
+            try!(w.write_u32::<LittleEndian>(0)); // central directory disk
+            try!(w.write_u64::<LittleEndian>(count));
+            try!(w.write_u64::<LittleEndian>(count));
+            try!(w.write_u64::<LittleEndian>(size));
+            try!(w.write_u64::<LittleEndian>(offset));
+
+            try!(w.write_u32::<LittleEndian>(ZIP64_LOCATOR));
+            try!(w.write_u32::<LittleEndian>(0)); // disk
+            try!(w.write_u64::<LittleEndian>(end64_offset));
+            try!(w.write_u32::<LittleEndian>(1)); // total disks
+        }
+        let count = if force { 0xffff } else { cmp::min(count, 0xffff) as u16 };
+        let size = if force { ZIP64_LIMIT } else { cmp::min(size, ZIP64_LIMIT) };
+        let offset = if force { ZIP64_LIMIT } else { cmp::min(offset, ZIP64_LIMIT) };
+        try!(w.write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY));
+        try!(w.write_u16::<LittleEndian>(0)); // disk
+        try!(w.write_u16::<LittleEndian>(0)); // central directory disk
+        try!(w.write_u16::<LittleEndian>(count));
+        try!(w.write_u16::<LittleEndian>(count));
+        try!(w.write_u32::<LittleEndian>(size as u32));
+        try!(w.write_u32::<LittleEndian>(offset as u32));
+        try!(w.write_u16::<LittleEndian>(0)); // comment
+        w.flush()
+    }
+
+    /// Completes the last entry and writes the central directory, returning
+    /// the underlying writer.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.finish_entry().and_then(|_| self.write_central_directory());
+        match self.sink {
+            Sink::Plain(c) => (c.w, result),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let current = match self.current {
+            Some(ref mut current) => current,
+            None => return error("no zip entry was started"),
+        };
+        let n = try!(match self.sink {
+            Sink::Plain(ref mut w) => w.write(buf),
+            Sink::Deflated(ref mut w) => w.write(buf),
+            Sink::Swapping => unreachable!(),
+        });
+        current.hash.feed(&buf[..n]);
+        current.entry.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.sink {
+            Sink::Plain(ref mut w) => w.flush(),
+            Sink::Deflated(ref mut w) => w.flush(),
+            Sink::Swapping => unreachable!(),
+        }
+    }
+}
+
+fn version_needed(entry: &Entry, zip64: bool) -> u16 {
+    if zip64 {
+        VERSION_ZIP64
+    } else if entry.method == METHOD_DEFLATED || entry.is_dir() {
+        VERSION_DEFLATED
+    } else {
+        VERSION_STORED
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read, Write};
+    use super::{Archive, DateTime, Writer, METHOD_DEFLATED, METHOD_STORED};
+
+    fn read_entry(archive: &mut Archive<Cursor<Vec<u8>>>, name: &str) -> Vec<u8> {
+        let index = archive.find(name).unwrap();
+        let mut buf = Vec::new();
+        archive.open(index).unwrap().read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn list_and_read() {
+        let input = include_bytes!("data/test.zip").to_vec();
+        let mut archive = Archive::new(Cursor::new(input)).unwrap();
+        {
+            let names: Vec<_> = archive.entries().iter().map(|e| &e.name[..]).collect();
+            assert_eq!(names, ["test.txt", "dir/", "dir/stored.txt"]);
+            let e = &archive.entries()[0];
+            assert_eq!((e.method, e.size, e.crc32), (super::METHOD_DEFLATED, 3050, 0x617d_8ec9));
+            assert_eq!(e.modified, DateTime { year: 2020, month: 7, day: 21,
+                                              hour: 12, minute: 34, second: 56 });
+            let (date, time) = e.modified.to_dos().unwrap();
+            assert_eq!(DateTime::from_dos(date, time), e.modified);
+            assert!(archive.entries()[1].is_dir());
+        }
+        assert_eq!(&read_entry(&mut archive, "test.txt")[..], &include_bytes!("data/test.txt")[..]);
+        assert_eq!(&read_entry(&mut archive, "dir/stored.txt")[..], b"stored entry\n");
+        assert!(read_entry(&mut archive, "dir/").is_empty());
+    }
+
+    #[test]
+    fn dos_timestamps() {
+        let first = DateTime { year: 1980, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+        let last = DateTime { year: 2107, month: 12, day: 31, hour: 23, minute: 59, second: 58 };
+        assert_eq!(first.to_dos().unwrap(), (0x0021, 0));
+        assert_eq!(last.to_dos().unwrap(), (0xff9f, 0xbf7d));
+        let (date, time) = last.to_dos().unwrap();
+        assert_eq!(DateTime::from_dos(date, time), last);
+        assert_eq!(DateTime { second: 59, ..last }.to_dos().unwrap(), last.to_dos().unwrap());
+        let wrong = [
+            DateTime { year: 1979, ..last },
+            DateTime { year: 0, ..last },
+            DateTime { year: 2108, ..first },
+            DateTime { year: 0xffff, ..first },
+            DateTime { month: 0, ..first },
+            DateTime { month: 13, ..first },
+            DateTime { month: 16, ..first },
+            DateTime { day: 0, ..first },
+            DateTime { day: 32, ..first },
+            DateTime { hour: 24, ..first },
+            DateTime { minute: 60, ..first },
+            DateTime { second: 60, ..first },
+        ];
+        for t in wrong.iter() {
+            assert!(t.to_dos().is_err(), "{:?}", t);
+        }
+        // the writer rejects them before writing anything
+        let mut w = Writer::new(Vec::new());
+        assert!(w.add_entry("bad", METHOD_STORED, DateTime { month: 16, ..first }, b"").is_err());
+        let (out, result) = w.finish();
+        result.unwrap();
+        assert_eq!(Archive::new(Cursor::new(out)).unwrap().entries().len(), 0);
+    }
+
+    #[test]
+    fn zip64() {
+        let input = include_bytes!("data/test64.zip").to_vec();
+        let mut archive = Archive::new(Cursor::new(input)).unwrap();
+        assert_eq!(archive.entries().len(), 2);
+        assert_eq!(&read_entry(&mut archive, "test.txt")[..], &include_bytes!("data/test.txt")[..]);
+        assert_eq!(&read_entry(&mut archive, "dir/stored.txt")[..], b"stored entry\n");
+    }
+
+    #[test]
+    fn corrupted_entry() {
+        let mut input = include_bytes!("data/test.zip").to_vec();
+        let pos = input.windows(5).rposition(|w| w == b"entry").unwrap();
+        input[pos] = b'E';
+        let mut archive = Archive::new(Cursor::new(input)).unwrap();
+        let index = archive.find("dir/stored.txt").unwrap();
+        let mut buf = Vec::new();
+        assert!(archive.open(index).unwrap().read_to_end(&mut buf).is_err());
+    }
+
+    fn write_archive(force_zip64: bool) -> Vec<u8> {
+        let modified = DateTime { year: 2020, month: 7, day: 21, hour: 12, minute: 34, second: 56 };
+        let mut writer = Writer::new(Vec::new());
+        writer.force_zip64 = force_zip64;
+        writer.add_entry("test.txt", METHOD_DEFLATED, modified,
+                         include_bytes!("data/test.txt")).unwrap();
+        writer.add_entry("dir/", METHOD_STORED, modified, b"").unwrap();
+        writer.start_entry("dir/streamed.txt", METHOD_DEFLATED, modified).unwrap();
+        for chunk in include_bytes!("data/test.txt").chunks(100) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.start_entry("dir/stored \u{e9}.txt", METHOD_STORED, modified).unwrap();
+        writer.write_all(b"stored entry\n").unwrap();
+        let (output, result) = writer.finish();
+        result.unwrap();
+        output
+    }
+
+    fn check_archive(input: Vec<u8>) {
+        let mut archive = Archive::new(Cursor::new(input)).unwrap();
+        {
+            let names: Vec<_> = archive.entries().iter().map(|e| &e.name[..]).collect();
+            assert_eq!(names, ["test.txt", "dir/", "dir/streamed.txt", "dir/stored \u{e9}.txt"]);
+            let e = &archive.entries()[2];
+            assert_eq!((e.method, e.size, e.crc32), (METHOD_DEFLATED, 3050, 0x617d_8ec9));
+            assert!(e.compressed_size < e.size);
+            assert_eq!(e.modified.second, 56);
+        }
+        let text = &include_bytes!("data/test.txt")[..];
+        assert_eq!(&read_entry(&mut archive, "test.txt")[..], text);
+        assert!(read_entry(&mut archive, "dir/").is_empty());
+        assert_eq!(&read_entry(&mut archive, "dir/streamed.txt")[..], text);
+        assert_eq!(&read_entry(&mut archive, "dir/stored \u{e9}.txt")[..], b"stored entry\n");
+    }
+
+    #[test]
+    fn write_and_read() {
+        check_archive(write_archive(false));
+    }
+
+    #[test]
+    fn write_zip64() {
+        check_archive(write_archive(true));
+    }
+
+    #[test]
+    fn streamed_zip64() {
+        let modified = DateTime { year: 2020, month: 7, day: 21, hour: 12, minute: 34, second: 56 };
+        let mut writer = Writer::new(Vec::new());
+        writer.start_entry("large", METHOD_STORED, modified).unwrap();
+        writer.write_all(b"large entry\n").unwrap();
+        // pretend that the entry got past 4 GiB
+        writer.current.as_mut().unwrap().entry.size = 5 << 30;
+        let (output, result) = writer.finish();
+        result.unwrap();
+
+        // the local header has a ZIP64 extra field, after the name
+        assert_eq!(&output[26..30], &[5, 0, 20, 0]);
+        assert_eq!(&output[35..39], &[1, 0, 16, 0]);
+        // the data descriptor follows the data, with 8 byte sizes
+        let descriptor = &output[(55 + 12)..(55 + 12 + 24)];
+        assert_eq!(&descriptor[..4], b"PK\x07\x08");
+        assert_eq!(&descriptor[8..16], &[12, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&descriptor[16..], &[0, 0, 0, 0x40, 1, 0, 0, 0]);
+        let archive = Archive::new(Cursor::new(output)).unwrap();
+        assert_eq!(archive.entries()[0].size, 5 << 30);
+        assert_eq!(archive.entries()[0].compressed_size, 12);
+    }
+}
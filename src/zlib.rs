@@ -16,23 +16,28 @@
 //! zlib::Decoder::new(stream).read_to_end(&mut decompressed);
 //! ```
 //!
+//! Compression goes through `Encoder`, which takes the same levels as
+//! `flate::Encoder`, including the optimal level 10.
+//!
 //! # Related links
 //!
 //! * http://tools.ietf.org/html/rfc1950 - RFC that this implementation is based
 //!   on
 
-use std::io::{self, Read};
-use super::byteorder::{BigEndian, ReadBytesExt};
+use std::io::{self, Read, Write};
+use super::byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
 use Adler32;
+use checksum::{ChecksumReader, ChecksumWriter};
 use flate;
 
 /// Structure used to decode a ZLIB-encoded stream. The wrapped stream can be
 /// re-acquired through the unwrap() method.
 pub struct Decoder<R> {
-    hash: Adler32,
-    inner: flate::Decoder<R>,
+    inner: ChecksumReader<flate::Decoder<R>, Adler32>,
     read_header: bool,
+    /// whether the trailer was read and checked
+    checked: bool,
 }
 
 impl<R: Read> Decoder<R> {
@@ -41,20 +46,21 @@ impl<R: Read> Decoder<R> {
     /// reader can be re-acquired through the `unwrap` method.
     pub fn new(r: R) -> Decoder<R> {
         Decoder {
-            hash: Adler32::new(),
-            inner: flate::Decoder::new(r),
+            inner: ChecksumReader::new(flate::Decoder::new(r), Adler32::new()),
             read_header: false,
+            checked: false,
         }
     }
 
     /// Destroys this decoder, returning the underlying reader.
     pub fn unwrap(self) -> R {
-        self.inner.r
+        self.inner.into_inner().r
     }
 
     fn validate_header(&mut self) -> io::Result<()> {
-        let cmf = try!(self.inner.r.read_u8());
-        let flg = try!(self.inner.r.read_u8());
+        let r = &mut self.inner.get_mut().r;
+        let cmf = try!(r.read_u8());
+        let flg = try!(r.read_u8());
         if cmf & 0xf != 0x8 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -86,13 +92,14 @@ impl<R: Read> Decoder<R> {
     }
 
     /// Tests if this stream has reached the EOF point yet.
-    pub fn eof(&self) -> bool { self.inner.eof() }
+    pub fn eof(&self) -> bool { self.inner.get_ref().eof() }
 
     #[allow(dead_code)]
     fn reset(&mut self) {
-        self.inner.reset();
-        self.hash.reset();
+        self.inner.get_mut().reset();
+        self.inner.checksum_mut().reset();
         self.read_header = false;
+        self.checked = false;
     }
 }
 
@@ -101,13 +108,16 @@ impl<R: Read> Read for Decoder<R> {
         if !self.read_header {
             try!(self.validate_header());
             self.read_header = true;
-        } else if self.inner.eof() {
+        }
+        if self.checked {
             return Ok(0);
         }
-        match self.inner.read(buf) {
-            Ok(0) => {
-                let cksum = try!(self.inner.r.read_u32::<BigEndian>());
-                if cksum != self.hash.result() {
+        match try!(self.inner.read(buf)) {
+            0 if !buf.is_empty() => {
+                // the trailer follows the deflate stream, once it is over
+                self.checked = true;
+                let cksum = try!(self.inner.get_mut().r.read_u32::<BigEndian>());
+                if cksum != self.inner.checksum().result() {
                     Err(io::Error::new(
                         io::ErrorKind::InvalidInput,
                         "invalid checksum on zlib stream"
@@ -117,15 +127,82 @@ impl<R: Read> Read for Decoder<R> {
                     Ok(0)
                 }
             }
-            Ok(n) => {
-                self.hash.feed(&buf[..n]);
-                Ok(n)
-            }
-            Err(e) => Err(e)
+            n => Ok(n),
         }
     }
 }
 
+/// Structure used to compress a stream into the ZLIB format. The wrapped
+/// stream can be re-acquired through the finish() method.
+pub struct Encoder<W> {
+    inner: ChecksumWriter<flate::Encoder<W>, Adler32>,
+    level: u32,
+    wrote_header: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new ZLIB-stream encoder with the default compression level,
+    /// which will have its output written to the given stream.
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, 6)
+    }
+
+    /// Creates a new ZLIB-stream encoder with the given compression level,
+    /// from 0 to 10 as for `flate::Encoder`.
+    pub fn with_level(w: W, level: u32) -> Encoder<W> {
+        Encoder {
+            inner: ChecksumWriter::new(flate::Encoder::with_level(w, level), Adler32::new()),
+            level,
+            wrote_header: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.wrote_header {
+            return Ok(())
+        }
+        self.wrote_header = true;
+        // deflate with a 32KB window, and the level as a hint
+        let cmf = 0x78u16;
+        let flevel = match self.level {
+            0 | 1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        };
+        let mut flg = flevel << 6;
+        flg += 31 - (cmf * 256 + flg) % 31;
+        let w = self.inner.get_mut().get_mut();
+        try!(w.write_u8(cmf as u8));
+        w.write_u8(flg as u8)
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.write_header();
+        let checksum = self.inner.checksum().result();
+        let (mut w, inner) = self.inner.into_inner().finish();
+        let result = result.and(inner)
+                           .and_then(|_| w.write_u32::<BigEndian>(checksum))
+                           .and_then(|_| w.flush());
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        try!(self.write_header());
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.write_header());
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 #[allow(warnings)]
 mod test {
@@ -133,7 +210,7 @@ mod test {
     use super::super::rand::{random};
     use super::super::byteorder::{LittleEndian, BigEndian, WriteBytesExt, ReadBytesExt};
     use std::str;
-    use super::{Decoder};
+    use super::{Decoder, Encoder};
     #[cfg(feature="unstable")]
     use test;
 
@@ -163,6 +240,23 @@ mod test {
         test_decode(include_bytes!("data/test.z.9"), reference);
     }
 
+    #[test]
+    fn corrupted() {
+        let input = include_bytes!("data/test.z.1");
+        let decode = |input: &[u8]| Decoder::new(input).read_to_end(&mut Vec::new());
+        assert!(decode(&input[..]).is_ok());
+        // a wrong checksum or data
+        for &pos in [input.len() - 1, input.len() - 4, input.len() / 2].iter() {
+            let mut wrong = input.to_vec();
+            wrong[pos] ^= 1;
+            assert!(decode(&wrong).is_err());
+        }
+        // a truncated trailer
+        for &cut in [1, 4].iter() {
+            assert!(decode(&input[..(input.len() - cut)]).is_err());
+        }
+    }
+
     #[test]
     fn large() {
         let reference = include_bytes!("data/test.large");
@@ -202,22 +296,26 @@ mod test {
         assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
     }
 
-    //fn roundtrip(bytes: &[u8]) {
-    //    let mut e = Encoder::new(MemWriter::new());
-    //    e.write(bytes);
-    //    let encoded = e.finish().unwrap();
-    //
-    //    let mut d = Decoder::new(BufReader::new(encoded));
-    //    let decoded = d.read_to_end();
-    //    assert_eq!(&decoded[..], bytes);
-    //}
-    //
-    //#[test]
-    //fn some_roundtrips() {
-    //    roundtrip(bytes!("test"));
-    //    roundtrip(bytes!(""));
-    //    roundtrip(include_bytes!("data/test.txt"));
-    //}
+    fn roundtrip(bytes: &[u8], level: u32) {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        e.write_all(bytes).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], bytes);
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        for &level in [0, 1, 6, 9, 10].iter() {
+            roundtrip(b"test", level);
+            roundtrip(b"", level);
+            roundtrip(include_bytes!("data/test.txt"), level);
+        }
+    }
 
     #[cfg(feature="unstable")]
     #[bench]
@@ -227,7 +325,7 @@ mod test {
         let mut output = [0u8; 65536];
         let mut output_size = 0;
         bh.iter(|| {
-            d.inner.r = BufReader::new(input);
+            d.inner.get_mut().r = BufReader::new(input);
             d.reset();
             output_size = d.read(&mut output[..]).unwrap();
         });